@@ -1,7 +1,10 @@
 //! This module contains the functionality for determing the first bookmark id
 //! to use for cross-references.
 
-use regex::Regex;
+use crate::{
+    diagnostic::{Diagnostic, Kind},
+    xml,
+};
 use slog::debug;
 
 /// Determine the bookmark id number to start with.
@@ -13,20 +16,29 @@ use slog::debug;
 /// This function collects all of the bookmark ids in the provided string (the
 /// `document.xml` file). It then adds 1 to the highest number and returns that
 /// number.
-pub fn starting_bookmark(doc_input: &str) -> Result<u32, String> {
+pub fn starting_bookmark(doc_input: &str) -> Result<u32, Diagnostic> {
     debug!(slog_scope::logger(), "Determining starting bookmark id...");
 
     // Create a new vector for storing all of the existing bookmarks
     let mut all_bookmarks: Vec<u32> = Vec::new();
 
-    // Use regex to get all of the bookmarks in the provided string
-    let re = Regex::new(r#"(<w:bookmarkStart w:id=")([0-9]{1,9})"#).unwrap();
-    for cap in re.captures_iter(doc_input) {
-        match cap[2].parse::<u32>() {
+    // Get all of the bookmarks in the provided string
+    for event in xml::events(doc_input) {
+        let event = event?;
+        if !matches!(event.kind, xml::Kind::Start | xml::Kind::Empty)
+            || event.name != "bookmarkStart"
+        {
+            continue;
+        }
+        let id = match event.attribute("id") {
+            Some(i) => i,
+            None => continue,
+        };
+        match id.parse::<u32>() {
             Ok(b) => all_bookmarks.push(b),
             Err(e) => {
                 let err_msg = format!("Error parsing existing bookmarks in document.xml: {}", e);
-                return Err(err_msg);
+                return Err(Diagnostic::error(Kind::Xml, err_msg).at(event.range.start));
             }
         }
     }
@@ -41,3 +53,21 @@ pub fn starting_bookmark(doc_input: &str) -> Result<u32, String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn existing_bookmarks() {
+        // The ids can come after the names or other attributes, with any
+        // prefix.
+        let input = r#"<w:body><w:p><w:bookmarkStart w:id="3" w:name="_Toc1"/><w:bookmarkEnd w:id="3"/><w:bookmarkStart w:name="_Toc2" w:id="12"/><w:bookmarkStart w:colFirst="0" w:colLast="1" w:id="7" w:name="_Toc3"/><ns0:bookmarkStart ns0:name="_Toc4" ns0:id="40"/></w:p></w:body>"#;
+        assert_eq!(starting_bookmark(input).unwrap(), 41);
+        assert_eq!(starting_bookmark("<w:body/>").unwrap(), 1);
+
+        let err = starting_bookmark(r#"<w:bookmarkStart w:id="x" w:name="a"/>"#).unwrap_err();
+        assert_eq!(err.kind, Kind::Xml);
+        assert_eq!(err.offset, Some(0));
+    }
+}
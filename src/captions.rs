@@ -0,0 +1,158 @@
+//! This module contains the functionality for the captions of tables and
+//! figures.
+//!
+//! Pandoc writes a caption as plain text in a paragraph with a caption style
+//! (*e.g.*, "Table 3: Results"). Word numbers its own captions with a `SEQ`
+//! field (*e.g.*, `SEQ Table \* ARABIC`), which counts the captions with the
+//! same label in the order they appear. So the numbers in Pandoc's captions are
+//! turned into those fields, and cross-references to tables and figures
+//! (*e.g.*, "*infra* Table 3") into `REF` fields, so that Word renumbers
+//! everything when a table is inserted.
+
+use crate::{
+    diagnostic::Diagnostic,
+    paragraph::{self, RunProps},
+    styles::ParagraphStyle,
+};
+use regex::Regex;
+use slog::{debug, trace};
+use std::{collections::HashMap, ops::Range};
+
+/// The kinds of captions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CaptionKind {
+    Table,
+    Figure,
+}
+
+impl CaptionKind {
+    /// The caption's label, which is also the identifier of its `SEQ` field.
+    pub fn label(&self) -> &'static str {
+        match self {
+            CaptionKind::Table => "Table",
+            CaptionKind::Figure => "Figure",
+        }
+    }
+}
+
+/// The number in a caption.
+///
+/// This has the number's range in the input, its text, and the properties of
+/// the runs it starts and ends in, which are needed to split those runs.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CaptionNumber<'a> {
+    pub kind: CaptionKind,
+    pub range: Range<usize>,
+    pub text: String,
+    pub runs: RunProps<'a>,
+}
+
+/// Find the numbers in the captions in `document.xml`.
+///
+/// A caption is a paragraph with one of the caption styles in
+/// `paragraph_styles` that starts with its label and number (*e.g.*, "Table
+/// 3" or "Figure 2"). A caption that Word numbered already has a field, so it
+/// is left alone. So is one whose number is spread over markup that cannot be
+/// split.
+pub fn numbers<'a>(
+    document: &'a str,
+    paragraph_styles: &HashMap<String, ParagraphStyle>,
+) -> Result<Vec<CaptionNumber<'a>>, Diagnostic> {
+    if !paragraph_styles.values().any(|s| s.caption) {
+        debug!(slog_scope::logger(), "No caption styles; no captions");
+        return Ok(Vec::new());
+    }
+    let re = Regex::new(r"^(Table|Figure)(?: |\u{A0}|&#160;)([0-9]{1,4})\b").unwrap();
+
+    let mut numbers = Vec::new();
+    for paragraph in paragraph::paragraphs(document)? {
+        let is_caption = paragraph
+            .style
+            .and_then(|s| paragraph_styles.get(s))
+            .is_some_and(|s| s.caption);
+        let cap = match re.captures(&paragraph.text) {
+            Some(c) if is_caption => c,
+            _ => continue,
+        };
+        let kind = match &cap[1] {
+            "Table" => CaptionKind::Table,
+            _ => CaptionKind::Figure,
+        };
+        let number = cap.get(2).unwrap().range();
+
+        let before = &document[paragraph.input_range(&(0..number.end))];
+        if before.contains("fldSimple") || before.contains("fldChar") {
+            trace!(
+                slog_scope::logger(),
+                "Skipping the caption {:?}, which already has a field",
+                &cap[0]
+            );
+            continue;
+        }
+        if !paragraph.is_splittable(&number) {
+            debug!(
+                slog_scope::logger(),
+                "Skipping the caption {:?}, whose number is spread over markup that cannot be split",
+                &cap[0]
+            );
+            continue;
+        }
+
+        trace!(slog_scope::logger(), "Found the caption {:?}", &cap[0]);
+        numbers.push(CaptionNumber {
+            kind,
+            range: paragraph.input_range(&number),
+            text: paragraph.text[number.clone()].to_string(),
+            runs: paragraph.run_props(&number),
+        });
+    }
+
+    // A paragraph in a text box ends before the paragraph around it.
+    numbers.sort_by_key(|n| n.range.start);
+    debug!(slog_scope::logger(), "Found {} captions", numbers.len());
+    Ok(numbers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caption_numbers() {
+        let caption = |style: &str, text: &str| {
+            format!(
+                r#"<w:p><w:pPr><w:pStyle w:val="{}"/></w:pPr><w:r><w:t xml:space="preserve">{}</w:t></w:r></w:p>"#,
+                style, text
+            )
+        };
+        let document = [
+            caption("TableCaption", "Table 1: Results"),
+            caption("Normal", "Table 2 shows more."),
+            caption("ImageCaption", "Figure\u{A0}1. A chart"),
+            caption("TableCaption", "Summary Table 3"),
+            r#"<w:p><w:pPr><w:pStyle w:val="Caption"/></w:pPr><w:r><w:t xml:space="preserve">Table </w:t></w:r><w:fldSimple w:instr=" SEQ Table \* ARABIC "><w:r><w:t>2</w:t></w:r></w:fldSimple></w:p>"#.to_string(),
+        ]
+        .concat();
+        let styles: HashMap<String, ParagraphStyle> = ["Caption", "TableCaption", "ImageCaption"]
+            .iter()
+            .map(|s| {
+                let style = ParagraphStyle {
+                    caption: true,
+                    ..Default::default()
+                };
+                (s.to_string(), style)
+            })
+            .collect();
+
+        let found = numbers(&document, &styles).unwrap();
+        let kinds: Vec<(CaptionKind, &str)> =
+            found.iter().map(|n| (n.kind, n.text.as_str())).collect();
+        assert_eq!(
+            kinds,
+            [(CaptionKind::Table, "1"), (CaptionKind::Figure, "1")]
+        );
+        assert_eq!(&document[found[0].range.clone()], "1");
+
+        assert!(numbers(&document, &HashMap::new()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,354 @@
+//! This module contains the functionality for checking a document for
+//! cross-references that AutoCref would miss.
+//!
+//! A cross-reference that is not converted stays a plain number, and nobody
+//! finds out until an editor notices a dead number in Word. So the check looks
+//! for text that looks like a cross-reference to a note in the grammar in use
+//! (*e.g.*, "*supra* note 12" or "(n 12)") and reports why it was not
+//! converted: a signal that is not italic, a range or list whose separator is
+//! irregularly spaced, or a number spread over markup that cannot be split. It also reports cross-references whose wording is off
+//! (*e.g.*, "note 3–5," "notes 7," or "notes 9–4") and Supra placeholders
+//! (*e.g.*, "[?id1]") that were never resolved.
+
+use crate::{
+    diagnostic::{Diagnostic, Kind},
+    grammar::{Grammar, Group, Matcher},
+    lexer::{self, Token, TokenType},
+    numfmt::NumFmt,
+    paragraph,
+    parser::{Label, Note, NoteKind},
+};
+use lazy_static::lazy_static;
+use regex::Regex;
+use slog::{debug, trace};
+use std::{collections::HashSet, ops::Range};
+
+lazy_static! {
+    /// A Supra cross-reference that was never resolved (*e.g.*, "[?id1]").
+    static ref RE_PLACEHOLDER: Regex = Regex::new(r"\[\?[^\]\s]+\]").unwrap();
+}
+
+/// Check a part for cross-references that were not converted or whose
+/// wording is off.
+///
+/// The `tokens` are the part's tokens from the lexer, which say which numbers
+/// are converted. The `notes` are the document's numbered notes, which name
+/// the note each problem is in. A note part has `kind`; the body text has
+/// none. What looks like a cross-reference depends on the `grammar` (see
+/// [`Matcher::loose`]) and on the `formats` of the note numbers. Each problem
+/// is a warning named after `part`.
+pub fn check(
+    input: &str,
+    part: &str,
+    kind: Option<NoteKind>,
+    tokens: &[Token],
+    notes: &[Note],
+    grammar: &Grammar,
+    formats: &[NumFmt],
+) -> Result<Vec<Diagnostic>, Diagnostic> {
+    debug!(slog_scope::logger(), "Checking {}...", part);
+    let matcher = Matcher::new(grammar, formats)?;
+    let loose = Matcher::loose(grammar, formats)?;
+
+    // The numbers that were converted, by where they start in the input
+    let converted: HashSet<usize> = tokens
+        .iter()
+        .filter(|t| t.token_type == TokenType::CrossRef)
+        .filter_map(|t| t.offset)
+        .collect();
+
+    // Where each note starts, with the name and number of that note
+    let starts: Vec<(usize, String, Option<u32>)> = match kind {
+        Some(k) => lexer::notes(input)?
+            .into_iter()
+            .map(
+                |(range, id)| match notes.iter().find(|n| n.kind == k && n.id == id) {
+                    Some(n) => {
+                        let number = match n.label {
+                            Label::Number(n) => Some(n),
+                            Label::Mark(_) => None,
+                        };
+                        (range.start, format!("{:?} {}", k, n.display()), number)
+                    }
+                    None => (
+                        range.start,
+                        format!("The {} with id {}", k.name(), id),
+                        None,
+                    ),
+                },
+            )
+            .collect(),
+        None => Vec::new(),
+    };
+    let place = |offset: usize| match starts.iter().rev().find(|(s, _, _)| *s <= offset) {
+        Some((_, name, number)) => (name.clone(), *number),
+        None => ("The body text".to_string(), None),
+    };
+
+    let mut found: Vec<Diagnostic> = Vec::new();
+    for paragraph in paragraph::paragraphs(input)? {
+        let text = &paragraph.text;
+        let mut warn = |range: Range<usize>, problem: String| {
+            let offset = paragraph.input_range(&range).start;
+            let (name, number) = place(offset);
+            let warning_msg = format!("{} has {}", name, problem);
+            trace!(slog_scope::logger(), "{}", warning_msg);
+            let mut diagnostic = Diagnostic::warning(Kind::Reference, warning_msg)
+                .in_part(part)
+                .at(offset)
+                .with_context(&lexer::context(text, &range));
+            if let Some(n) = number {
+                diagnostic = diagnostic.in_note(n);
+            }
+            found.push(diagnostic);
+        };
+
+        for m in RE_PLACEHOLDER.find_iter(text) {
+            let problem = format!(
+                "the Supra placeholder {:?}, which was never resolved",
+                m.as_str()
+            );
+            warn(m.range(), problem);
+        }
+
+        for cap in loose.re.captures_iter(text) {
+            let whole = cap.get(0).unwrap();
+            let mut previous = whole.start();
+            for (group, group_kind) in loose.groups(&cap) {
+                let word_range = previous..group.start();
+                previous = group.end();
+                let plural = match group_kind {
+                    Group::Note => false,
+                    Group::List => true,
+                    _ => continue,
+                };
+                let word = text[word_range].trim_matches(|c: char| c == ',' || c.is_whitespace());
+
+                // The numbers in the list, with the separators before them
+                let mut numbers: Vec<(&str, Range<usize>)> = Vec::new();
+                let mut separator = "";
+                let mut start = 0;
+                for m in loose.re_separator.find_iter(group.as_str()) {
+                    numbers.push((separator, group.start() + start..group.start() + m.start()));
+                    separator = m.as_str();
+                    start = m.end();
+                }
+                numbers.push((separator, group.start() + start..group.end()));
+
+                // The wording
+                if plural && numbers.len() == 1 {
+                    let problem = format!(
+                        "{:?}, which has only one number after {:?}",
+                        whole.as_str(),
+                        word
+                    );
+                    warn(whole.range(), problem);
+                } else if !plural && numbers.len() > 1 {
+                    let problem = format!(
+                        "{:?}, which has more than one number after {:?}",
+                        whole.as_str(),
+                        word
+                    );
+                    warn(whole.range(), problem);
+                }
+                for pair in numbers.windows(2) {
+                    let (first, (separator, last)) = (&pair[0].1, &pair[1]);
+                    let backward = text[first.clone()].parse::<u32>().ok()
+                        > text[last.clone()].parse::<u32>().ok();
+                    if loose.separator(separator) == Some(true) && backward {
+                        let problem = format!("{:?}, whose range runs backward", whole.as_str());
+                        warn(whole.range(), problem);
+                    }
+                }
+
+                // Why the numbers that were not converted were not. After
+                // the singular word, only the first number could have been.
+                let is_converted = |r: &Range<usize>| {
+                    paragraph.is_splittable(r)
+                        && converted.contains(&paragraph.input_range(r).start)
+                };
+                let candidates = if plural { &numbers[..] } else { &numbers[..1] };
+                let missed = match candidates.iter().find(|(_, r)| !is_converted(r)) {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let number = &missed.1;
+                let irregular = numbers
+                    .iter()
+                    .skip(1)
+                    .find(|(s, _)| matcher.separator(s).is_none());
+                if !paragraph.is_splittable(number) {
+                    let problem = format!(
+                        "{:?}, whose number {:?} is spread over markup that cannot be split",
+                        whole.as_str(),
+                        &text[number.clone()]
+                    );
+                    warn(whole.range(), problem);
+                } else if let Some(signal) =
+                    loose.unitalicized_signal(&paragraph, &cap).filter(|_| {
+                        !loose.has_signal(&paragraph, &cap)
+                            && !numbers.iter().any(|(_, r)| is_converted(r))
+                    })
+                {
+                    let problem = format!(
+                        "{:?}, which was not converted because {:?} is not italic",
+                        &text[signal.start..whole.end()],
+                        &text[signal.clone()]
+                    );
+                    warn(signal.start..whole.end(), problem);
+                } else if let Some((separator, _)) = irregular {
+                    let what = match loose.separator(separator) {
+                        Some(true) => "range",
+                        _ => "list",
+                    };
+                    let problem = format!(
+                        "{:?}, whose {} was not converted because the spacing around {:?} is irregular",
+                        whole.as_str(),
+                        what,
+                        separator.trim()
+                    );
+                    warn(whole.range(), problem);
+                }
+            }
+        }
+    }
+
+    found.sort_by_key(|d| d.offset);
+    debug!(
+        slog_scope::logger(),
+        "Found {} problems in {}",
+        found.len(),
+        part
+    );
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::{CitationStyle, Phrase};
+
+    #[test]
+    fn near_misses() {
+        let run = |text: &str, italic: bool| {
+            let props = if italic { "<w:rPr><w:i/></w:rPr>" } else { "" };
+            format!(
+                r#"<w:r>{}<w:t xml:space="preserve">{}</w:t></w:r>"#,
+                props, text
+            )
+        };
+        let input = [
+            "<w:body><w:p>".to_string(),
+            run("supra", true),
+            run(" note 1, but ", false),
+            run("supra note 2, ", false),
+            run("supra", true),
+            run(" notes 3, ", false),
+            run("supra", true),
+            run(" notes 6–4, and ", false),
+            run("supra", true),
+            run(" notes 7 \u{2013}  8. See [?id9].", false),
+            "</w:p></w:body>".to_string(),
+        ]
+        .concat();
+
+        // The "3" and "6–4" were converted, so they are only reported for
+        // their wording.
+        let converted = |number: &str| {
+            let offset = input.find(number).unwrap();
+            Token::new(TokenType::CrossRef, &input[offset..offset + number.len()])
+                .with_context("", offset)
+        };
+        let tokens = [
+            converted(" 1,").with_context("", input.find("1,").unwrap()),
+            converted("3,"),
+            converted("6–4"),
+            converted("4,"),
+        ];
+        let bluebook = CitationStyle::Bluebook.grammar();
+        let formats = [NumFmt::Decimal];
+        let found = check(
+            &input,
+            "word/document.xml",
+            None,
+            &tokens,
+            &[],
+            &bluebook,
+            &formats,
+        )
+        .unwrap();
+        let messages: Vec<&str> = found.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "The body text has \"supra note 2\", which was not converted because \"supra\" is not italic",
+                "The body text has \"notes 3\", which has only one number after \"notes\"",
+                "The body text has \"notes 6–4\", whose range runs backward",
+                "The body text has \"notes 7 –  8\", whose range was not converted because the spacing around \"–\" is irregular",
+                "The body text has the Supra placeholder \"[?id9]\", which was never resolved",
+            ]
+        );
+        assert!(found
+            .iter()
+            .all(|d| d.severity == crate::diagnostic::Severity::Warning));
+        assert_eq!(found[0].offset, Some(input.find("supra note 2").unwrap()));
+        assert_eq!(found[0].part.as_deref(), Some("word/document.xml"));
+
+        // Without signals, a signal need not be italic
+        let lenient = Grammar {
+            signals: Vec::new(),
+            ..bluebook
+        };
+        let found = check(
+            &input,
+            "word/document.xml",
+            None,
+            &tokens,
+            &[],
+            &lenient,
+            &formats,
+        )
+        .unwrap();
+        assert_eq!(found.len(), 4);
+
+        // The near misses are those of the grammar in use, whether a preset
+        // or one from a configuration file.
+        let input = [
+            "<w:body><w:p>".to_string(),
+            run("Smith (nn 3 –  4); ", false),
+            run("supra note 5; oben Fn. 6 ,  7.", false),
+            "</w:p></w:body>".to_string(),
+        ]
+        .concat();
+        let messages = |grammar: &Grammar| -> Vec<String> {
+            check(
+                &input,
+                "word/document.xml",
+                None,
+                &[],
+                &[],
+                grammar,
+                &formats,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|d| d.message)
+            .collect()
+        };
+        assert_eq!(
+            messages(&CitationStyle::Oscola.grammar()),
+            ["The body text has \"nn 3 –  4\", whose range was not converted because the spacing around \"–\" is irregular"]
+        );
+        let config = Grammar {
+            phrases: vec![Phrase {
+                pattern: r"oben Fn\. {LIST}".to_string(),
+                signal: false,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            messages(&config),
+            ["The body text has \"oben Fn. 6 ,  7\", whose list was not converted because the spacing around \",\" is irregular"]
+        );
+    }
+}
@@ -0,0 +1,348 @@
+//! This module contains the functionality for reading `autocref.toml`.
+//!
+//! The configuration file adds forms of cross-reference to a citation style's
+//! grammar (or replaces its forms), for projects whose cross-references do not
+//! fit any of the presets (*e.g.*, "oben Fn. 12" or "see text at note 12").
+//!
+//! ```toml
+//! citation-style = "bluebook"
+//! list-separators = [", ", ",? (?:and|und) "]
+//!
+//! [[phrase]]
+//! pattern = 'oben Fn\. {NOTE}'
+//! signal = false
+//!
+//! [[phrase]]
+//! pattern = 'see text at note (?P<n>[0-9]+)'
+//! notes = ["n"]
+//! ```
+//!
+//! A phrase can mark its numbers with the placeholders of a [`Phrase`] or
+//! with its own capture groups, listed under `notes`, `lists`, `chapters`,
+//! `headings`, `paragraphs`, `tables`, or `figures`.
+
+use crate::{
+    diagnostic::{Diagnostic, Kind},
+    grammar::{CitationStyle, Grammar, Matcher, Phrase, Signal},
+};
+use slog::{debug, trace};
+use toml::{Table, Value};
+
+/// The name of the configuration file.
+pub const CONFIG_FILE: &str = "autocref.toml";
+
+/// Read the grammar in a configuration file.
+///
+/// The grammar starts from a citation style: `citation_style` if it is
+/// provided (*e.g.*, on the command line), otherwise the file's
+/// `citation-style`, otherwise the Bluebook. The file's phrases and signals
+/// are added to the style's, unless `replace` is set, in which case they take
+/// the place of the style's. Its separators always take the place of the
+/// style's.
+pub fn grammar(
+    config_input: &str,
+    citation_style: Option<CitationStyle>,
+) -> Result<Grammar, Diagnostic> {
+    let config: Table = match toml::from_str(config_input) {
+        Ok(c) => c,
+        Err(e) => {
+            let err_msg = format!("Invalid toml: {}", e);
+            return Err(Diagnostic::error(Kind::Config, err_msg));
+        }
+    };
+    check_keys(
+        &config,
+        "the configuration",
+        &[
+            "citation-style",
+            "replace",
+            "range-separators",
+            "list-separators",
+            "phrase",
+            "signal",
+        ],
+    )?;
+
+    // Start from the citation style
+    let citation_style = match (citation_style, config.get("citation-style")) {
+        (Some(s), _) => s,
+        (None, Some(Value::String(name))) => match CitationStyle::from_name(name) {
+            Some(s) => s,
+            None => {
+                let err_msg = format!("Unknown citation-style {:?}", name);
+                return Err(Diagnostic::error(Kind::Config, err_msg));
+            }
+        },
+        (None, Some(_)) => {
+            let err_msg = "citation-style must be a string".to_string();
+            return Err(Diagnostic::error(Kind::Config, err_msg));
+        }
+        (None, None) => CitationStyle::default(),
+    };
+    debug!(
+        slog_scope::logger(),
+        "Starting from the {:?} grammar", citation_style
+    );
+    let mut grammar = citation_style.grammar();
+
+    if boolean(&config, "replace", "the configuration")?.unwrap_or(false) {
+        grammar.phrases.clear();
+        grammar.signals.clear();
+    }
+    if let Some(s) = strings(&config, "range-separators", "the configuration")? {
+        grammar.range_separators = s;
+    }
+    if let Some(s) = strings(&config, "list-separators", "the configuration")? {
+        grammar.list_separators = s;
+    }
+
+    // The file's phrases come first, so that they win over the style's
+    // phrases that start at the same place.
+    let mut phrases = Vec::new();
+    for (i, table) in tables(&config, "phrase")?.into_iter().enumerate() {
+        let context = format!("phrase {}", i + 1);
+        check_keys(
+            table,
+            &context,
+            &[
+                "pattern",
+                "signal",
+                "notes",
+                "lists",
+                "chapters",
+                "headings",
+                "paragraphs",
+                "tables",
+                "figures",
+            ],
+        )?;
+
+        let mut pattern = match table.get("pattern") {
+            Some(Value::String(p)) => p.clone(),
+            _ => {
+                let err_msg = format!("{} needs a pattern string", context);
+                return Err(Diagnostic::error(Kind::Config, err_msg));
+            }
+        };
+
+        // Name the groups after the kinds of numbers in them
+        for (key, name) in [
+            ("notes", "note"),
+            ("lists", "list"),
+            ("chapters", "chapter"),
+            ("headings", "heading"),
+            ("paragraphs", "paragraph"),
+            ("tables", "table"),
+            ("figures", "figure"),
+        ] {
+            for group in strings(table, key, &context)?.unwrap_or_default() {
+                let from = format!("(?P<{}>", group);
+                if !pattern.contains(&from) {
+                    let err_msg = format!("{} has no group named {:?}", context, group);
+                    return Err(Diagnostic::error(Kind::Config, err_msg));
+                }
+                pattern = pattern.replace(&from, &format!("(?P<{}>", name));
+            }
+        }
+        if ![
+            "{NOTE}",
+            "{LIST}",
+            "{HEADING}",
+            "{PARAGRAPH}",
+            "{PARAGRAPHS}",
+            "{TABLE}",
+            "{FIGURE}",
+            "(?P<note>",
+            "(?P<list>",
+            "(?P<heading>",
+            "(?P<paragraph>",
+            "(?P<table>",
+            "(?P<figure>",
+        ]
+        .iter()
+        .any(|p| pattern.contains(p))
+        {
+            let err_msg = format!(
+                "{} has no note, heading, paragraph, table, or figure numbers",
+                context
+            );
+            return Err(Diagnostic::error(Kind::Config, err_msg));
+        }
+
+        trace!(slog_scope::logger(), "Adding phrase {:?}", pattern);
+        phrases.push(Phrase {
+            pattern,
+            signal: boolean(table, "signal", &context)?.unwrap_or(true),
+        });
+    }
+    phrases.append(&mut grammar.phrases);
+    grammar.phrases = phrases;
+
+    for (i, table) in tables(&config, "signal")?.into_iter().enumerate() {
+        let context = format!("signal {}", i + 1);
+        check_keys(table, &context, &["before", "after", "italic"])?;
+
+        let string = |key: &str| match table.get(key) {
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => {
+                let err_msg = format!("{} of {} must be a string", key, context);
+                Err(Diagnostic::error(Kind::Config, err_msg))
+            }
+            None => Ok(None),
+        };
+        grammar.signals.push(Signal {
+            before: string("before")?,
+            after: string("after")?,
+            italic: boolean(table, "italic", &context)?.unwrap_or(false),
+        });
+    }
+
+    // Make sure that the patterns are valid before any files are read
+    Matcher::new(&grammar, &[])?;
+
+    Ok(grammar)
+}
+
+/// Make sure that a table has only the known keys, to catch typos.
+fn check_keys(table: &Table, context: &str, keys: &[&str]) -> Result<(), Diagnostic> {
+    match table.keys().find(|k| !keys.contains(&k.as_str())) {
+        Some(k) => {
+            let err_msg = format!("Unknown key {:?} in {}", k, context);
+            Err(Diagnostic::error(Kind::Config, err_msg))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Get a boolean from a table.
+fn boolean(table: &Table, key: &str, context: &str) -> Result<Option<bool>, Diagnostic> {
+    match table.get(key) {
+        Some(Value::Boolean(b)) => Ok(Some(*b)),
+        Some(_) => {
+            let err_msg = format!("{} of {} must be true or false", key, context);
+            Err(Diagnostic::error(Kind::Config, err_msg))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Get an array of strings from a table.
+fn strings(table: &Table, key: &str, context: &str) -> Result<Option<Vec<String>>, Diagnostic> {
+    let array = match table.get(key) {
+        Some(Value::Array(a)) => a,
+        Some(_) => {
+            let err_msg = format!("{} of {} must be an array of strings", key, context);
+            return Err(Diagnostic::error(Kind::Config, err_msg));
+        }
+        None => return Ok(None),
+    };
+
+    let mut strings = Vec::new();
+    for value in array {
+        match value {
+            Value::String(s) => strings.push(s.clone()),
+            _ => {
+                let err_msg = format!("{} of {} must be an array of strings", key, context);
+                return Err(Diagnostic::error(Kind::Config, err_msg));
+            }
+        }
+    }
+    Ok(Some(strings))
+}
+
+/// Get an array of tables (*e.g.*, the `[[phrase]]`s) from the configuration.
+fn tables<'a>(config: &'a Table, key: &str) -> Result<Vec<&'a Table>, Diagnostic> {
+    let array = match config.get(key) {
+        Some(Value::Array(a)) => a,
+        Some(_) => {
+            let err_msg = format!("{} must be an array of tables ([[{}]])", key, key);
+            return Err(Diagnostic::error(Kind::Config, err_msg));
+        }
+        None => return Ok(Vec::new()),
+    };
+
+    let mut tables = Vec::new();
+    for value in array {
+        match value {
+            Value::Table(t) => tables.push(t),
+            _ => {
+                let err_msg = format!("{} must be an array of tables ([[{}]])", key, key);
+                return Err(Diagnostic::error(Kind::Config, err_msg));
+            }
+        }
+    }
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_phrases() {
+        let input = r#"
+citation-style = "oscola"
+list-separators = [", ", " und "]
+
+[[phrase]]
+pattern = 'oben Fn\. {LIST}'
+signal = false
+
+[[phrase]]
+pattern = 'see text at note (?P<n>[0-9]+)'
+notes = ["n"]
+
+[[phrase]]
+pattern = 'Abschnitt (?P<h>[0-9.]+)'
+headings = ["h"]
+
+[[phrase]]
+pattern = 'Tabelle (?P<t>[0-9]+)'
+tables = ["t"]
+
+[[signal]]
+before = '\bvoir '
+"#;
+
+        let grammar = grammar(input, None).unwrap();
+        assert_eq!(grammar.phrases.len(), 13);
+        assert_eq!(grammar.phrases[0].pattern, r"oben Fn\. {LIST}");
+        assert!(!grammar.phrases[0].signal);
+        assert_eq!(
+            grammar.phrases[1].pattern,
+            "see text at note (?P<note>[0-9]+)"
+        );
+        assert!(grammar.phrases[1].signal);
+        assert_eq!(grammar.phrases[2].pattern, "Abschnitt (?P<heading>[0-9.]+)");
+        assert_eq!(grammar.phrases[3].pattern, "Tabelle (?P<table>[0-9]+)");
+        assert_eq!(
+            grammar.phrases[4..],
+            CitationStyle::Oscola.grammar().phrases
+        );
+        assert_eq!(grammar.list_separators, [", ", " und "]);
+        assert_eq!(
+            grammar.range_separators,
+            CitationStyle::Oscola.grammar().range_separators
+        );
+        assert_eq!(grammar.signals.len(), 3);
+
+        let grammar = super::grammar(
+            "replace = true\n[[phrase]]\npattern = 'nota {NOTE}'",
+            Some(CitationStyle::Chicago),
+        )
+        .unwrap();
+        assert_eq!(grammar.phrases, [Phrase::new("nota {NOTE}")]);
+        assert!(grammar.signals.is_empty());
+
+        for input in [
+            "citation-style = \"mla\"",
+            "phrases = []",
+            "[[phrase]]\npattern = 'nota'",
+            "[[phrase]]\npattern = 'nota (?P<n>[0-9]+)'\nnotes = [\"m\"]",
+            "[[phrase]]\npattern = 'nota {NOTE'",
+            "[[phrase]]\npattern = 'nota ({NOTE}'",
+        ] {
+            assert!(super::grammar(input, None).is_err(), "{}", input);
+        }
+    }
+}
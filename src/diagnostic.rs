@@ -0,0 +1,241 @@
+//! This module contains the diagnostics that AutoCref reports.
+//!
+//! A diagnostic says what went wrong (its [`Kind`] and message) and, as far as
+//! it is known, where: the part of the package, the byte offset in that part,
+//! the note it is in, and the text around it. That way a program running
+//! AutoCref can tell a file that could not be read from a malformed document
+//! or a cross-reference to a note that does not exist, and a person can find
+//! the problem in the document. A diagnostic displays itself the way a compiler
+//! does:
+//!
+//! ```text
+//! error[reference]: Footnote 1 refers to footnote 412, which does not exist
+//!   --> word/footnotes.xml (byte 5321, note 1)
+//!    | …nized in its cases, see supra note 412, at 5, the rule applies in ev…
+//! ```
+
+use std::fmt;
+
+/// The name of the main document part in diagnostics.
+///
+/// [`crate::autocref_parts`] does not know where its parts are in a package,
+/// so it names them after the parts' usual places.
+pub(crate) const DOCUMENT_PART: &str = "word/document.xml";
+/// The name of the footnotes part in diagnostics.
+pub(crate) const FOOTNOTES_PART: &str = "word/footnotes.xml";
+/// The name of the endnotes part in diagnostics.
+pub(crate) const ENDNOTES_PART: &str = "word/endnotes.xml";
+/// The name of the settings part in diagnostics.
+pub(crate) const SETTINGS_PART: &str = "word/settings.xml";
+/// The name of the styles part in diagnostics.
+pub(crate) const STYLES_PART: &str = "word/styles.xml";
+/// The name of the numbering part in diagnostics.
+pub(crate) const NUMBERING_PART: &str = "word/numbering.xml";
+
+/// The kinds of problems that AutoCref reports.
+///
+/// `Io` is a file that could not be read. `Package` is a `.docx` package that
+/// could not be opened or written, or that is missing a part. `Xml` is a part
+/// whose contents could not be read. `Config` is a configuration (*e.g.*,
+/// `autocref.toml`) or cross-reference pattern that is not valid. `Reference`
+/// is a cross-reference that cannot be converted (*e.g.*, one to a note that
+/// does not exist).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Io,
+    Package,
+    Xml,
+    Config,
+    Reference,
+}
+
+impl Kind {
+    /// The kind's name, for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Io => "io",
+            Kind::Package => "package",
+            Kind::Xml => "xml",
+            Kind::Config => "config",
+            Kind::Reference => "reference",
+        }
+    }
+}
+
+/// How serious a problem is.
+///
+/// An error stops AutoCref. A warning does not, and whatever it is about is
+/// left as it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    /// The severity's name, for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A problem that AutoCref found.
+///
+/// The `part` is the name of the part of the package (or the file) the
+/// problem is in, and the `offset` is where in that part it is, in bytes. A
+/// problem in a note has the `note`'s number (unless the note has a custom
+/// mark instead). A problem with a cross-reference has its `context`, the text
+/// around it. A diagnostic that sums up several problems (*e.g.*, every
+/// cross-reference to a note that does not exist) has them as `related`
+/// diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: Kind,
+    pub severity: Severity,
+    pub message: String,
+    pub part: Option<String>,
+    pub offset: Option<usize>,
+    pub note: Option<u32>,
+    pub context: Option<String>,
+    pub related: Box<[Diagnostic]>,
+}
+
+impl Diagnostic {
+    /// Creates a new error [`Diagnostic`].
+    pub fn error(kind: Kind, message: String) -> Diagnostic {
+        Diagnostic {
+            kind,
+            severity: Severity::Error,
+            message,
+            part: None,
+            offset: None,
+            note: None,
+            context: None,
+            related: Box::new([]),
+        }
+    }
+
+    /// Creates a new warning [`Diagnostic`].
+    pub fn warning(kind: Kind, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(kind, message)
+        }
+    }
+
+    /// Adds the part that a [`Diagnostic`] is in, unless it already has one.
+    ///
+    /// This lets a function that reads several parts name the part of a
+    /// problem that it knows better than its caller.
+    pub fn in_part(self, part: &str) -> Diagnostic {
+        Diagnostic {
+            part: self.part.or_else(|| Some(part.to_string())),
+            ..self
+        }
+    }
+
+    /// Adds the byte offset of a problem to a [`Diagnostic`].
+    pub fn at(self, offset: usize) -> Diagnostic {
+        Diagnostic {
+            offset: Some(offset),
+            ..self
+        }
+    }
+
+    /// Adds the note that a problem is in to a [`Diagnostic`].
+    pub fn in_note(self, note: u32) -> Diagnostic {
+        Diagnostic {
+            note: Some(note),
+            ..self
+        }
+    }
+
+    /// Adds the text around a problem to a [`Diagnostic`].
+    pub fn with_context(self, context: &str) -> Diagnostic {
+        Diagnostic {
+            context: Some(context.to_string()),
+            ..self
+        }
+    }
+
+    /// Adds the problems that a [`Diagnostic`] sums up.
+    pub fn with_related(self, related: Vec<Diagnostic>) -> Diagnostic {
+        Diagnostic {
+            related: related.into_boxed_slice(),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {}",
+            self.severity.name(),
+            self.kind.name(),
+            self.message
+        )?;
+
+        // Where the problem is
+        let mut location: Vec<String> = Vec::new();
+        if let Some(o) = self.offset {
+            location.push(format!("byte {}", o));
+        }
+        if let Some(n) = &self.note {
+            location.push(format!("note {}", n));
+        }
+        match (&self.part, location.is_empty()) {
+            (Some(p), true) => write!(f, "\n  --> {}", p)?,
+            (Some(p), false) => write!(f, "\n  --> {} ({})", p, location.join(", "))?,
+            (None, false) => write!(f, "\n  --> {}", location.join(", "))?,
+            (None, true) => {}
+        }
+        if let Some(c) = &self.context {
+            write!(f, "\n   | {}", c)?;
+        }
+
+        for related in self.related.iter() {
+            write!(f, "\n\n{}", related)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let dangling = Diagnostic::error(
+            Kind::Reference,
+            "Footnote 1 refers to footnote 412, which does not exist".to_string(),
+        )
+        .in_part(FOOTNOTES_PART)
+        .in_part(DOCUMENT_PART)
+        .at(5321)
+        .in_note(1)
+        .with_context("…see supra note 412, at 5…");
+        assert_eq!(dangling.part.as_deref(), Some(FOOTNOTES_PART));
+        assert_eq!(
+            dangling.to_string(),
+            "error[reference]: Footnote 1 refers to footnote 412, which does not exist\n  --> word/footnotes.xml (byte 5321, note 1)\n   | …see supra note 412, at 5…"
+        );
+
+        let io = Diagnostic::warning(Kind::Io, "error reading the file a.xml".to_string());
+        assert_eq!(io.to_string(), "warning[io]: error reading the file a.xml");
+
+        let summary = Diagnostic::error(Kind::Reference, "Found 2".to_string())
+            .with_related(vec![io.clone(), io.in_part("a.xml")]);
+        assert_eq!(
+            summary.to_string(),
+            "error[reference]: Found 2\n\nwarning[io]: error reading the file a.xml\n\nwarning[io]: error reading the file a.xml\n  --> a.xml"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::diagnostic::{Diagnostic, Kind};
 use std::{fs, path::Path};
 
 use slog::debug;
@@ -5,7 +6,7 @@ use slog::debug;
 /// Load a file into a string.
 ///
 /// This function loads the contents of a plain-text file into a string.
-pub fn load_file(path: &Path) -> Result<String, String> {
+pub fn load_file(path: &Path) -> Result<String, Diagnostic> {
     debug!(
         slog_scope::logger(),
         "Loading file {:}",
@@ -23,7 +24,7 @@ pub fn load_file(path: &Path) -> Result<String, String> {
         }
         Err(e) => {
             let err_msg = format!("error reading the file {}—{}", path.to_string_lossy(), e);
-            Err(err_msg)
+            Err(Diagnostic::error(Kind::Io, err_msg).in_part(&path.to_string_lossy()))
         }
     }
 }
@@ -31,20 +32,95 @@ pub fn load_file(path: &Path) -> Result<String, String> {
 /// Save a string to a file.
 ///
 /// This function saves a string as a file.
-pub fn save_file(path: &Path, output: &str) {
+pub fn save_file(path: &Path, output: &str) -> Result<(), Diagnostic> {
     debug!(
         slog_scope::logger(),
         "Saving file {:}",
         path.to_string_lossy()
     );
 
-    fs::write(path, output).expect("Unable to write file");
+    match fs::write(path, output) {
+        Ok(()) => {
+            debug!(
+                slog_scope::logger(),
+                "File {} saved.",
+                path.to_string_lossy()
+            );
+            Ok(())
+        }
+        Err(e) => {
+            let err_msg = format!("error writing the file {}—{}", path.to_string_lossy(), e);
+            Err(Diagnostic::error(Kind::Io, err_msg).in_part(&path.to_string_lossy()))
+        }
+    }
+}
+
+/// Load a part of an unzipped package.
+///
+/// This function loads a part (*e.g.*, `word/document.xml`) relative to the
+/// current directory, which should be the directory into which a `.docx` file
+/// was unzipped. A part that does not exist returns `None`.
+pub fn load_part(name: &str) -> Result<Option<String>, Diagnostic> {
+    let path = Path::new(name);
+    if path.is_file() {
+        load_file(path).map(Some)
+    } else {
+        debug!(slog_scope::logger(), "Part {} not found", name);
+        Ok(None)
+    }
+}
 
+/// Load a `.docx` file into bytes.
+///
+/// This function loads the contents of a binary file, such as a `.docx`
+/// package, into a vector of bytes.
+pub fn load_docx(path: &Path) -> Result<Vec<u8>, Diagnostic> {
     debug!(
         slog_scope::logger(),
-        "Fikle {} saved.",
+        "Loading package {:}",
         path.to_string_lossy()
     );
+
+    match fs::read(path) {
+        Ok(r) => {
+            debug!(
+                slog_scope::logger(),
+                "Package {} loaded.",
+                path.to_string_lossy()
+            );
+            Ok(r)
+        }
+        Err(e) => {
+            let err_msg = format!("error reading the file {}—{}", path.to_string_lossy(), e);
+            Err(Diagnostic::error(Kind::Io, err_msg).in_part(&path.to_string_lossy()))
+        }
+    }
+}
+
+/// Save bytes to a `.docx` file.
+///
+/// This function saves the bytes of a package as a file.
+pub fn save_docx(path: &Path, output: &[u8]) -> Result<(), Diagnostic> {
+    debug!(
+        slog_scope::logger(),
+        "Saving package {:}",
+        path.to_string_lossy()
+    );
+
+    match fs::write(path, output) {
+        Ok(()) => {
+            debug!(
+                slog_scope::logger(),
+                "Package {} saved.",
+                path.to_string_lossy()
+            );
+            Ok(())
+        }
+        Err(e) => {
+            let err_msg = format!("error writing the file {}—{}", path.to_string_lossy(), e);
+            Err(Diagnostic::error(Kind::Io, err_msg).in_part(&path.to_string_lossy()))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -67,10 +143,28 @@ mod tests {
         #[test]
         fn fail_load_file() {
             let file = "./tests/does-not-exist.md";
-            let load_result = load_file(Path::new(file));
-            assert!(load_result
-                .unwrap_err()
-                .contains("No such file or directory"));
+            let err = load_file(Path::new(file)).unwrap_err();
+            assert_eq!(err.kind, Kind::Io);
+            assert_eq!(err.part.as_deref(), Some(file));
+            assert!(err.message.contains("No such file or directory"));
+        }
+
+        #[test]
+        fn fail_load_docx() {
+            let file = "./tests/does-not-exist.docx";
+            let err = load_docx(Path::new(file)).unwrap_err();
+            assert_eq!(err.kind, Kind::Io);
+            assert_eq!(err.part.as_deref(), Some(file));
+            assert!(err.message.contains("No such file or directory"));
         }
     }
+
+    #[test]
+    fn fail_save_file() {
+        let file = "./tests/does-not-exist/document.xml";
+        let err = save_file(Path::new(file), "").unwrap_err();
+        assert_eq!(err.kind, Kind::Io);
+        assert_eq!(err.part.as_deref(), Some(file));
+        assert!(err.message.contains("No such file or directory"));
+    }
 }
@@ -0,0 +1,735 @@
+//! This module contains the grammar of cross-references.
+//!
+//! Citation styles write cross-references differently. The Bluebook uses
+//! "*supra* note 12" (or "n.12" and "nn.12–14" in parentheticals), OSCOLA and
+//! AGLC use "(n 12)" or "n 12," and Chicago uses "see note 12 above." A
+//! [`Grammar`] describes one of those ways: the phrases that make up a
+//! cross-reference, the separators between the numbers in a list, and the
+//! signals that a cross-reference needs in strict mode. Each
+//! [`CitationStyle`] has a built-in grammar.
+//!
+//! Cross-references can also refer to the document's own numbered headings
+//! and paragraphs (*e.g.*, "*infra* Part II.A," "*supra* ¶ 12," or "Section
+//! 4.2(b) above") and to its tables and figures (*e.g.*, "*infra* Table 3").
+
+use crate::{
+    diagnostic::{Diagnostic, Kind},
+    numfmt::NumFmt,
+    paragraph::Paragraph,
+};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::ops::Range;
+
+/// The citation styles that AutoCref has a grammar for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CitationStyle {
+    #[default]
+    Bluebook,
+    Oscola,
+    Aglc,
+    Chicago,
+}
+
+/// The names of the citation styles, as used on the command line.
+pub const CITATION_STYLE_NAMES: [&str; 4] = ["bluebook", "oscola", "aglc", "chicago"];
+
+impl CitationStyle {
+    /// Get a citation style by its name (ignoring case).
+    pub fn from_name(name: &str) -> Option<CitationStyle> {
+        match name.to_lowercase().as_str() {
+            "bluebook" => Some(CitationStyle::Bluebook),
+            "oscola" => Some(CitationStyle::Oscola),
+            "aglc" => Some(CitationStyle::Aglc),
+            "chicago" => Some(CitationStyle::Chicago),
+            _ => None,
+        }
+    }
+
+    /// Get the citation style's grammar.
+    pub fn grammar(&self) -> Grammar {
+        let phrases = |s: &[&str]| -> Vec<Phrase> {
+            s.iter()
+                .chain(&PARAGRAPH_PHRASES)
+                .chain(&CAPTION_PHRASES)
+                .map(|s| Phrase::new(s))
+                .collect()
+        };
+        let signal = |before: Option<&str>, after: Option<&str>, italic: bool| Signal {
+            before: before.map(String::from),
+            after: after.map(String::from),
+            italic,
+        };
+
+        match self {
+            // "*supra* note 12," "*infra* notes 12–14," "*supra* ch. 2, n. 12,"
+            // and "*supra* n.12" in a parenthetical. A sentence that starts
+            // with "Note 12" or "Notes 12–14" needs a signal, too, since a
+            // period after an abbreviation ("Id." or "Code.") looks just like
+            // the end of a sentence. Briefs and agreements also write "Section
+            // 4.2(b) above."
+            CitationStyle::Bluebook => Grammar {
+                phrases: phrases(&[
+                    r"\bch\. {CHAPTER}, n\. ?{NOTE}",
+                    r"\bch\. {CHAPTER}, nn\. ?{LIST}",
+                    r"\b[Nn]ote {NOTE}",
+                    r"\b[Nn]otes {LIST}",
+                    r"\bn\. ?{NOTE}",
+                    r"\bnn\. ?{LIST}",
+                ]),
+                signals: vec![
+                    signal(Some(r"\b(?i:supra|infra) "), None, true),
+                    signal(None, Some(r" (?:above|below)\b"), false),
+                ],
+                ..Default::default()
+            },
+            // "Smith (n 12) 45," "(nn 12–14)," and "see n 12"
+            CitationStyle::Oscola => Grammar {
+                phrases: phrases(&[r"\bn {NOTE}", r"\bnn {LIST}"]),
+                signals: vec![
+                    signal(Some(r"\("), Some(r"\)"), false),
+                    signal(Some(r"\b(?:[Ss]ee|[Cc]f)(?: also)? "), None, false),
+                ],
+                ..Default::default()
+            },
+            // The same, plus the older "Smith, above n 12, 45"
+            CitationStyle::Aglc => Grammar {
+                phrases: phrases(&[r"\bn {NOTE}", r"\bnn {LIST}"]),
+                signals: vec![
+                    signal(Some(r"\("), Some(r"\)"), false),
+                    signal(Some(r"\b(?:[Ss]ee|[Cc]f)(?: also)? "), None, false),
+                    signal(Some(r"\b[Aa]bove "), None, false),
+                ],
+                ..Default::default()
+            },
+            // "see note 12 above," "notes 12–14 below," and "n. 12 above"
+            CitationStyle::Chicago => Grammar {
+                phrases: phrases(&[
+                    r"\b[Nn]ote {NOTE}",
+                    r"\b[Nn]otes {LIST}",
+                    r"\bn\. {NOTE}",
+                    r"\bnn\. {LIST}",
+                ]),
+                signals: vec![signal(None, Some(r" (?:above|below)\b"), false)],
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// The phrases for cross-references to headings and numbered paragraphs,
+/// which every citation style has: "Part II.A," "Section 4.2(b)," "¶ 12," and
+/// "¶¶ 12–14."
+const PARAGRAPH_PHRASES: [&str; 4] = [
+    r"\b[Pp]art {HEADING}",
+    r"\b[Ss]ection {HEADING}",
+    r"¶¶ ?{PARAGRAPHS}",
+    r"¶ ?{PARAGRAPH}",
+];
+
+/// The phrases for cross-references to tables and figures, which every
+/// citation style has, too: "Table 3," "Figure 2," and "Fig. 2."
+const CAPTION_PHRASES: [&str; 3] = [
+    r"\b[Tt]able {TABLE}",
+    r"\b[Ff]igure {FIGURE}",
+    r"\bFig\. ?{FIGURE}",
+];
+
+/// A signal that a cross-reference needs in strict mode.
+///
+/// `before` must match the text right before the cross-reference, and `after`
+/// must match the text right after it. Both are regex patterns. If `italic` is
+/// set, the words matched by `before` must be italicized.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Signal {
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub italic: bool,
+}
+
+/// Which way a cross-reference points, according to its signal.
+///
+/// A `Supra` cross-reference ("*supra* note 12," "above n 12," or "note 12
+/// above") refers to something earlier in the document, and an `Infra` one
+/// ("*infra* note 40" or "note 40 below") to something later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Supra,
+    Infra,
+}
+
+impl Direction {
+    /// The direction's signal, for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Supra => "supra",
+            Direction::Infra => "infra",
+        }
+    }
+}
+
+lazy_static! {
+    /// A signal with a direction right before a cross-reference.
+    static ref RE_DIRECTION_BEFORE: Regex =
+        Regex::new(&format!(r"\b(?i:(supra|infra|above|below)){}+$", SPACE)).unwrap();
+    /// A signal with a direction right after a cross-reference.
+    static ref RE_DIRECTION_AFTER: Regex =
+        Regex::new(&format!(r"^{}+(?i:(above|below))\b", SPACE)).unwrap();
+}
+
+/// Determine which way a match in a paragraph's text points.
+///
+/// This looks for the words "supra," "infra," "above," and "below" (in any
+/// case) right before the match and for "above" and "below" right after it,
+/// whether or not the grammar needs them as signals. A match with one of them
+/// has its direction, along with where that word is in the text. A match
+/// without any of them has no direction.
+pub fn direction(text: &str, range: &Range<usize>) -> Option<(Direction, Range<usize>)> {
+    let word = match RE_DIRECTION_BEFORE.captures(&text[..range.start]) {
+        Some(cap) => cap.get(1)?.range(),
+        None => {
+            let m = RE_DIRECTION_AFTER.captures(&text[range.end..])?.get(1)?;
+            range.end + m.start()..range.end + m.end()
+        }
+    };
+    match text[word.clone()].to_lowercase().as_str() {
+        "supra" | "above" => Some((Direction::Supra, word)),
+        _ => Some((Direction::Infra, word)),
+    }
+}
+
+/// Get the word for the other direction, written the way `word` is (*e.g.*,
+/// "Infra" for "Supra" or "below" for "above").
+///
+/// The words are the same length, so one can take the place of the other in a
+/// run without changing anything else.
+pub fn opposite(word: &str) -> Option<String> {
+    let other = match word.to_lowercase().as_str() {
+        "supra" => "infra",
+        "infra" => "supra",
+        "above" => "below",
+        "below" => "above",
+        _ => return None,
+    };
+    if word.chars().all(char::is_uppercase) {
+        Some(other.to_uppercase())
+    } else if word.starts_with(char::is_uppercase) {
+        Some(other[..1].to_uppercase() + &other[1..])
+    } else {
+        Some(other.to_string())
+    }
+}
+
+/// A form of cross-reference.
+///
+/// The pattern is a regex pattern, in which `{NOTE}` stands for a note number,
+/// `{LIST}` for a list of note numbers, `{CHAPTER}` for a chapter (*i.e.*,
+/// section) number, `{HEADING}` for the number of one of the document's
+/// headings, `{PARAGRAPH}` for the number of a numbered paragraph,
+/// `{PARAGRAPHS}` for a list of those, and `{TABLE}` and `{FIGURE}` for the
+/// number of a table or figure. Capture groups named `note`, `list`,
+/// `chapter`, `heading`, `paragraph`, `paragraphs`, `table`, or `figure` work
+/// the same way, for numbers that the placeholders do not fit. If `signal` is
+/// set, the phrase needs one of the grammar's signals in strict mode. A phrase
+/// with its own signal words (*e.g.*, "oben Fn. {NOTE}") does not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phrase {
+    pub pattern: String,
+    pub signal: bool,
+}
+
+impl Phrase {
+    /// Create a new [`Phrase`] that needs a signal.
+    pub fn new(pattern: &str) -> Phrase {
+        Phrase {
+            pattern: pattern.to_string(),
+            signal: true,
+        }
+    }
+}
+
+/// The grammar of cross-references.
+///
+/// Each of the [`Phrase`]s is one form of cross-reference. The numbers in a
+/// list are separated by the `range_separators` (*e.g.*, the en-dash in
+/// "12–14") and the `list_separators` (*e.g.*, the ", and" in "12, 15, and
+/// 17"), which are regex patterns, too. A cross-reference needs one of the
+/// `signals` in strict mode; with no signals, every match is a cross-reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grammar {
+    pub phrases: Vec<Phrase>,
+    pub range_separators: Vec<String>,
+    pub list_separators: Vec<String>,
+    pub signals: Vec<Signal>,
+}
+
+impl Default for Grammar {
+    /// An empty grammar with the usual separators.
+    ///
+    /// Ranges are separated by an en-dash (U+2013), an em-dash (U+2014), a
+    /// figure dash (U+2012), a hyphen, or a non-breaking hyphen (U+2011), with
+    /// or without spaces around it, or by "to" or "through." Other items are
+    /// separated by commas, "and", or "&" (escaped in the xml).
+    fn default() -> Grammar {
+        Grammar {
+            phrases: Vec::new(),
+            range_separators: vec![" ?[–—‒‑-] ?".to_string(), " (?:to|through) ".to_string()],
+            list_separators: vec![",? (?:and|&amp;) ".to_string(), ", ".to_string()],
+            signals: Vec::new(),
+        }
+    }
+}
+
+/// The ways a space can be written: a space, a no-break space (U+00A0), or a
+/// no-break space's character reference.
+const SPACE: &str = "(?: |\u{A0}|&#160;|&#[xX]0*[aA]0;)";
+
+/// A part of a paragraph's number: a number, a Roman numeral, or a single
+/// letter.
+const NUMBER_PART: &str = "(?:[0-9]{1,4}|[IVXLCDM]{1,8}|[ivxlcdm]{1,8}|[A-Za-z])";
+
+/// Let each literal space in a pattern match any of the ways a space can be
+/// written (see [`SPACE`]).
+///
+/// Typesetting rules often call for a no-break space (*e.g.*, after "note"), so
+/// a pattern should not have to spell out every kind of space. Spaces that are
+/// escaped or in a character class are left alone.
+fn tolerate_spaces(pattern: &str) -> String {
+    let mut output = String::new();
+    let mut escaped = false;
+    let mut class = 0;
+    for c in pattern.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => class += 1,
+            ']' if class > 0 => class -= 1,
+            ' ' if class == 0 => {
+                output.push_str(SPACE);
+                continue;
+            }
+            _ => {}
+        }
+        output.push(c);
+    }
+    output
+}
+
+/// Any number of spaces of any kind, including none and thin spaces (U+2009
+/// and U+202F).
+fn loose_space() -> String {
+    format!("(?:{}|\u{2009}|\u{202F})*", SPACE)
+}
+
+/// Let each literal space in a pattern match any number of spaces of any kind
+/// (see [`loose_space`]).
+///
+/// This is for finding near misses (*e.g.*, "notes 7 –  8"), so a quantifier
+/// after a space (as in " ?") is dropped. Spaces that are escaped or in a
+/// character class are left alone, as in [`tolerate_spaces`].
+fn loosen_spaces(pattern: &str) -> String {
+    let mut output = String::new();
+    let mut escaped = false;
+    let mut class = 0;
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => class += 1,
+            ']' if class > 0 => class -= 1,
+            ' ' if class == 0 => {
+                chars.next_if(|c| "?*+".contains(*c));
+                output.push_str(&loose_space());
+                continue;
+            }
+            _ => {}
+        }
+        output.push(c);
+    }
+    output
+}
+
+/// The kinds of numbers in a cross-reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Group {
+    Note,
+    List,
+    Chapter,
+    Heading,
+    Paragraph,
+    Paragraphs,
+    Table,
+    Figure,
+}
+
+/// A grammar compiled into regex.
+#[derive(Debug)]
+pub(crate) struct Matcher {
+    pub re: Regex,
+    pub re_separator: Regex,
+    /// The list separators alone, and the range separators alone, each
+    /// matching the whole of a separator.
+    re_list: Regex,
+    re_range: Regex,
+    signals: Vec<(Option<Regex>, Option<Regex>, bool)>,
+    /// The names of the groups around the phrases that need no signal.
+    unsignaled: Vec<String>,
+}
+
+impl Matcher {
+    /// Compile a grammar for notes numbered in `formats`.
+    ///
+    /// Note numbers can be decimal numbers, custom marks, or numbers in any of
+    /// `formats`. Chapter numbers are always decimal numbers. The numbers of
+    /// headings and paragraphs are made of the parts in [`NUMBER_PART`],
+    /// separated by periods and followed by any parts in parentheses (*e.g.*,
+    /// "II.A" or "4.2(b)"). The numbers of tables and figures are decimal
+    /// numbers. Each placeholder and number group in a phrase becomes its own
+    /// named capture group (*e.g.*, `note3`), so the phrases can be joined into
+    /// one regex. Spaces in the patterns match no-break spaces, too.
+    pub fn new(grammar: &Grammar, formats: &[NumFmt]) -> Result<Matcher, Diagnostic> {
+        Matcher::compile(grammar, formats, false)
+    }
+
+    /// Compile a grammar for notes numbered in `formats` that also matches the
+    /// near misses of its phrases.
+    ///
+    /// This is [`Matcher::new`], except that `{NOTE}` matches a list, too (as
+    /// in "note 3–5"), and the separators can have any number of spaces of any
+    /// kind in and around them (as in "notes 7 –  8").
+    pub fn loose(grammar: &Grammar, formats: &[NumFmt]) -> Result<Matcher, Diagnostic> {
+        Matcher::compile(grammar, formats, true)
+    }
+
+    /// Compile a grammar, loosely or not.
+    fn compile(grammar: &Grammar, formats: &[NumFmt], loose: bool) -> Result<Matcher, Diagnostic> {
+        if grammar.phrases.is_empty() {
+            let err_msg = "The cross-reference grammar has no phrases".to_string();
+            return Err(Diagnostic::error(Kind::Config, err_msg));
+        }
+
+        let mut patterns = vec![NumFmt::Decimal.pattern(), "[*†‡§‖]{1,9}"];
+        for format in formats {
+            if !patterns.contains(&format.pattern()) {
+                patterns.push(format.pattern());
+            }
+        }
+        let number = format!("(?:{})", patterns.join("|"));
+        // Loosely, a separator can have spaces around it, too (as in "6 ,  7").
+        let separators = |s: &[String]| -> String {
+            if loose {
+                let s: Vec<String> = s.iter().map(|s| loosen_spaces(s)).collect();
+                format!("{0}(?:{1}){0}", loose_space(), s.join("|"))
+            } else {
+                let s: Vec<String> = s.iter().map(|s| tolerate_spaces(s)).collect();
+                format!("(?:{})", s.join("|"))
+            }
+        };
+        let separator = separators(
+            &[
+                grammar.list_separators.clone(),
+                grammar.range_separators.clone(),
+            ]
+            .concat(),
+        );
+        let list = format!("{}(?:{}{})*", number, separator, number);
+        let paragraph = format!(r"{p}(?:\.{p})*\b(?:\({p}\))*", p = NUMBER_PART);
+        let paragraphs = format!("{}(?:{}{})*", paragraph, separator, paragraph);
+
+        let mut groups = 0;
+        let mut phrases = Vec::new();
+        let mut unsignaled = Vec::new();
+        for (index, phrase) in grammar.phrases.iter().enumerate() {
+            let mut pattern = tolerate_spaces(&phrase.pattern);
+            for (name, contents) in [
+                (
+                    "note",
+                    if loose {
+                        list.as_str()
+                    } else {
+                        number.as_str()
+                    },
+                ),
+                ("list", list.as_str()),
+                ("chapter", "[0-9]{1,9}"),
+                ("heading", paragraph.as_str()),
+                ("paragraphs", paragraphs.as_str()),
+                ("paragraph", paragraph.as_str()),
+                ("table", "[0-9]{1,4}"),
+                ("figure", "[0-9]{1,4}"),
+            ] {
+                let placeholder = format!("{{{}}}", name.to_uppercase());
+                let group = format!("(?P<{}>", name);
+                while let Some(i) = pattern.find(&placeholder) {
+                    groups += 1;
+                    pattern.replace_range(
+                        i..i + placeholder.len(),
+                        &format!("(?P<{}{}>{})", name, groups, contents),
+                    );
+                }
+                while let Some(i) = pattern.find(&group) {
+                    groups += 1;
+                    pattern.replace_range(i..i + group.len(), &format!("(?P<{}{}>", name, groups));
+                }
+            }
+
+            if phrase.signal {
+                phrases.push(format!("(?:{})", pattern));
+            } else {
+                let name = format!("unsignaled{}", index);
+                phrases.push(format!("(?P<{}>{})", name, pattern));
+                unsignaled.push(name);
+            }
+        }
+
+        let compile = |pattern: &str| {
+            Regex::new(pattern).map_err(|e| {
+                let err_msg = format!("Invalid cross-reference pattern {:?}: {}", pattern, e);
+                Diagnostic::error(Kind::Config, err_msg)
+            })
+        };
+
+        let mut signals = Vec::new();
+        for signal in &grammar.signals {
+            let before = match &signal.before {
+                Some(b) => Some(compile(&format!("(?:{})$", tolerate_spaces(b)))?),
+                None => None,
+            };
+            let after = match &signal.after {
+                Some(a) => Some(compile(&format!("^(?:{})", tolerate_spaces(a)))?),
+                None => None,
+            };
+            signals.push((before, after, signal.italic));
+        }
+
+        Ok(Matcher {
+            re: compile(&phrases.join("|"))?,
+            re_separator: compile(&separator)?,
+            re_list: compile(&format!("^{}$", separators(&grammar.list_separators)))?,
+            re_range: compile(&format!("^{}$", separators(&grammar.range_separators)))?,
+            signals,
+            unsignaled,
+        })
+    }
+
+    /// Get the numbers in a match, with the kind of each, in the order they
+    /// appear.
+    pub fn groups<'t>(&self, cap: &Captures<'t>) -> Vec<(regex::Match<'t>, Group)> {
+        let mut groups: Vec<(regex::Match, Group)> = self
+            .re
+            .capture_names()
+            .flatten()
+            .filter_map(|name| {
+                let group = match name.trim_end_matches(|c: char| c.is_ascii_digit()) {
+                    "note" => Group::Note,
+                    "list" => Group::List,
+                    "chapter" => Group::Chapter,
+                    "heading" => Group::Heading,
+                    "paragraph" => Group::Paragraph,
+                    "paragraphs" => Group::Paragraphs,
+                    "table" => Group::Table,
+                    "figure" => Group::Figure,
+                    _ => return None,
+                };
+                cap.name(name).map(|m| (m, group))
+            })
+            .collect();
+        groups.sort_by_key(|(m, _)| m.start());
+        groups
+    }
+
+    /// Determine whether a match in a paragraph's text has one of the
+    /// grammar's signals.
+    ///
+    /// A grammar without signals needs none, and neither does a phrase without
+    /// [`Phrase::signal`].
+    pub fn has_signal(&self, paragraph: &Paragraph, cap: &Captures) -> bool {
+        if self.signals.is_empty() || self.unsignaled.iter().any(|n| cap.name(n).is_some()) {
+            return true;
+        }
+
+        let range = cap.get(0).unwrap().range();
+        let before = &paragraph.text[..range.start];
+        let after = &paragraph.text[range.end..];
+        self.signals.iter().any(|(re_before, re_after, italic)| {
+            let before_ok = match re_before {
+                Some(re) => match re.find(before) {
+                    Some(m) if *italic => {
+                        let words = words(&m);
+                        !words.is_empty() && paragraph.is_italic(&words)
+                    }
+                    Some(_) => true,
+                    None => false,
+                },
+                None => true,
+            };
+            let after_ok = match re_after {
+                Some(re) => re.is_match(after),
+                None => true,
+            };
+            before_ok && after_ok
+        })
+    }
+
+    /// Find the words before a match in a paragraph's text that would be one of
+    /// the grammar's signals if they were italicized (*e.g.*, a plain "supra").
+    pub fn unitalicized_signal(
+        &self,
+        paragraph: &Paragraph,
+        cap: &Captures,
+    ) -> Option<Range<usize>> {
+        let range = cap.get(0).unwrap().range();
+        let before = &paragraph.text[..range.start];
+        let after = &paragraph.text[range.end..];
+        self.signals
+            .iter()
+            .find_map(|(re_before, re_after, italic)| {
+                let words = words(&re_before.as_ref()?.find(before)?);
+                let after_ok = match re_after {
+                    Some(re) => re.is_match(after),
+                    None => true,
+                };
+                if *italic && after_ok && !words.is_empty() && !paragraph.is_italic(&words) {
+                    Some(words)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Determine whether some text is one of the grammar's separators and, if
+    /// it is, whether it separates a range.
+    pub fn separator(&self, text: &str) -> Option<bool> {
+        if self.re_range.is_match(text) {
+            Some(true)
+        } else if self.re_list.is_match(text) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// Get where the words of a signal are, without the spaces around them, which
+/// need not be italicized.
+fn words(m: &regex::Match) -> Range<usize> {
+    let text = m.as_str();
+    let start = m.start() + (text.len() - text.trim_start().len());
+    let end = m.start() + text.trim_end().len();
+    start..end.max(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(style: CitationStyle, text: &str) -> Vec<String> {
+        let matcher = Matcher::new(&style.grammar(), &[NumFmt::Decimal]).unwrap();
+        matcher
+            .re
+            .find_iter(text)
+            .map(|m| m.as_str().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn preset_phrases() {
+        assert_eq!(
+            matches(
+                CitationStyle::Bluebook,
+                "supra note 1; (citing supra n.2); supra nn.3–4; Notes 5 &amp; 6; ch. 2, n. 7"
+            ),
+            ["note 1", "n.2", "nn.3–4", "Notes 5 &amp; 6", "ch. 2, n. 7"]
+        );
+        assert_eq!(
+            matches(
+                CitationStyle::Bluebook,
+                "Note 1 says so. Notes 2–3 agree; infra Part II.A; supra Section 3.2; the Parties"
+            ),
+            ["Note 1", "Notes 2–3", "Part II.A", "Section 3.2"]
+        );
+        assert_eq!(
+            matches(
+                CitationStyle::Bluebook,
+                "supra ¶ 12; infra ¶¶ 3–5 &amp; 7; Section 4.2(b) above; ¶¶ (a)"
+            ),
+            ["¶ 12", "¶¶ 3–5 &amp; 7", "Section 4.2(b)"]
+        );
+        assert_eq!(
+            matches(
+                CitationStyle::Chicago,
+                "see Table 3 below; infra Fig. 2; figure\u{A0}4; the Tables 5"
+            ),
+            ["Table 3", "Fig. 2", "figure\u{A0}4"]
+        );
+        assert_eq!(
+            matches(CitationStyle::Oscola, "Smith (n 12) 45; see nn 3–4, 6"),
+            ["n 12", "nn 3–4, 6"]
+        );
+        assert_eq!(
+            matches(
+                CitationStyle::Chicago,
+                "see note 12 above; notes 3 and 4 below"
+            ),
+            ["note 12", "notes 3 and 4"]
+        );
+        assert_eq!(
+            matches(
+                CitationStyle::Bluebook,
+                "note\u{A0}1; notes 2 – 3; notes 4—5 &amp; 6\u{2011}7; notes&#160;8 through 9"
+            ),
+            [
+                "note\u{A0}1",
+                "notes 2 – 3",
+                "notes 4—5 &amp; 6\u{2011}7",
+                "notes&#160;8 through 9"
+            ]
+        );
+        assert_eq!(tolerate_spaces(r"a [ ]\ b"), format!(r"a{}[ ]\ b", SPACE));
+        assert_eq!(CitationStyle::from_name("AGLC"), Some(CitationStyle::Aglc));
+        assert_eq!(CitationStyle::from_name("mla"), None);
+
+        let grammar = Grammar {
+            phrases: vec![Phrase::new(r"\bnote [")],
+            ..Default::default()
+        };
+        assert!(Matcher::new(&grammar, &[]).is_err());
+        assert!(Matcher::new(&Grammar::default(), &[]).is_err());
+    }
+
+    #[test]
+    fn directions() {
+        let direction_of = |text: &str, number: &str| {
+            let start = text.find(number).unwrap();
+            direction(text, &(start..start + number.len())).map(|(d, _)| d)
+        };
+        assert_eq!(
+            direction_of("See Supra note 12.", "note 12"),
+            Some(Direction::Supra)
+        );
+        assert_eq!(
+            direction_of("Smith, above&#160;n 12, 45", "n 12"),
+            Some(Direction::Supra)
+        );
+        assert_eq!(
+            direction_of("see infra\u{A0}notes 3–4", "notes 3–4"),
+            Some(Direction::Infra)
+        );
+        assert_eq!(
+            direction_of("see note 40 below.", "note 40"),
+            Some(Direction::Infra)
+        );
+        assert_eq!(direction_of("(n 12) 45", "n 12"), None);
+        assert_eq!(direction_of("the suprarenal note 12", "note 12"), None);
+        assert_eq!(
+            direction("see note 40 below.", &(4..11)),
+            Some((Direction::Infra, 12..17))
+        );
+
+        assert_eq!(opposite("supra").as_deref(), Some("infra"));
+        assert_eq!(opposite("Infra").as_deref(), Some("Supra"));
+        assert_eq!(opposite("ABOVE").as_deref(), Some("BELOW"));
+        assert_eq!(opposite("see"), None);
+    }
+}
@@ -1,10 +1,20 @@
 //! This module contains the lexer for breaking down the xml files. It
 //! identifies the chunks of potential bookmarks (for `document.xml`) and
-//! cross-references (for `footnotes.xml) as well as chunks containing
-//! everything else.
+//! cross-references (for `footnotes.xml` and `endnotes.xml`) as well as chunks
+//! containing everything else.
 
+use crate::{
+    captions::{self, CaptionKind},
+    diagnostic::{self, Diagnostic},
+    grammar::{self, Direction, Grammar, Group, Matcher},
+    numfmt::NumFmt,
+    paragraph::{self, RunProps},
+    styles::ParagraphStyle,
+    xml::{self, Kind},
+};
 use regex::Regex;
-use slog::{debug, o, trace};
+use slog::{debug, o, trace, warn};
+use std::{collections::HashMap, iter::Peekable, ops::Range};
 
 /// The lexer that works through an input string.
 ///
@@ -23,19 +33,106 @@ impl Lexer {
 /// The tokens of the input string.
 ///
 /// Tokens consist of a [`TokenType`] and contents. The contents refer to a
-/// slice of the input string.
+/// slice of the input string. Note references and notes also carry the note's
+/// id (the `w:id` attribute), which links the references in `document.xml` to
+/// the notes in `footnotes.xml` or `endnotes.xml`. A note reference with a
+/// custom mark (*e.g.*, an author's "*" footnote) carries that mark, too.
+///
+/// Cross-references carry their text as read, which differs from their
+/// contents when they are spread over several runs, and the properties of the
+/// runs they start and end in, which are needed to split those runs. A
+/// cross-reference qualified with a chapter also carries that chapter's
+/// number, and one with a signal that points one way (*e.g.*, "*supra*")
+/// carries that [`Direction`] and, if the signal's word is a token of its own,
+/// where that word starts in the input (its `signal`). And each carries the
+/// text around it (its `context`) and where it starts in the input (its
+/// `offset`), for messages about it.
 #[derive(Debug, PartialEq, Eq)]
 pub struct Token<'a> {
     pub token_type: TokenType,
     pub contents: &'a str,
+    pub id: Option<&'a str>,
+    pub mark: Option<&'a str>,
+    pub text: Option<String>,
+    pub runs: Option<RunProps<'a>>,
+    pub chapter: Option<String>,
+    pub direction: Option<Direction>,
+    pub signal: Option<usize>,
+    pub context: Option<String>,
+    pub offset: Option<usize>,
 }
 
-impl Token<'_> {
+impl<'a> Token<'a> {
     /// Creates a new [`Token`].
-    pub fn new(token_type: TokenType, contents: &str) -> Token {
+    pub fn new(token_type: TokenType, contents: &'a str) -> Token<'a> {
+        Token {
+            token_type,
+            contents,
+            id: None,
+            mark: None,
+            text: None,
+            runs: None,
+            chapter: None,
+            direction: None,
+            signal: None,
+            context: None,
+            offset: None,
+        }
+    }
+
+    /// Creates a new [`Token`] with a note id.
+    pub fn with_id(token_type: TokenType, contents: &'a str, id: &'a str) -> Token<'a> {
         Token {
             token_type,
             contents,
+            id: Some(id),
+            mark: None,
+            text: None,
+            runs: None,
+            chapter: None,
+            direction: None,
+            signal: None,
+            context: None,
+            offset: None,
+        }
+    }
+
+    /// Adds a custom mark to a [`Token`].
+    pub fn with_mark(self, mark: &'a str) -> Token<'a> {
+        Token {
+            mark: Some(mark),
+            ..self
+        }
+    }
+
+    /// Adds a cross-reference's text and the properties of the runs around it
+    /// to a [`Token`].
+    pub fn with_runs(self, text: &str, runs: RunProps<'a>, chapter: Option<&str>) -> Token<'a> {
+        Token {
+            text: Some(text.to_string()),
+            runs: Some(runs),
+            chapter: chapter.map(|c| c.to_string()),
+            ..self
+        }
+    }
+
+    /// Adds the direction of a cross-reference's signal, and where the
+    /// signal's word starts in the input, to a [`Token`].
+    pub fn with_signal(self, direction: Option<Direction>, signal: Option<usize>) -> Token<'a> {
+        Token {
+            direction,
+            signal,
+            ..self
+        }
+    }
+
+    /// Adds the text around a cross-reference, and where it starts in the
+    /// input, to a [`Token`].
+    pub fn with_context(self, context: &str, offset: usize) -> Token<'a> {
+        Token {
+            context: Some(context.to_string()),
+            offset: Some(offset),
+            ..self
         }
     }
 }
@@ -43,102 +140,357 @@ impl Token<'_> {
 /// The types of tokens in the documents.
 ///
 /// A `FootnoteRef` refers to a chunk containing the markup for a footnote
-/// reference in `document.xml`.
+/// reference in `document.xml`. An `EndnoteRef` is the same for an endnote
+/// reference.
 ///
-/// A `CrossRef` refers to a chunk containing the number referencing another
-/// footnote in `footnotes.xml`.
+/// A `Note` refers to a chunk containing the start tag of a numbered note in
+/// `footnotes.xml` or `endnotes.xml`. A `CrossRef` refers to a chunk
+/// containing the number (or custom mark) referencing another note. A
+/// `Chapter` refers to a chunk containing the number of the chapter (section)
+/// that the cross-references in the same match point into. A `HeadingRef`
+/// refers to a chunk containing the number of one of the document's headings,
+/// and a `ParagraphRef` to one containing the number of a numbered paragraph.
+/// A `TableRef` or `FigureRef` refers to a chunk containing the number of a
+/// table or figure, and a `TableCaption` or `FigureCaption` to one containing
+/// the number in the caption of a table or figure in `document.xml`. A
+/// `Signal` refers to a chunk containing the word of a cross-reference's
+/// signal that says which way it points (*e.g.*, "supra" or "below").
 ///
 /// Everything else is `Other`.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TokenType {
+    Chapter,
     CrossRef,
+    EndnoteRef,
+    FigureCaption,
+    FigureRef,
     FootnoteRef,
+    HeadingRef,
+    Note,
     Other,
+    ParagraphRef,
+    Signal,
+    TableCaption,
+    TableRef,
 }
 
+/// The complex type that the [`lex`] returns.
+///
+/// The lexer returns the tokens for `document.xml` and, if the document has
+/// them, the tokens for `footnotes.xml` and `endnotes.xml`.
+pub type LexResults<'a> = (
+    Vec<Token<'a>>,
+    Option<Vec<Token<'a>>>,
+    Option<Vec<Token<'a>>>,
+);
+
 /// The main lexer function.
 ///
-/// This is a parent function for the two separate lexers.
+/// This is a parent function for the separate lexers. The document is lexed
+/// with the ids of the note reference styles (`reference_styles`), which
+/// decide whether a custom mark in the run after a reference is part of it,
+/// and the paragraph styles that mark captions (`paragraph_styles`), and the
+/// notes are lexed with the number formats that their kind of note uses
+/// (`fn_formats` and `en_formats`). Cross-references, in the notes and in the
+/// body text, are found with the citation style's `grammar`.
+#[allow(clippy::too_many_arguments)]
 pub fn lex<'a>(
     doc_input: &'a str,
-    fn_input: &'a str,
-) -> Result<(Vec<Token<'a>>, Vec<Token<'a>>), String> {
+    fn_input: Option<&'a str>,
+    en_input: Option<&'a str>,
+    reference_styles: &[String],
+    paragraph_styles: &HashMap<String, ParagraphStyle>,
+    fn_formats: &[NumFmt],
+    en_formats: &[NumFmt],
+    grammar: &Grammar,
+) -> Result<LexResults<'a>, Diagnostic> {
     debug!(slog_scope::logger(), "Starting lexer...");
 
-    // First get the tokens from doc_input
-    let doc_lex =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "lex_doc()")), || {
-            lex_doc(doc_input)
-        }) {
-            Ok(l) => l,
-            Err(e) => return Err(e),
-        };
+    // First get the tokens from doc_input. Cross-references in the body text
+    // refer to footnotes, unless the document only has endnotes.
+    let doc_formats = match fn_input {
+        Some(_) => fn_formats,
+        None => en_formats,
+    };
+    let doc_lex = slog_scope::scope(&slog_scope::logger().new(o!("fn" => "lex_doc()")), || {
+        lex_doc(
+            doc_input,
+            reference_styles,
+            paragraph_styles,
+            doc_formats,
+            grammar,
+        )
+        .map_err(|e| e.in_part(diagnostic::DOCUMENT_PART))
+    })?;
 
     // Then get the tokens from fn_input
-    let fn_lex = match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "lex_fn()")), || {
-        lex_fn(fn_input)
-    }) {
-        Ok(l) => l,
-        Err(e) => return Err(e),
+    let fn_lex = match fn_input {
+        Some(i) => Some(slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "lex_fn()")),
+            || lex_fn(i, fn_formats, grammar).map_err(|e| e.in_part(diagnostic::FOOTNOTES_PART)),
+        )?),
+        None => None,
+    };
+
+    // And the tokens from en_input, which are lexed the same way
+    let en_lex = match en_input {
+        Some(i) => Some(slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "lex_fn()")),
+            || lex_fn(i, en_formats, grammar).map_err(|e| e.in_part(diagnostic::ENDNOTES_PART)),
+        )?),
+        None => None,
     };
 
     debug!(slog_scope::logger(), "Lexer finished.");
-    Ok((doc_lex, fn_lex))
+    Ok((doc_lex, fn_lex, en_lex))
+}
+
+/// A note reference that the lexer has found in `document.xml`.
+#[derive(Debug)]
+struct Reference<'a> {
+    token_type: TokenType,
+    id: &'a str,
+    custom: bool,
+    /// Where the reference's run starts.
+    start: usize,
 }
 
 /// Lex the contents of document.xml.
 ///
-/// This function uses regex to identify the footnote references in
-/// `document.xml`. It then uses the index of those points to create tokens of
-/// the [`TokenType`] `FootnoteRef` or `Other`.
-fn lex_doc(doc_input: &str) -> Result<Vec<Token>, String> {
+/// This function reads the xml events in `document.xml` to identify the runs
+/// with footnote and endnote references. It then uses the range of those runs
+/// to create tokens of the [`TokenType`] `FootnoteRef`, `EndnoteRef`, or
+/// `Other`. Cross-references in the body text are found the same way as in
+/// the notes (see [`cross_refs`]), with the notes' number `formats`, and
+/// become `CrossRef`, `HeadingRef`, `ParagraphRef`, `TableRef`, `FigureRef`,
+/// `Chapter`, and `Signal` tokens. The numbers in the captions of tables and
+/// figures (see [`captions::numbers`]) become `TableCaption` and
+/// `FigureCaption` tokens, and a cross-reference that overlaps one of them is
+/// skipped.
+///
+/// A reference with `w:customMarkFollows` is followed by the mark that Word
+/// displays instead of a number. That mark is usually in the same run, but it
+/// can be in the next run if that run has one of the `reference_styles` (see
+/// [`crate::styles`]). The mark is added to the token, whose contents then
+/// include both runs. A mark that is a symbol (`w:sym`) is added as an empty
+/// string.
+fn lex_doc<'a>(
+    doc_input: &'a str,
+    reference_styles: &[String],
+    paragraph_styles: &HashMap<String, ParagraphStyle>,
+    formats: &[NumFmt],
+    grammar: &Grammar,
+) -> Result<Vec<Token<'a>>, Diagnostic> {
     debug!(slog_scope::logger(), "Lexing document...");
 
     // Create a new lexer and empty vector of tokens
     let mut lexer = Lexer::new();
     let mut lex: Vec<Token> = Vec::new();
 
-    // Use regex to identify each match
-    let re = Regex::new(
-        r#"(<w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id=")([0-9]{1,9})(" /></w:r>)"#
-    ).unwrap();
-    for mat in re.find_iter(doc_input) {
-        // The file should always start with an other chunk. And this loop
-        // always ends with a new other chunk. So each loop should start by
-        // closing off an other chunk. This chunk runs from the starting index
-        // in the lexer to the beginning of the match.
-        trace!(
-            slog_scope::logger(),
-            "Pushing token type {:?} containing {:?}",
-            TokenType::Other,
-            &doc_input[lexer.start..mat.start()],
-        );
-        lex.push(Token::new(
-            TokenType::Other,
-            &doc_input[lexer.start..mat.start()],
-        ));
-
-        // The other chunk is followed by either a footnote reference or the end
-        // of the string. Unless the other chunk ends the string, the next chunk
-        // is a footnote reference. It runs from the start of the match to the
-        // end of the match.
-        trace!(
-            slog_scope::logger(),
-            "Pushing token type {:?} containing {:?}",
-            TokenType::FootnoteRef,
-            &doc_input[mat.start()..mat.end()],
-        );
-        lex.push(Token::new(
-            TokenType::FootnoteRef,
-            &doc_input[mat.start()..mat.end()],
-        ));
+    // The numbers in the captions, and the cross-references in the body text
+    // that are not in those numbers
+    let mut pieces: Vec<Piece> = captions::numbers(doc_input, paragraph_styles)?
+        .into_iter()
+        .map(|n| Piece {
+            token_type: match n.kind {
+                CaptionKind::Table => TokenType::TableCaption,
+                CaptionKind::Figure => TokenType::FigureCaption,
+            },
+            range: n.range,
+            text: n.text,
+            runs: n.runs,
+            chapter: None,
+            direction: None,
+            signal: None,
+            context: String::new(),
+        })
+        .collect();
+    for piece in cross_refs(doc_input, formats, grammar)? {
+        let range = &piece.range;
+        if pieces
+            .iter()
+            .any(|c| c.range.start < range.end && range.start < c.range.end)
+        {
+            debug!(
+                slog_scope::logger(),
+                "Skipping {:?}, which overlaps a caption's number", piece.text
+            );
+            continue;
+        }
+        pieces.push(piece);
+    }
+    pieces.sort_by_key(|p| p.range.start);
+    let mut pieces = pieces.into_iter().peekable();
+
+    // The current run: where it starts, its style, the note reference (if
+    // any) in it, and its text
+    let mut run_start: Option<usize> = None;
+    let mut style: Option<&str> = None;
+    let mut reference: Option<Reference> = None;
+    let mut mark: Option<&str> = None;
+    let mut text_start: Option<usize> = None;
 
-        // Set the new starting index.
-        lexer.start = mat.end();
+    // A custom-mark reference whose mark might be in the next run, and where
+    // its own run ends
+    let mut waiting: Option<(Reference, usize)> = None;
+
+    for event in xml::events(doc_input) {
+        let event = event?;
+
+        // Only the next run can have the mark.
+        if run_start.is_none()
+            && !matches!(event.kind, Kind::Text | Kind::Other)
+            && event.name != "r"
+        {
+            if let Some((r, end)) = waiting.take() {
+                push_reference(
+                    &mut lex,
+                    &mut lexer,
+                    &mut pieces,
+                    doc_input,
+                    r,
+                    end,
+                    Some(""),
+                );
+            }
+        }
+
+        match event.kind {
+            Kind::Start if event.name == "r" => {
+                run_start = Some(event.range.start);
+                style = None;
+                reference = None;
+                mark = None;
+            }
+            Kind::Empty if event.name == "rStyle" => style = event.attribute("val"),
+            Kind::Start | Kind::Empty
+                if event.name == "footnoteReference" || event.name == "endnoteReference" =>
+            {
+                if let Some((r, end)) = waiting.take() {
+                    push_reference(
+                        &mut lex,
+                        &mut lexer,
+                        &mut pieces,
+                        doc_input,
+                        r,
+                        end,
+                        Some(""),
+                    );
+                }
+
+                let token_type = match event.name {
+                    "endnoteReference" => TokenType::EndnoteRef,
+                    _ => TokenType::FootnoteRef,
+                };
+                let id = match event.attribute("id") {
+                    Some(i) => i,
+                    None => {
+                        warn!(
+                            slog_scope::logger(),
+                            "Skipping note reference without an id: {:?}",
+                            &doc_input[event.range.clone()]
+                        );
+                        continue;
+                    }
+                };
+                let custom = matches!(
+                    event.attribute("customMarkFollows"),
+                    Some("1" | "true" | "on")
+                );
+                reference = run_start.map(|start| Reference {
+                    token_type,
+                    id,
+                    custom,
+                    start,
+                });
+            }
+            Kind::Start if event.name == "t" && run_start.is_some() => {
+                text_start = Some(event.range.end)
+            }
+            Kind::End if event.name == "t" => {
+                if let Some(start) = text_start.take() {
+                    mark.get_or_insert(&doc_input[start..event.range.start]);
+                }
+            }
+            Kind::Empty if event.name == "sym" => {
+                mark.get_or_insert("");
+            }
+            Kind::End if event.name == "r" => {
+                run_start = None;
+
+                if let Some(r) = reference.take() {
+                    if r.custom && mark.is_none() {
+                        // The mark might be in the next run.
+                        waiting = Some((r, event.range.end));
+                    } else {
+                        let mark = if r.custom { mark } else { None };
+                        push_reference(
+                            &mut lex,
+                            &mut lexer,
+                            &mut pieces,
+                            doc_input,
+                            r,
+                            event.range.end,
+                            mark,
+                        );
+                    }
+                } else if let Some((r, end)) = waiting.take() {
+                    let styled = style.is_some_and(|s| reference_styles.iter().any(|r| r == s));
+                    match mark {
+                        Some(m) if styled => {
+                            trace!(
+                                slog_scope::logger(),
+                                "Found the mark {:?} for note {} in the next run",
+                                m,
+                                r.id
+                            );
+                            push_reference(
+                                &mut lex,
+                                &mut lexer,
+                                &mut pieces,
+                                doc_input,
+                                r,
+                                event.range.end,
+                                Some(m),
+                            );
+                        }
+                        _ => push_reference(
+                            &mut lex,
+                            &mut lexer,
+                            &mut pieces,
+                            doc_input,
+                            r,
+                            end,
+                            Some(""),
+                        ),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((r, end)) = waiting.take() {
+        push_reference(
+            &mut lex,
+            &mut lexer,
+            &mut pieces,
+            doc_input,
+            r,
+            end,
+            Some(""),
+        );
     }
 
-    // After the last footnote-reference chunk is processed, there should still
-    // be an other chunk. This closes that last chunk off.
+    // After the last note-reference chunk is processed, there should still
+    // be an other chunk, which might have more cross-references in it. This
+    // closes that last chunk off.
+    push_pieces(
+        &mut lex,
+        &mut lexer,
+        &mut pieces,
+        doc_input,
+        doc_input.len(),
+    );
     trace!(
         slog_scope::logger(),
         "Pushing token type {:?} containing {:?}",
@@ -151,153 +503,751 @@ fn lex_doc(doc_input: &str) -> Result<Vec<Token>, String> {
     Ok(lex)
 }
 
-/// Lex the contents of `footnotes.xml`.
+/// Push a note reference that ends at `end`, along with its custom mark (if
+/// any), after the cross-references before it.
+fn push_reference<'a, I>(
+    lex: &mut Vec<Token<'a>>,
+    lexer: &mut Lexer,
+    pieces: &mut Peekable<I>,
+    doc_input: &'a str,
+    reference: Reference<'a>,
+    end: usize,
+    mark: Option<&'a str>,
+) where
+    I: Iterator<Item = Piece<'a>>,
+{
+    // Any cross-references before the note reference come first.
+    push_pieces(lex, lexer, pieces, doc_input, reference.start);
+
+    // The file should always start with an other chunk. And the lexer always
+    // ends with a new other chunk. So each note reference should start by
+    // closing off an other chunk. This chunk runs from the starting index in
+    // the lexer to the beginning of the reference's run.
+    trace!(
+        slog_scope::logger(),
+        "Pushing token type {:?} containing {:?}",
+        TokenType::Other,
+        &doc_input[lexer.start..reference.start],
+    );
+    lex.push(Token::new(
+        TokenType::Other,
+        &doc_input[lexer.start..reference.start],
+    ));
+
+    // The other chunk is followed by the note reference's run.
+    trace!(
+        slog_scope::logger(),
+        "Pushing token type {:?} with id {} containing {:?}",
+        reference.token_type,
+        reference.id,
+        &doc_input[reference.start..end],
+    );
+    let token = Token::with_id(
+        reference.token_type,
+        &doc_input[reference.start..end],
+        reference.id,
+    );
+    match mark {
+        Some(m) => lex.push(token.with_mark(m)),
+        None => lex.push(token),
+    }
+
+    // Set the new starting index.
+    lexer.start = end;
+}
+
+/// Lex the contents of `footnotes.xml` or `endnotes.xml`.
 ///
-/// This function lexes the `footnotes.xml` contents into `CrossRef` and `Other`
-/// tokens. It is probably a little brittle. It uses regex to find the
-/// cross-references and more regex when there is a range of numbers. It then
-/// relies on index offsets to identify the numbers.
+/// This function lexes the notes' contents into `Note`, `CrossRef`,
+/// `HeadingRef`, `ParagraphRef`, `TableRef`, `FigureRef`, `Chapter`,
+/// `Signal`, and `Other` tokens. The notes are found from the xml events (see
+/// [`notes`]), and the cross-references with the citation style's `grammar`
+/// (see [`cross_refs`]). Everything between them is "other."
 ///
 /// The file should always start with an "other" chunk. And the loop always ends
 /// with a new "other" chunk. So each loop should start by closing off an
 /// "other."
-fn lex_fn(input: &str) -> Result<Vec<Token>, String> {
+fn lex_fn<'a>(
+    input: &'a str,
+    formats: &[NumFmt],
+    grammar: &Grammar,
+) -> Result<Vec<Token<'a>>, Diagnostic> {
     // Create a new lexer and empty vector of tokens
     let mut lexer = Lexer::new();
     let mut lex: Vec<Token> = Vec::new();
 
-    // Use regex to identify each match.
-    //
-    // The first group `((>note )([0-9]{1,9}))` captures references to single
-    // footnotes. It should have three total capture groups. The second group
-    // `((>notes )([0-9]{1,9})(-|–)([0-9]{1,9}))` captures references to a range
-    // of footnotes. It should have five total capture groups.
-    let re =
-        Regex::new(r#"((>note )([0-9]{1,9}))|((>notes )([0-9]{1,9})(-|–)([0-9]{1,9}))"#).unwrap();
-
-    // This regex finds numbers within a range.
-    let re_range = Regex::new(r#"([0-9]{1,9})(-|–)([0-9]{1,9})"#).unwrap();
-
-    // Iterate over the matches groups
-    for mat in re.find_iter(input) {
-        // Determine whether the match is to a single footnote or a range. Note,
-        // a hyphen is in the first conditional, an en-dash (U+2013) is in the
-        // second.
-        if mat.as_str().contains('-') || mat.as_str().contains('–') {
-            // Push the precedeing "other" chunk, which goes from the lexer's
-            // current starting index to seven spaces after the beginning of the
-            // match.
-            trace!(
-                slog_scope::logger(),
-                "Pushing token type {:?} containing {:?}",
-                TokenType::Other,
-                &input[lexer.start..mat.start() + 7],
-            );
-            lex.push(Token::new(
-                TokenType::Other,
-                &input[lexer.start..mat.start() + 7],
-            ));
-
-            // Find the two numbers in the string.
-            let range = re_range.captures(mat.as_str()).unwrap();
-
-            // Then get their indexes. The first number starts at mat.start() +
-            // 7 and ends at mat.start() + 7 + the length of the number
-            let first_digit = (mat.start() + 7, mat.start() + 7 + range[1].len());
-            // Then the range indicator, which should be an en-dash. I also
-            // account for hyphens.
-            let dash = (first_digit.1, first_digit.1 + range[2].len());
-            // Then the second digit, which follows the range indicator and goes
-            // to the end of that capture.
-            let second_digit = (dash.1, dash.1 + range[3].len());
-
-            // Then push the first number, the range indicator, and the second
-            // number
-            trace!(
-                slog_scope::logger(),
-                "Pushing token type {:?} containing {}",
-                TokenType::CrossRef,
-                &input[first_digit.0..first_digit.1],
-            );
-            lex.push(Token::new(
-                TokenType::CrossRef,
-                &input[first_digit.0..first_digit.1],
-            ));
+    // The notes come before the paragraphs in them
+    let mut notes = notes(input)?.into_iter().peekable();
 
-            trace!(
-                slog_scope::logger(),
-                "Pushing token type {:?} containing {}",
-                TokenType::Other,
-                &input[dash.0..dash.1],
-            );
-            lex.push(Token::new(TokenType::Other, &input[dash.0..dash.1]));
+    for piece in cross_refs(input, formats, grammar)? {
+        // Push the notes that start before the number, and then the number
+        push_notes(&mut lex, &mut lexer, &mut notes, input, piece.range.start);
+        push_piece(&mut lex, &mut lexer, input, piece);
+    }
 
-            trace!(
-                slog_scope::logger(),
-                "Pushing token type {:?} containing {}",
-                TokenType::CrossRef,
-                &input[second_digit.0..second_digit.1],
-            );
-            lex.push(Token::new(
-                TokenType::CrossRef,
-                &input[second_digit.0..second_digit.1],
-            ));
-
-            // Set the new starting index
-            lexer.start = mat.end();
-        } else {
-            // Push the precedeing "other" chunk, which goes from the lexer's
-            // current starting index to six spaces after the beginning of the
-            // match.
-            trace!(
-                slog_scope::logger(),
-                "Pushing token type {:?} containing {:?}",
-                TokenType::Other,
-                &input[lexer.start..mat.start() + 6],
-            );
-            lex.push(Token::new(
-                TokenType::Other,
-                &input[lexer.start..mat.start() + 6],
-            ));
-
-            // If there's no range of cross-references, then the "other" chunk
-            // is followed by either a cross-reference or the end of the string.
-            // Unless the "other" chunk ends the string, the next chunk is a
-            // cross reference. It consists only of the number and thus runs
-            // from six after the start of the match to the end of the match.
-            trace!(
+    // After the last cross-reference chunk is processed, there should still be
+    // one last "other" chunk, which might have more notes in it. Close that
+    // last chunk off.
+    push_notes(&mut lex, &mut lexer, &mut notes, input, input.len());
+    push_token(&mut lex, TokenType::Other, &input[lexer.start..]);
+
+    debug!(slog_scope::logger(), "Note lexing finished.");
+    Ok(lex)
+}
+
+/// A number in a cross-reference (or the chapter that qualifies it) that the
+/// lexer has found.
+///
+/// This has the number's range in the input, its text as read, the
+/// properties of the runs it starts and ends in, the chapter (if any), the
+/// direction of the cross-reference's signal (if any) and where the signal's
+/// word starts in the input, and the text around the cross-reference (see
+/// [`context`]).
+#[derive(Debug)]
+struct Piece<'a> {
+    token_type: TokenType,
+    range: Range<usize>,
+    text: String,
+    runs: RunProps<'a>,
+    chapter: Option<String>,
+    direction: Option<Direction>,
+    signal: Option<usize>,
+    context: String,
+}
+
+/// The most characters of a paragraph's text on either side of a
+/// cross-reference in its context.
+const CONTEXT_CHARS: usize = 30;
+
+/// Find the cross-references in an input.
+///
+/// The cross-references are found with regex in the text of each paragraph
+/// (see [`paragraph::paragraphs`]), so that a cross-reference is found even
+/// when Word has split it over several runs. The forms of cross-reference come
+/// from the citation style's `grammar` (see [`Grammar`]), which has capture
+/// groups for the numbers in each form, and each of those numbers becomes its
+/// own [`Piece`].
+///
+/// If the grammar has signals, a match must have one of them (*e.g.*, an
+/// italicized "supra" or "infra" and a space right before it). That keeps
+/// other notes that happen to be mentioned in a note (*e.g.*, "the Advisory
+/// Committee's note 5") from becoming cross-references. Otherwise, every match
+/// is a cross-reference.
+///
+/// The pieces are returned in the order they appear in the input.
+fn cross_refs<'a>(
+    input: &'a str,
+    formats: &[NumFmt],
+    grammar: &Grammar,
+) -> Result<Vec<Piece<'a>>, Diagnostic> {
+    // Compile the grammar, which identifies each match with regex.
+    let matcher = Matcher::new(grammar, formats)?;
+    let mut pieces: Vec<Piece> = Vec::new();
+
+    // Iterate over the matches in each paragraph
+    for paragraph in paragraph::paragraphs(input)? {
+        let first = pieces.len();
+        for cap in matcher.re.captures_iter(&paragraph.text) {
+            let whole = cap.get(0).unwrap();
+            if !matcher.has_signal(&paragraph, &cap) {
+                trace!(
+                    slog_scope::logger(),
+                    "Skipping {:?}, which does not have a signal",
+                    whole.as_str()
+                );
+                continue;
+            }
+
+            // Collect the numbers in this match, in the order they appear.
+            let context = context(&paragraph.text, &whole.range());
+            let mut chapter = None;
+            let mut numbers: Vec<(Range<usize>, TokenType)> = Vec::new();
+            for (m, group) in matcher.groups(&cap) {
+                match group {
+                    Group::Chapter => {
+                        chapter = Some(m.as_str());
+                        numbers.push((m.range(), TokenType::Chapter));
+                    }
+                    // Skip matches that only look like numbers (*e.g.*, "note
+                    // that" in a document that numbers notes with letters).
+                    Group::Note if is_label(m.as_str(), formats) => {
+                        numbers.push((m.range(), TokenType::CrossRef));
+                    }
+                    Group::Note => {}
+                    Group::List => {
+                        let is_label = |n: &str| is_label(n, formats);
+                        for number in split_list(m, &matcher.re_separator, is_label) {
+                            numbers.push((number, TokenType::CrossRef));
+                        }
+                    }
+                    Group::Heading => numbers.push((m.range(), TokenType::HeadingRef)),
+                    Group::Paragraph => numbers.push((m.range(), TokenType::ParagraphRef)),
+                    Group::Paragraphs => {
+                        for number in split_list(m, &matcher.re_separator, |_| true) {
+                            numbers.push((number, TokenType::ParagraphRef));
+                        }
+                    }
+                    Group::Table => numbers.push((m.range(), TokenType::TableRef)),
+                    Group::Figure => numbers.push((m.range(), TokenType::FigureRef)),
+                }
+            }
+
+            if !numbers.iter().any(|(_, t)| *t != TokenType::Chapter) {
+                trace!(
+                    slog_scope::logger(),
+                    "Skipping {:?}, which is not a cross-reference",
+                    whole.as_str()
+                );
+                continue;
+            }
+
+            // The word of a signal that points one way becomes a piece of its
+            // own, so that it can be changed if the cross-reference points the
+            // other way. Only a word within a single run can be (and a word
+            // after one match might come before the next).
+            let (direction, signal) = match grammar::direction(&paragraph.text, &whole.range()) {
+                Some((d, word))
+                    if numbers.iter().any(|(_, t)| *t == TokenType::CrossRef)
+                        && paragraph.input_range(&word).len() == word.len() =>
+                {
+                    let range = paragraph.input_range(&word);
+                    if !pieces[first..].iter().any(|p| p.range == range) {
+                        pieces.push(Piece {
+                            token_type: TokenType::Signal,
+                            range: range.clone(),
+                            text: paragraph.text[word.clone()].to_string(),
+                            runs: paragraph.run_props(&word),
+                            chapter: None,
+                            direction: Some(d),
+                            signal: None,
+                            context: context.clone(),
+                        });
+                    }
+                    (Some(d), Some(range.start))
+                }
+                Some((d, _)) => (Some(d), None),
+                None => (None, None),
+            };
+
+            for (range, token_type) in numbers {
+                // Find the number in the input. It might be spread over
+                // several runs, but only plain runs can be split.
+                if !paragraph.is_splittable(&range) {
+                    warn!(
+                        slog_scope::logger(),
+                        "Skipping {:?}, which is spread over markup that cannot be split",
+                        &paragraph.text[range]
+                    );
+                    continue;
+                }
+
+                pieces.push(Piece {
+                    token_type,
+                    range: paragraph.input_range(&range),
+                    text: paragraph.text[range.clone()].to_string(),
+                    runs: paragraph.run_props(&range),
+                    chapter: chapter.map(String::from),
+                    direction,
+                    signal,
+                    context: context.clone(),
+                });
+            }
+        }
+    }
+
+    // A paragraph in a text box ends before the paragraph around it.
+    pieces.sort_by_key(|p| p.range.start);
+    Ok(pieces)
+}
+
+/// Get the text around a match in a paragraph's text.
+///
+/// This is the match plus up to [`CONTEXT_CHARS`] characters on either side,
+/// with an ellipsis where the paragraph's text is cut off.
+pub fn context(text: &str, range: &Range<usize>) -> String {
+    let start = text[..range.start]
+        .char_indices()
+        .rev()
+        .nth(CONTEXT_CHARS - 1)
+        .map_or(0, |(i, _)| i);
+    let end = text[range.end..]
+        .char_indices()
+        .nth(CONTEXT_CHARS)
+        .map_or(text.len(), |(i, _)| range.end + i);
+    format!(
+        "{}{}{}",
+        if start > 0 { "…" } else { "" },
+        &text[start..end],
+        if end < text.len() { "…" } else { "" }
+    )
+}
+
+/// Push a number in a cross-reference, preceded by the "other" chunk before
+/// it.
+fn push_piece<'a>(lex: &mut Vec<Token<'a>>, lexer: &mut Lexer, input: &'a str, piece: Piece<'a>) {
+    // Push the preceding "other" chunk, which goes from the lexer's current
+    // starting index to the beginning of the number.
+    push_token(
+        lex,
+        TokenType::Other,
+        &input[lexer.start..piece.range.start],
+    );
+
+    // Then push the number itself.
+    let contents = &input[piece.range.clone()];
+    if piece.token_type != TokenType::Chapter {
+        trace!(
+            slog_scope::logger(),
+            "Pushing token type {:?} containing {:?}",
+            piece.token_type,
+            contents,
+        );
+        lex.push(
+            Token::new(piece.token_type, contents)
+                .with_runs(&piece.text, piece.runs, piece.chapter.as_deref())
+                .with_signal(piece.direction, piece.signal)
+                .with_context(&piece.context, piece.range.start),
+        );
+    } else {
+        push_token(lex, piece.token_type, contents);
+    }
+
+    // Set the new starting index
+    lexer.start = piece.range.end;
+}
+
+/// Push the cross-references that come before a point in `document.xml`.
+///
+/// A cross-reference that overlaps a note reference's run (*e.g.*, a custom
+/// mark that happens to look like a note number) is skipped, so that the run
+/// is left whole.
+fn push_pieces<'a, I>(
+    lex: &mut Vec<Token<'a>>,
+    lexer: &mut Lexer,
+    pieces: &mut Peekable<I>,
+    input: &'a str,
+    end: usize,
+) where
+    I: Iterator<Item = Piece<'a>>,
+{
+    while let Some(piece) = pieces.next_if(|p| p.range.start < end) {
+        if piece.range.start < lexer.start || piece.range.end > end {
+            debug!(
                 slog_scope::logger(),
-                "Pushing token type {:?} containing {:?}",
-                TokenType::CrossRef,
-                &input[mat.start() + 5..mat.end()],
+                "Skipping {:?}, which overlaps a note reference", piece.text
             );
-            lex.push(Token::new(
-                TokenType::CrossRef,
-                &input[mat.start() + 6..mat.end()],
-            ));
+            continue;
+        }
+        push_piece(lex, lexer, input, piece);
+    }
+}
+
+/// Find the numbered notes in `footnotes.xml` or `endnotes.xml`.
+///
+/// This returns the range of each note's start tag and the note's id.
+/// Separators and continuation notices (notes with a `w:type` other than
+/// "normal") are not numbered, so they are skipped.
+pub fn notes(input: &str) -> Result<Vec<(Range<usize>, &str)>, Diagnostic> {
+    let mut notes = Vec::new();
+
+    for event in xml::events(input) {
+        let event = event?;
+        if event.kind != Kind::Start || !(event.name == "footnote" || event.name == "endnote") {
+            continue;
+        }
 
-            // Set the new starting index
-            lexer.start = mat.end();
+        let id = match event.attribute("id") {
+            Some(i) => i,
+            None => continue,
+        };
+        match event.attribute("type") {
+            Some(t) if t != "normal" => {
+                trace!(slog_scope::logger(), "Skipping {} note with id {}", t, id);
+            }
+            _ => notes.push((event.range, id)),
         }
     }
 
-    // After the last cross-reference chunk is processed, there should still be
-    // one last "other" chunk. Close that last chunk off.
+    Ok(notes)
+}
+
+/// Push the notes that start before a point in the input.
+///
+/// Each note's start tag becomes its own token, preceded by the "other" chunk
+/// before it.
+fn push_notes<'a, I>(
+    lex: &mut Vec<Token<'a>>,
+    lexer: &mut Lexer,
+    notes: &mut Peekable<I>,
+    input: &'a str,
+    end: usize,
+) where
+    I: Iterator<Item = (Range<usize>, &'a str)>,
+{
+    while let Some((range, id)) = notes.next_if(|(r, _)| r.start < end) {
+        push_token(lex, TokenType::Other, &input[lexer.start..range.start]);
+        trace!(
+            slog_scope::logger(),
+            "Pushing token type {:?} with id {} containing {:?}",
+            TokenType::Note,
+            id,
+            &input[range.clone()],
+        );
+        lex.push(Token::with_id(TokenType::Note, &input[range.clone()], id));
+        lexer.start = range.end;
+    }
+}
+
+/// Split a list of notes (or paragraphs) into its numbers, returning their
+/// ranges in the input.
+///
+/// The list ends at the first thing that is not actually a number (*e.g.*, the
+/// "at" in "notes 12, at 5" in a document that numbers notes with letters).
+fn split_list<F>(list: regex::Match, re_separator: &Regex, is_number: F) -> Vec<Range<usize>>
+where
+    F: Fn(&str) -> bool,
+{
+    let text = list.as_str();
+    let mut numbers = Vec::new();
+    let mut start = 0;
+    for separator in re_separator.find_iter(text) {
+        numbers.push(start..separator.start());
+        start = separator.end();
+    }
+    numbers.push(start..text.len());
+
+    numbers
+        .into_iter()
+        .take_while(|n| is_number(&text[n.clone()]))
+        .map(|n| list.start() + n.start..list.start() + n.end)
+        .collect()
+}
+
+/// Determine whether some text is a note number or custom mark.
+fn is_label(text: &str, formats: &[NumFmt]) -> bool {
+    text.parse::<u32>().is_ok()
+        || text.chars().all(|c| "*†‡§‖".contains(c))
+        || formats.iter().any(|f| f.parse(text).is_some())
+}
+
+/// Push a new token.
+fn push_token<'a>(lex: &mut Vec<Token<'a>>, token_type: TokenType, contents: &'a str) {
     trace!(
         slog_scope::logger(),
         "Pushing token type {:?} containing {:?}",
-        TokenType::Other,
-        &input[lexer.start..],
+        token_type,
+        contents,
     );
-    lex.push(Token::new(TokenType::Other, &input[lexer.start..]));
-
-    debug!(slog_scope::logger(), "Footnote lexing finished.");
-    Ok(lex)
+    lex.push(Token::new(token_type, contents));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::grammar::CitationStyle;
+
+    /// The default grammar.
+    fn bluebook() -> Grammar {
+        CitationStyle::Bluebook.grammar()
+    }
+
+    /// The default grammar without signals.
+    fn lenient() -> Grammar {
+        Grammar {
+            signals: Vec::new(),
+            ..bluebook()
+        }
+    }
+
+    /// The usual reference styles.
+    fn styles() -> Vec<String> {
+        vec![
+            "FootnoteReference".to_string(),
+            "EndnoteReference".to_string(),
+        ]
+    }
+
+    #[test]
+    fn note_refs() {
+        let input = r#"<w:p><w:r><w:t>Text.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="20" /></w:r><w:r><w:rPr><w:rStyle w:val="EndnoteReference" /></w:rPr><w:endnoteReference w:id="2" /></w:r></w:p>"#;
+
+        let tokens = lex_doc(
+            input,
+            &styles(),
+            &HashMap::new(),
+            &[NumFmt::Decimal],
+            &bluebook(),
+        )
+        .unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[0].token_type, TokenType::Other);
+        assert_eq!(tokens[1].token_type, TokenType::FootnoteRef);
+        assert_eq!(tokens[2].token_type, TokenType::Other);
+        assert_eq!(tokens[3].token_type, TokenType::EndnoteRef);
+        assert_eq!(tokens[4].token_type, TokenType::Other);
+
+        assert_eq!(
+            tokens[3].contents,
+            r#"<w:r><w:rPr><w:rStyle w:val="EndnoteReference" /></w:rPr><w:endnoteReference w:id="2" /></w:r>"#
+        );
+        assert_eq!(tokens[1].id, Some("20"));
+        assert_eq!(tokens[3].id, Some("2"));
+        assert_eq!(tokens[4].contents, "</w:p>");
+    }
+
+    #[test]
+    fn other_serializations() {
+        // As Word writes it, with another prefix, attributes in another order,
+        // and more run properties.
+        let input = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<x:document xmlns:x="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><x:body><x:p><x:r w:rsidRPr="00C1"><x:rPr><x:rStyle x:val="FootnoteReference"/><x:vertAlign x:val="superscript"/></x:rPr><x:footnoteReference x:id="4" x:customMarkFollows="true"/><x:t>†</x:t></x:r><x:r><x:footnoteReference x:id="5"/></x:r></x:p></x:body></x:document>"#;
+
+        let tokens = lex_doc(
+            input,
+            &styles(),
+            &HashMap::new(),
+            &[NumFmt::Decimal],
+            &bluebook(),
+        )
+        .unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[1].token_type, TokenType::FootnoteRef);
+        assert_eq!(tokens[1].id, Some("4"));
+        assert_eq!(tokens[1].mark, Some("†"));
+        assert!(tokens[1].contents.starts_with(r#"<x:r w:rsidRPr="00C1">"#));
+        assert!(tokens[1].contents.ends_with("</x:r>"));
+        assert_eq!(
+            tokens[3].contents,
+            r#"<x:r><x:footnoteReference x:id="5"/></x:r>"#
+        );
+        assert_eq!(tokens[3].mark, None);
+
+        let input = r#"<x:footnotes><x:footnote x:type="separator" x:id="-1"><x:p><x:r><x:separator/></x:r></x:p></x:footnote><x:footnote x:id="4"><x:p><x:r><x:t xml:space="preserve">See note 1.</x:t></x:r></x:p></x:footnote></x:footnotes>"#;
+
+        let tokens = lex_fn(input, &[NumFmt::Decimal], &lenient()).unwrap();
+        let types: Vec<&TokenType> = tokens.iter().map(|t| &t.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                &TokenType::Other,
+                &TokenType::Note,
+                &TokenType::Other,
+                &TokenType::CrossRef,
+                &TokenType::Other,
+            ]
+        );
+        assert_eq!(tokens[1].id, Some("4"));
+    }
+
+    #[test]
+    fn strict_refs() {
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">Quoting the court's note 3; </w:t></w:r><w:r><w:rPr><w:i w:val="1"/></w:rPr><w:t>supra</w:t></w:r><w:r><w:t xml:space="preserve"> note 1; supra note 2; </w:t></w:r><w:r><w:rPr><w:i/></w:rPr><w:t>infra</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:i w:val="0"/></w:rPr><w:t>notes 4–5.</w:t></w:r></w:p>"#;
+        let refs = |strict| -> Vec<&str> {
+            let grammar = if strict { bluebook() } else { lenient() };
+            lex_fn(input, &[NumFmt::Decimal], &grammar)
+                .unwrap()
+                .into_iter()
+                .filter(|t| t.token_type == TokenType::CrossRef)
+                .map(|t| t.contents)
+                .collect()
+        };
+
+        assert_eq!(refs(true), ["1", "4", "5"]);
+        assert_eq!(refs(false), ["3", "1", "2", "4", "5"]);
+    }
+
+    #[test]
+    fn citation_styles() {
+        fn refs(input: &str, style: CitationStyle) -> Vec<&str> {
+            lex_fn(input, &[NumFmt::Decimal], &style.grammar())
+                .unwrap()
+                .into_iter()
+                .filter(|t| t.token_type == TokenType::CrossRef)
+                .map(|t| t.contents)
+                .collect()
+        }
+
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">Smith (n 3) 45; see nn 4–5; Jones n 6.</w:t></w:r></w:p>"#;
+        assert_eq!(refs(input, CitationStyle::Oscola), ["3", "4", "5"]);
+
+        let input =
+            r#"<w:p><w:r><w:t xml:space="preserve">Jones, above n 6; n 7.</w:t></w:r></w:p>"#;
+        assert_eq!(refs(input, CitationStyle::Aglc), ["6"]);
+
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">See note 3 above and notes 4–5 below, but not note 6.</w:t></w:r></w:p>"#;
+        assert_eq!(refs(input, CitationStyle::Chicago), ["3", "4", "5"]);
+
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">Notes 1–2 agree. (citing Doe, </w:t></w:r><w:r><w:rPr><w:i/></w:rPr><w:t>supra</w:t></w:r><w:r><w:t xml:space="preserve"> nn.3, 4); the court's n.5.</w:t></w:r></w:p>"#;
+        assert_eq!(refs(input, CitationStyle::Bluebook), ["3", "4"]);
+
+        // A period after an abbreviation is not the end of a sentence, so a
+        // "Note" after it is not a cross-reference without a signal.
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">Id. Note 1 to the Rule. See Unif. Prob. Code. Note 12 explains. Notes 2–3 above.</w:t></w:r></w:p>"#;
+        assert_eq!(refs(input, CitationStyle::Bluebook), ["2", "3"]);
+    }
+
+    #[test]
+    fn typographic_variants() {
+        let input = "<w:p><w:r><w:t xml:space=\"preserve\">notes\u{A0}1 – 2; notes 3</w:t><w:noBreakHyphen/><w:t xml:space=\"preserve\">4; notes 5 to 6.</w:t></w:r></w:p>";
+
+        let tokens = lex_fn(input, &[NumFmt::Decimal], &lenient()).unwrap();
+        let contents: Vec<&str> = tokens.iter().map(|t| t.contents).collect();
+        assert_eq!(
+            contents,
+            [
+                "<w:p><w:r><w:t xml:space=\"preserve\">notes\u{A0}",
+                "1",
+                " – ",
+                "2",
+                "; notes ",
+                "3",
+                "</w:t><w:noBreakHyphen/><w:t xml:space=\"preserve\">",
+                "4",
+                "; notes ",
+                "5",
+                " to ",
+                "6",
+                ".</w:t></w:r></w:p>",
+            ]
+        );
+    }
+
+    #[test]
+    fn body_refs() {
+        let input = r#"<w:body><w:p><w:r><w:t xml:space="preserve">As discussed </w:t></w:r><w:r><w:rPr><w:i/></w:rPr><w:t>infra</w:t></w:r><w:r><w:t xml:space="preserve"> note 2.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference"/></w:rPr><w:footnoteReference w:id="1"/></w:r></w:p><w:p><w:r><w:t xml:space="preserve">Quoting note </w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference"/></w:rPr><w:footnoteReference w:customMarkFollows="1" w:id="2"/><w:t>*</w:t></w:r></w:p></w:body>"#;
+
+        let tokens = lex_doc(
+            input,
+            &styles(),
+            &HashMap::new(),
+            &[NumFmt::Decimal],
+            &lenient(),
+        )
+        .unwrap();
+        let types: Vec<&TokenType> = tokens.iter().map(|t| &t.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                &TokenType::Other,
+                &TokenType::Signal,
+                &TokenType::Other,
+                &TokenType::CrossRef,
+                &TokenType::Other,
+                &TokenType::FootnoteRef,
+                &TokenType::Other,
+                &TokenType::FootnoteRef,
+                &TokenType::Other,
+            ]
+        );
+        assert_eq!(tokens[1].contents, "infra");
+        assert_eq!(tokens[3].contents, "2");
+        assert_eq!(tokens[3].direction, Some(Direction::Infra));
+        assert_eq!(tokens[4].contents, ".</w:t></w:r>");
+        assert_eq!(tokens[7].mark, Some("*"));
+    }
+
+    #[test]
+    fn cross_ref_context() {
+        let text = "As the Supreme Court has long recognized in its cases, see supra note 412, at 5, the rule applies in every one of these cases.";
+        let start = text.find("note").unwrap();
+        assert_eq!(
+            context(text, &(start..start + 8)),
+            "…nized in its cases, see supra note 412, at 5, the rule applies in ev…"
+        );
+        assert_eq!(context("See note 3.", &(4..10)), "See note 3.");
+    }
+
+    #[test]
+    fn caption_refs() {
+        let input = r#"<w:body><w:p><w:r><w:t xml:space="preserve">See Table 1 and Fig. 2.</w:t></w:r></w:p><w:p><w:pPr><w:pStyle w:val="TableCaption"/></w:pPr><w:r><w:t xml:space="preserve">Table 1: Results</w:t></w:r></w:p></w:body>"#;
+        let paragraph_styles = HashMap::from([(
+            "TableCaption".to_string(),
+            ParagraphStyle {
+                caption: true,
+                ..Default::default()
+            },
+        )]);
+
+        let tokens = lex_doc(
+            input,
+            &styles(),
+            &paragraph_styles,
+            &[NumFmt::Decimal],
+            &lenient(),
+        )
+        .unwrap();
+        let types: Vec<(&TokenType, &str)> =
+            tokens.iter().map(|t| (&t.token_type, t.contents)).collect();
+        assert_eq!(
+            types,
+            [
+                (
+                    &TokenType::Other,
+                    r#"<w:body><w:p><w:r><w:t xml:space="preserve">See Table "#
+                ),
+                (&TokenType::TableRef, "1"),
+                (&TokenType::Other, " and Fig. "),
+                (&TokenType::FigureRef, "2"),
+                (
+                    &TokenType::Other,
+                    r#".</w:t></w:r></w:p><w:p><w:pPr><w:pStyle w:val="TableCaption"/></w:pPr><w:r><w:t xml:space="preserve">Table "#
+                ),
+                (&TokenType::TableCaption, "1"),
+                (&TokenType::Other, ": Results</w:t></w:r></w:p></w:body>"),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_mark_refs() {
+        let input = r#"<w:p><w:r><w:rPr><w:rStyle w:val="FootnoteReference"/></w:rPr><w:footnoteReference w:customMarkFollows="1" w:id="1"/><w:t>*</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" w:customMarkFollows="1" /><w:sym w:font="Symbol" w:char="F0A0"/></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r></w:p>"#;
+
+        let tokens = lex_doc(
+            input,
+            &styles(),
+            &HashMap::new(),
+            &[NumFmt::Decimal],
+            &bluebook(),
+        )
+        .unwrap();
+        assert_eq!(tokens.len(), 7);
+        assert_eq!(tokens[1].token_type, TokenType::FootnoteRef);
+        assert_eq!(tokens[1].id, Some("1"));
+        assert_eq!(tokens[1].mark, Some("*"));
+        assert_eq!(tokens[3].id, Some("2"));
+        assert_eq!(tokens[3].mark, Some(""));
+        assert_eq!(tokens[5].id, Some("3"));
+        assert_eq!(tokens[5].mark, None);
+
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">note *.</w:t></w:r></w:p>"#;
+        let tokens = lex_fn(input, &[NumFmt::Decimal], &lenient()).unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[1].token_type, TokenType::CrossRef);
+        assert_eq!(tokens[1].contents, "*");
+
+        // A "#" before a number is not a custom mark.
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">note #3.</w:t></w:r></w:p>"#;
+        let tokens = lex_fn(input, &[NumFmt::Decimal], &lenient()).unwrap();
+        assert!(tokens.iter().all(|t| t.token_type != TokenType::CrossRef));
+    }
+
+    #[test]
+    fn formatted_refs() {
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">note iv; </w:t></w:r><w:r><w:t xml:space="preserve">notes x–xii; </w:t></w:r><w:r><w:t xml:space="preserve">note that.</w:t></w:r></w:p>"#;
+
+        let tokens = lex_fn(
+            input,
+            &[NumFmt::LowerRoman, NumFmt::LowerLetter],
+            &lenient(),
+        )
+        .unwrap();
+        let refs: Vec<&str> = tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::CrossRef)
+            .map(|t| t.contents)
+            .collect();
+        assert_eq!(refs, ["iv", "x", "xii"]);
+    }
 
     #[test]
     fn single_ref() {
@@ -308,23 +1258,100 @@ mod tests {
   <w:footnoteRef />
 </w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">Footnote 2. Cross references footnote 1.</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">See</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">supra</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">note 1.</w:t></w:r></w:p></w:footnote>"#;
 
-        let tokens = lex_fn(input).unwrap();
-        assert_eq!(tokens.len(), 3);
+        let tokens = lex_fn(input, &[NumFmt::Decimal], &lenient()).unwrap();
+        assert_eq!(tokens.len(), 7);
         assert_eq!(tokens[0].token_type, TokenType::Other);
-        assert_eq!(tokens[1].token_type, TokenType::CrossRef);
+        assert_eq!(tokens[1].token_type, TokenType::Note);
+        assert_eq!(tokens[1].id, Some("21"));
         assert_eq!(tokens[2].token_type, TokenType::Other);
+        assert_eq!(tokens[3].token_type, TokenType::Signal);
+        assert_eq!(tokens[4].token_type, TokenType::Other);
+        assert_eq!(tokens[5].token_type, TokenType::CrossRef);
+        assert_eq!(tokens[6].token_type, TokenType::Other);
 
+        assert_eq!(tokens[1].contents, r#"<w:footnote w:id="21">"#);
         assert_eq!(
-            tokens[0].contents,
-            r#"<w:footnote w:id="21"><w:p><w:pPr><w:pStyle w:val="FootnoteText" /></w:pPr><w:r>
+            tokens[2].contents,
+            r#"<w:p><w:pPr><w:pStyle w:val="FootnoteText" /></w:pPr><w:r>
   <w:rPr>
     <w:rStyle w:val="FootnoteReference" />
   </w:rPr>
   <w:footnoteRef />
-</w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">Footnote 2. Cross references footnote 1.</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">See</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">supra</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">note "#
+</w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">Footnote 2. Cross references footnote 1.</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">See</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">"#
         );
-        assert_eq!(tokens[1].contents, "1");
-        assert_eq!(tokens[2].contents, r#".</w:t></w:r></w:p></w:footnote>"#);
+        assert_eq!(tokens[3].contents, "supra");
+        assert_eq!(
+            tokens[4].contents,
+            r#"</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">note "#
+        );
+        assert_eq!(tokens[5].contents, "1");
+        assert_eq!(tokens[5].direction, Some(Direction::Supra));
+        assert_eq!(tokens[5].signal, tokens[3].offset);
+        assert_eq!(tokens[6].contents, r#".</w:t></w:r></w:p></w:footnote>"#);
+    }
+
+    #[test]
+    fn chapter_ref() {
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">ch. 2, n. 12.</w:t></w:r><w:r><w:t xml:space="preserve">ch. 3, nn. 4–5.</w:t></w:r></w:p>"#;
+
+        let tokens = lex_fn(input, &[NumFmt::Decimal], &lenient()).unwrap();
+        let types: Vec<&TokenType> = tokens.iter().map(|t| &t.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                &TokenType::Other,
+                &TokenType::Chapter,
+                &TokenType::Other,
+                &TokenType::CrossRef,
+                &TokenType::Other,
+                &TokenType::Chapter,
+                &TokenType::Other,
+                &TokenType::CrossRef,
+                &TokenType::Other,
+                &TokenType::CrossRef,
+                &TokenType::Other,
+            ]
+        );
+        let contents: Vec<&str> = tokens.iter().map(|t| t.contents).collect();
+        assert_eq!(contents[1], "2");
+        assert_eq!(contents[2], ", n. ");
+        assert_eq!(contents[3], "12");
+        assert_eq!(contents[5], "3");
+        assert_eq!(contents[7], "4");
+        assert_eq!(contents[8], "–");
+        assert_eq!(contents[9], "5");
+    }
+
+    #[test]
+    fn ref_list() {
+        let input = r#"<w:p><w:r><w:t xml:space="preserve">notes 12, 15, and 31–33; </w:t></w:r><w:r><w:t xml:space="preserve">notes 4 &amp; 9, at 2.</w:t></w:r></w:p>"#;
+
+        let tokens = lex_fn(input, &[NumFmt::Decimal], &lenient()).unwrap();
+        let refs: Vec<&str> = tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::CrossRef)
+            .map(|t| t.contents)
+            .collect();
+        assert_eq!(refs, ["12", "15", "31", "33", "4", "9"]);
+
+        // The separators are left as they were.
+        let contents: Vec<&str> = tokens.iter().map(|t| t.contents).collect();
+        assert_eq!(contents[2], ", ");
+        assert_eq!(contents[4], ", and ");
+        assert_eq!(contents[6], "–");
+        assert_eq!(contents[10], " &amp; ");
+        assert_eq!(contents[12], r#", at 2.</w:t></w:r></w:p>"#);
+
+        // A list ends at the first thing that is not a number.
+        let input =
+            r#"<w:p><w:r><w:t xml:space="preserve">notes a, b, and that.</w:t></w:r></w:p>"#;
+        let tokens = lex_fn(input, &[NumFmt::LowerLetter], &lenient()).unwrap();
+        let refs: Vec<&str> = tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::CrossRef)
+            .map(|t| t.contents)
+            .collect();
+        assert_eq!(refs, ["a", "b"]);
     }
 
     #[test]
@@ -336,26 +1363,35 @@ mod tests {
   <w:footnoteRef />
 </w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">Footnote 3. Cross references a range of footnotes, 1 and 2.</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">See</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">supra</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">notes 1–2.</w:t></w:r></w:p></w:footnote>"#;
 
-        let tokens = lex_fn(input).unwrap();
-        assert_eq!(tokens.len(), 5);
+        let tokens = lex_fn(input, &[NumFmt::Decimal], &lenient()).unwrap();
+        assert_eq!(tokens.len(), 9);
         assert_eq!(tokens[0].token_type, TokenType::Other);
-        assert_eq!(tokens[1].token_type, TokenType::CrossRef);
+        assert_eq!(tokens[1].token_type, TokenType::Note);
+        assert_eq!(tokens[1].id, Some("22"));
         assert_eq!(tokens[2].token_type, TokenType::Other);
-        assert_eq!(tokens[3].token_type, TokenType::CrossRef);
+        assert_eq!(tokens[3].token_type, TokenType::Signal);
         assert_eq!(tokens[4].token_type, TokenType::Other);
+        assert_eq!(tokens[5].token_type, TokenType::CrossRef);
+        assert_eq!(tokens[6].token_type, TokenType::Other);
+        assert_eq!(tokens[7].token_type, TokenType::CrossRef);
+        assert_eq!(tokens[8].token_type, TokenType::Other);
 
+        assert_eq!(tokens[1].contents, r#"<w:footnote w:id="22">"#);
         assert_eq!(
-            tokens[0].contents,
-            r#"<w:footnote w:id="22"><w:p><w:pPr><w:pStyle w:val="FootnoteText" /></w:pPr><w:r>
+            tokens[2].contents,
+            r#"<w:p><w:pPr><w:pStyle w:val="FootnoteText" /></w:pPr><w:r>
   <w:rPr>
     <w:rStyle w:val="FootnoteReference" />
   </w:rPr>
   <w:footnoteRef />
-</w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">Footnote 3. Cross references a range of footnotes, 1 and 2.</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">See</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">supra</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">notes "#
+</w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">Footnote 3. Cross references a range of footnotes, 1 and 2.</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">See</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:rPr><w:iCs /><w:i /></w:rPr><w:t xml:space="preserve">"#
         );
-        assert_eq!(tokens[1].contents, r#"1"#);
-        assert_eq!(tokens[2].contents, r#"–"#);
-        assert_eq!(tokens[3].contents, r#"2"#);
-        assert_eq!(tokens[4].contents, r#".</w:t></w:r></w:p></w:footnote>"#);
+        assert_eq!(tokens[3].contents, r#"supra"#);
+        assert_eq!(tokens[5].contents, r#"1"#);
+        assert_eq!(tokens[6].contents, r#"–"#);
+        assert_eq!(tokens[7].contents, r#"2"#);
+        assert_eq!(tokens[8].contents, r#".</w:t></w:r></w:p></w:footnote>"#);
+        assert_eq!(tokens[5].signal, tokens[3].offset);
+        assert_eq!(tokens[7].signal, tokens[3].offset);
     }
 }
@@ -1,52 +1,557 @@
 mod bookmarks;
+mod captions;
+mod check;
+pub mod config;
+pub mod diagnostic;
 pub mod fs;
+pub mod grammar;
 mod lexer;
+mod notes;
+mod numbering;
+mod numfmt;
+pub mod package;
+mod paragraph;
 mod parser;
 mod render;
+mod styles;
+mod xml;
 
+use diagnostic::{Diagnostic, Kind};
 use slog::o;
+use std::borrow::Cow;
+
+/// The contents of the parts of a Word document that AutoCref works on.
+///
+/// A document might have footnotes, endnotes, both, or neither. The settings,
+/// styles, and numbering parts are only read, for how notes are numbered, which
+/// styles mark note references, and how paragraphs are numbered.
+#[derive(Debug, Clone, Copy)]
+pub struct Parts<'a> {
+    pub document: &'a str,
+    pub footnotes: Option<&'a str>,
+    pub endnotes: Option<&'a str>,
+    pub settings: Option<&'a str>,
+    pub styles: Option<&'a str>,
+    pub numbering: Option<&'a str>,
+}
+
+/// The options that change how AutoCref processes a document.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// The number of the document's first footnote.
+    ///
+    /// This matches Supra's offset option (the first footnote is the offset
+    /// plus 1). It overrides the document's own numbering settings, and it is
+    /// written into the document so that Word displays the same numbers.
+    pub first_note: Option<u32>,
+    /// The ids of the character styles that mark note references.
+    ///
+    /// Note references are found whatever their style, so these only decide
+    /// whether a custom mark in the run after a reference is part of it. They
+    /// override the styles found in the document's styles part, for templates
+    /// whose reference styles cannot be found by name.
+    pub reference_styles: Vec<String>,
+    /// Whether to convert cross-references without a signal (*e.g.*, an
+    /// italicized "supra" or "infra").
+    ///
+    /// By default, only those with one of the citation style's signals are
+    /// converted, so that other notes mentioned in a note (*e.g.*, "the
+    /// court's note 3") are left alone. The lenient mode is for drafts with
+    /// manual cross-references.
+    pub lenient: bool,
+    /// The citation style whose cross-references are converted.
+    ///
+    /// This sets the phrases that make up a cross-reference (*e.g.*, "note 12"
+    /// or "n 12") and the signals that a cross-reference needs (*e.g.*, an
+    /// italicized "supra" or a following "above"). The default is the Bluebook.
+    pub citation_style: grammar::CitationStyle,
+    /// A grammar that takes the place of the citation style's (*e.g.*, one read
+    /// from `autocref.toml` with [`config::grammar`]).
+    pub grammar: Option<grammar::Grammar>,
+    /// Whether to leave cross-references to notes that do not exist as text.
+    ///
+    /// By default, such a cross-reference (*e.g.*, "*supra* note 412" in an
+    /// article with 300 notes, or one written with a stale number) is an error
+    /// that names the note it is in. With this option, it is a warning.
+    pub allow_dangling: bool,
+    /// Whether to change signals that point the wrong way.
+    ///
+    /// By default, a cross-reference whose signal points the wrong way
+    /// (*e.g.*, "*supra* note 40" in note 22, after the notes were rearranged)
+    /// is converted with a warning. With this option, its "supra" becomes
+    /// "infra" (or "infra" becomes "supra," "above" becomes "below," and so
+    /// on), keeping its formatting, as long as no other cross-reference needs
+    /// the signal as it is.
+    pub fix_signals: bool,
+}
+
+/// The new contents of the parts of a Word document.
+///
+/// Each notes part is present in the output only if it was present in the
+/// input.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Output {
+    pub document: String,
+    pub footnotes: Option<String>,
+    pub endnotes: Option<String>,
+}
 
 /// The primary function.
 ///
+/// This function runs [`autocref_parts`] on a document with only footnotes,
+/// outputting the contents of the two `.xml` files with additional markup.
+pub fn autocref(doc_input: &str, fn_input: &str) -> Result<(String, String), Diagnostic> {
+    let output = autocref_parts(
+        &Parts {
+            document: doc_input,
+            footnotes: Some(fn_input),
+            endnotes: None,
+            settings: None,
+            styles: None,
+            numbering: None,
+        },
+        &Options::default(),
+    )?;
+
+    Ok((output.document, output.footnotes.unwrap_or_default()))
+}
+
+/// Process the parts of a document.
+///
 /// This function determines which bookmark id to start with and then runs the
-/// lexer, parser, and renderer, eventually outputting the contents of the two
-/// `.xml` files with additional markup.
-pub fn autocref(doc_input: &str, fn_input: &str) -> Result<(String, String), String> {
+/// lexer, parser, and renderer, eventually outputting the contents of the
+/// `.xml` files with additional markup. Footnotes and endnotes are handled
+/// separately: cross-references in footnotes refer to footnotes, and
+/// cross-references in endnotes refer to endnotes. Cross-references in the body
+/// text refer to footnotes, unless the document only has endnotes. References
+/// to the document's numbered headings and paragraphs (*e.g.*, "*infra* Part
+/// II" or "*supra* ¶ 12") become `REF` fields, and the paragraphs they refer to
+/// get bookmarks. The numbers in the captions of tables and figures become
+/// `SEQ` fields, and references to them (*e.g.*, "*infra* Table 3") become
+/// `REF` fields, too. See [`Options`] for the ways this can be changed.
+///
+/// A problem is returned as a [`Diagnostic`], which names the part it is in
+/// after the part's usual place in a package (*e.g.*, `word/footnotes.xml`)
+/// and, when it is known, where in the part it is.
+pub fn autocref_parts(parts: &Parts, options: &Options) -> Result<Output, Diagnostic> {
+    // Set the first footnote's number
+    let document = first_note(parts, options)?;
+
     // Determine the starting bookmark id number
-    let starting_bookmark = match slog_scope::scope(
+    let starting_bookmark = slog_scope::scope(
         &slog_scope::logger().new(o!("fn" => "starting_bookmark()")),
-        || bookmarks::starting_bookmark(doc_input),
-    ) {
-        Ok(i) => i,
-        Err(e) => return Err(e),
+        || {
+            bookmarks::starting_bookmark(&document)
+                .map_err(|e| e.in_part(diagnostic::DOCUMENT_PART))
+        },
+    )?;
+
+    // Read the other parts and lex the inputs
+    let Lexed {
+        settings,
+        paragraphs,
+        tokens: (doc_tokens, fn_tokens, en_tokens),
+        ..
+    } = lex(&document, parts, options)?;
+
+    // Parse the tokens. The problems that do not stop the parser are logged
+    // as they are found.
+    let parser::Parsed {
+        doc: doc_branches,
+        footnotes: fn_branches,
+        endnotes: en_branches,
+        refd_notes,
+        refd_paragraphs,
+        refd_captions,
+        ..
+    } = slog_scope::scope(&slog_scope::logger().new(o!("fn" => "parser()")), || {
+        parser::parser(
+            &doc_tokens,
+            fn_tokens.as_deref(),
+            en_tokens.as_deref(),
+            &settings,
+            &paragraphs,
+            options.allow_dangling,
+            options.fix_signals,
+        )
+    })?;
+
+    // Render the output
+    let (document, footnotes, endnotes) =
+        slog_scope::scope(&slog_scope::logger().new(o!("fn" => "render()")), || {
+            render::render(
+                &doc_branches,
+                refd_notes,
+                &paragraphs,
+                &refd_paragraphs,
+                &refd_captions,
+                starting_bookmark,
+                fn_branches.as_deref(),
+                en_branches.as_deref(),
+            )
+        })?;
+
+    Ok(Output {
+        document,
+        footnotes,
+        endnotes,
+    })
+}
+
+/// Check the parts of a document for cross-references that AutoCref would
+/// miss.
+///
+/// This function reads and lexes the parts as [`autocref_parts`] does, without
+/// changing anything. It returns a warning for each piece of text that looks
+/// like a cross-reference to a note but is not converted (*e.g.*, one whose
+/// "*supra*" is not italic) or whose wording is off (*e.g.*, "notes 7" or
+/// "notes 9–4"), for each Supra placeholder that was never resolved, and for
+/// each cross-reference whose signal points the wrong way or that refers to
+/// its own note. Each cross-reference to a note that does not exist is an
+/// error, as it would be in [`autocref_parts`]. A document without any of
+/// these problems returns nothing.
+pub fn check_parts(parts: &Parts, options: &Options) -> Result<Vec<Diagnostic>, Diagnostic> {
+    let document = first_note(parts, options)?;
+    let Lexed {
+        settings,
+        paragraphs,
+        grammar,
+        formats: (fn_formats, en_formats),
+        tokens: (doc_tokens, fn_tokens, en_tokens),
+        ..
+    } = lex(&document, parts, options)?;
+
+    // Number the notes, to name the ones with problems
+    let notes = parser::notes(
+        &doc_tokens,
+        fn_tokens.as_deref(),
+        en_tokens.as_deref(),
+        &settings,
+    )?;
+
+    // Check each part for near misses
+    let mut found: Vec<Diagnostic> = Vec::new();
+    for (input, part, kind, tokens, formats) in [
+        (
+            Some(document.as_ref()),
+            diagnostic::DOCUMENT_PART,
+            None,
+            Some(&doc_tokens[..]),
+            &fn_formats,
+        ),
+        (
+            parts.footnotes,
+            diagnostic::FOOTNOTES_PART,
+            Some(parser::NoteKind::Footnote),
+            fn_tokens.as_deref(),
+            &fn_formats,
+        ),
+        (
+            parts.endnotes,
+            diagnostic::ENDNOTES_PART,
+            Some(parser::NoteKind::Endnote),
+            en_tokens.as_deref(),
+            &en_formats,
+        ),
+    ] {
+        if let (Some(input), Some(tokens)) = (input, tokens) {
+            found.append(&mut slog_scope::scope(
+                &slog_scope::logger().new(o!("fn" => "check()")),
+                || {
+                    check::check(input, part, kind, tokens, &notes, &grammar, formats)
+                        .map_err(|e| e.in_part(part))
+                },
+            )?);
+        }
+    }
+
+    // Then for cross-references to notes that do not exist and ones that
+    // point the wrong way. Allowing the former lets the parser find them all.
+    let parsed = slog_scope::scope(&slog_scope::logger().new(o!("fn" => "parser()")), || {
+        parser::parser(
+            &doc_tokens,
+            fn_tokens.as_deref(),
+            en_tokens.as_deref(),
+            &settings,
+            &paragraphs,
+            true,
+            false,
+        )
+    })?;
+    found.extend(parsed.problems);
+
+    Ok(found)
+}
+
+/// Set the number of the first footnote in `document.xml`, if there is one
+/// in the options.
+fn first_note<'a>(parts: &Parts<'a>, options: &Options) -> Result<Cow<'a, str>, Diagnostic> {
+    match options.first_note {
+        Some(n) => Ok(Cow::Owned(slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "set_first_note()")),
+            || {
+                notes::set_first_note(parts.document, n)
+                    .map_err(|e| e.in_part(diagnostic::DOCUMENT_PART))
+            },
+        )?)),
+        None => Ok(Cow::Borrowed(parts.document)),
+    }
+}
+
+/// What is read from a document's parts, along with their tokens.
+struct Lexed<'a> {
+    settings: notes::NoteSettings,
+    paragraphs: Vec<numbering::Numbered>,
+    grammar: grammar::Grammar,
+    /// The formats of the footnote and endnote numbers.
+    formats: (Vec<numfmt::NumFmt>, Vec<numfmt::NumFmt>),
+    tokens: lexer::LexResults<'a>,
+}
+
+/// Read how the document's notes and paragraphs are numbered and which styles
+/// mark its note references, and then lex the inputs.
+///
+/// The `document` is the main document part, whose first footnote's number
+/// might have been set (see [`first_note`]).
+fn lex<'a>(
+    document: &'a str,
+    parts: &Parts<'a>,
+    options: &Options,
+) -> Result<Lexed<'a>, Diagnostic> {
+    // Determine how notes are numbered
+    let settings = slog_scope::scope(&slog_scope::logger().new(o!("fn" => "settings()")), || {
+        notes::settings(parts.settings).map_err(|e| e.in_part(diagnostic::SETTINGS_PART))
+    })?;
+
+    // Determine which styles mark note references (and their custom marks)
+    let reference_styles = if options.reference_styles.is_empty() {
+        slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "reference_styles()")),
+            || {
+                styles::reference_styles(parts.styles)
+                    .map_err(|e| e.in_part(diagnostic::STYLES_PART))
+            },
+        )?
+    } else {
+        options.reference_styles.clone()
+    };
+
+    // Determine how the headings and other paragraphs are numbered
+    let paragraph_styles = slog_scope::scope(
+        &slog_scope::logger().new(o!("fn" => "paragraph_styles()")),
+        || styles::paragraph_styles(parts.styles).map_err(|e| e.in_part(diagnostic::STYLES_PART)),
+    )?;
+    let paragraphs = slog_scope::scope(
+        &slog_scope::logger().new(o!("fn" => "paragraphs()")),
+        || {
+            numbering::paragraphs(document, &paragraph_styles, parts.numbering)
+                .map_err(|e| e.in_part(diagnostic::DOCUMENT_PART))
+        },
+    )?;
+
+    // Determine how note numbers look
+    let formats = |kind| {
+        notes::formats(&settings, document, kind).map_err(|e| e.in_part(diagnostic::DOCUMENT_PART))
     };
+    let fn_formats = formats(parser::NoteKind::Footnote)?;
+    let en_formats = formats(parser::NoteKind::Endnote)?;
+
+    // Determine what cross-references look like. Without signals, every
+    // match is a cross-reference.
+    let mut grammar = match &options.grammar {
+        Some(g) => g.clone(),
+        None => options.citation_style.grammar(),
+    };
+    if options.lenient {
+        grammar.signals.clear();
+    }
 
     // Lex the inputs
-    let (doc_tokens, fn_tokens) =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "lex()")), || {
-            lexer::lex(doc_input, fn_input)
-        }) {
-            Ok(t) => t,
-            Err(e) => return Err(e),
-        };
+    let tokens = slog_scope::scope(&slog_scope::logger().new(o!("fn" => "lex()")), || {
+        lexer::lex(
+            document,
+            parts.footnotes,
+            parts.endnotes,
+            &reference_styles,
+            &paragraph_styles,
+            &fn_formats,
+            &en_formats,
+            &grammar,
+        )
+    })?;
 
-    // Parse the tokens
-    let (doc_branches, fn_branches, refd_fns) =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "parser()")), || {
-            parser::parser(&doc_tokens, &fn_tokens)
-        }) {
-            Ok(t) => t,
-            Err(e) => return Err(e),
-        };
+    Ok(Lexed {
+        settings,
+        paragraphs,
+        grammar,
+        formats: (fn_formats, en_formats),
+        tokens,
+    })
+}
 
-    // Render the output
-    let (doc_output, fn_output) =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "render()")), || {
-            render::render(&doc_branches, refd_fns, starting_bookmark, &fn_branches)
-        }) {
-            Ok(t) => t,
-            Err(e) => return Err(e),
-        };
+/// Process a `.docx` file.
+///
+/// This function takes the bytes of a `.docx` file, runs [`autocref_parts`] on
+/// its main document, footnotes, and endnotes parts, and returns the bytes of a
+/// new `.docx` file. The settings, styles, and numbering parts are read for how
+/// notes, paragraphs, and captions are numbered and marked. Every other part of
+/// the package is copied as is. A document without footnotes or endnotes still
+/// has its cross-references to paragraphs and captions converted.
+///
+/// The parts are located through the package's relationships and content
+/// types (see [`package::locate_parts`]), so they need not be at
+/// `word/document.xml` and `word/footnotes.xml`. A [`Diagnostic`] names the
+/// parts where they actually are.
+pub fn autocref_docx(input: &[u8], options: &Options) -> Result<Vec<u8>, Diagnostic> {
+    let mut package = slog_scope::scope(
+        &slog_scope::logger().new(o!("fn" => "Package::open()")),
+        || package::Package::open(input),
+    )?;
+
+    // Find the parts
+    let locations = slog_scope::scope(
+        &slog_scope::logger().new(o!("fn" => "locate_parts()")),
+        || package::locate_parts(|name| package.read_part(name)),
+    )?;
+
+    // Read the parts and run the main program
+    let inputs = read_parts(&mut package, &locations)?;
+    let output = autocref_parts(&inputs.parts(), options).map_err(|e| locate(e, &locations))?;
+
+    // Write the new package
+    package.replace_part(&locations.document, output.document);
+    if let (Some(part), Some(contents)) = (&locations.footnotes, output.footnotes) {
+        package.replace_part(part, contents);
+    }
+    if let (Some(part), Some(contents)) = (&locations.endnotes, output.endnotes) {
+        package.replace_part(part, contents);
+    }
+    slog_scope::scope(
+        &slog_scope::logger().new(o!("fn" => "Package::write()")),
+        || package.write(),
+    )
+}
+
+/// Check a `.docx` file for cross-references that AutoCref would miss.
+///
+/// This function runs [`check_parts`] on the parts of a `.docx` file, which
+/// are located as in [`autocref_docx`]. Nothing is written.
+pub fn check_docx(input: &[u8], options: &Options) -> Result<Vec<Diagnostic>, Diagnostic> {
+    let mut package = slog_scope::scope(
+        &slog_scope::logger().new(o!("fn" => "Package::open()")),
+        || package::Package::open(input),
+    )?;
+
+    // Find the parts
+    let locations = slog_scope::scope(
+        &slog_scope::logger().new(o!("fn" => "locate_parts()")),
+        || package::locate_parts(|name| package.read_part(name)),
+    )?;
 
-    Ok((doc_output, fn_output))
+    // Read the parts and check them
+    let inputs = read_parts(&mut package, &locations)?;
+    match check_parts(&inputs.parts(), options) {
+        Ok(found) => Ok(found.into_iter().map(|d| locate(d, &locations)).collect()),
+        Err(e) => Err(locate(e, &locations)),
+    }
+}
+
+/// The contents of the parts read from a package.
+struct Inputs {
+    document: String,
+    footnotes: Option<String>,
+    endnotes: Option<String>,
+    settings: Option<String>,
+    styles: Option<String>,
+    numbering: Option<String>,
+}
+
+impl Inputs {
+    /// Get the [`Parts`] with these contents.
+    fn parts(&self) -> Parts<'_> {
+        Parts {
+            document: &self.document,
+            footnotes: self.footnotes.as_deref(),
+            endnotes: self.endnotes.as_deref(),
+            settings: self.settings.as_deref(),
+            styles: self.styles.as_deref(),
+            numbering: self.numbering.as_deref(),
+        }
+    }
+}
+
+/// Read the parts of a package that AutoCref works on.
+fn read_parts(
+    package: &mut package::Package,
+    locations: &package::WordParts,
+) -> Result<Inputs, Diagnostic> {
+    let document = read_required_part(package, &locations.document)?;
+    let footnotes = match &locations.footnotes {
+        Some(f) => Some(read_required_part(package, f)?),
+        None => None,
+    };
+    let endnotes = match &locations.endnotes {
+        Some(e) => Some(read_required_part(package, e)?),
+        None => None,
+    };
+    let settings = match &locations.settings {
+        Some(s) => package.read_part(s)?,
+        None => None,
+    };
+    let styles = match &locations.styles {
+        Some(s) => package.read_part(s)?,
+        None => None,
+    };
+    let numbering = match &locations.numbering {
+        Some(n) => package.read_part(n)?,
+        None => None,
+    };
+
+    Ok(Inputs {
+        document,
+        footnotes,
+        endnotes,
+        settings,
+        styles,
+        numbering,
+    })
+}
+
+/// Read a part that the package's relationships say must exist.
+fn read_required_part(package: &mut package::Package, name: &str) -> Result<String, Diagnostic> {
+    match package.read_part(name)? {
+        Some(p) => Ok(p),
+        None => {
+            let err_msg = format!("the package does not contain {}", name);
+            Err(Diagnostic::error(Kind::Package, err_msg).in_part(name))
+        }
+    }
+}
+
+/// Name the parts in a diagnostic from [`autocref_parts`], which go by their
+/// usual places, by where they actually are in the package.
+fn locate(diagnostic: Diagnostic, locations: &package::WordParts) -> Diagnostic {
+    let part = diagnostic.part.map(|p| {
+        let location = match p.as_str() {
+            diagnostic::DOCUMENT_PART => Some(&locations.document),
+            diagnostic::FOOTNOTES_PART => locations.footnotes.as_ref(),
+            diagnostic::ENDNOTES_PART => locations.endnotes.as_ref(),
+            diagnostic::SETTINGS_PART => locations.settings.as_ref(),
+            diagnostic::STYLES_PART => locations.styles.as_ref(),
+            diagnostic::NUMBERING_PART => locations.numbering.as_ref(),
+            _ => None,
+        };
+        location.cloned().unwrap_or(p)
+    });
+    let related = diagnostic
+        .related
+        .into_vec()
+        .into_iter()
+        .map(|d| locate(d, locations))
+        .collect();
+    Diagnostic {
+        part,
+        related,
+        ..diagnostic
+    }
 }
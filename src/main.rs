@@ -1,10 +1,20 @@
 #[macro_use]
 extern crate slog;
 
-use autocref::fs::{load_file, save_file};
+use autocref::{
+    config::{self, CONFIG_FILE},
+    diagnostic::Diagnostic,
+    fs::{load_docx, load_file, load_part, save_docx, save_file},
+    grammar::{CitationStyle, Grammar, CITATION_STYLE_NAMES},
+};
 use clap::{crate_version, App, Arg};
 use slog::{Drain, Level};
-use std::{path::Path, process, sync::Mutex};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
 
 fn main() {
     // Get the command-line arguments and flags
@@ -12,19 +22,72 @@ fn main() {
         .version(crate_version!())
         .author("Bryan Lammon")
         .about("A Supra + Pandoc post-processor for footnote cross-references")
+        .args(input_args())
         .arg(
-            Arg::with_name("doc_input")
-                .value_name("DOCUMENT.XML FILE")
-                .help("The document.xml file to process")
-                .default_value("./word/document.xml")
-                .index(1),
+            Arg::with_name("offset")
+                .long("offset")
+                .value_name("NUMBER")
+                .help("The number of footnotes before the first one, as with Supra's offset")
+                .global(true)
+                .conflicts_with("first_note"),
+        )
+        .arg(
+            Arg::with_name("first_note")
+                .long("first-note")
+                .value_name("NUMBER")
+                .help("The number of the first footnote")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("reference_style")
+                .long("reference-style")
+                .value_name("STYLE ID")
+                .help("The id of a character style that marks note references (can be repeated)")
+                .multiple_occurrences(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("lenient")
+                .long("lenient")
+                .help(
+                    "Converts cross-references without a signal (e.g., an italicized supra or infra)",
+                )
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("allow_dangling")
+                .long("allow-dangling")
+                .help(
+                "Leaves cross-references to notes that do not exist as text instead of stopping",
+            )
+                .global(true),
         )
         .arg(
-            Arg::with_name("fn_input")
-                .value_name("FOOTNOTES.XML FILE")
-                .help("The footnotes.xml file to process")
-                .default_value("./word/footnotes.xml")
-                .index(2),
+            Arg::with_name("fix_signals")
+                .long("fix-signals")
+                .help(
+                "Changes supra to infra (or infra to supra) where a cross-reference points the other way",
+            )
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("citation_style")
+                .long("citation-style")
+                .value_name("STYLE")
+                .help("The citation style of the cross-references")
+                .possible_values(CITATION_STYLE_NAMES)
+                .ignore_case(true)
+                .default_value("bluebook")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help(
+                "The configuration file with cross-reference phrases (default: the nearest autocref.toml)",
+            )
+                .global(true),
         )
         .arg(
             Arg::with_name("verbose")
@@ -34,7 +97,8 @@ fn main() {
                 .help("Verbosity level between 0 (critical) and 3 (info)")
                 .hidden_short_help(true)
                 .hidden_long_help(true)
-                .default_value("3"),
+                .default_value("3")
+                .global(true),
         )
         .arg(
             Arg::with_name("no_save")
@@ -42,10 +106,22 @@ fn main() {
                 .long("no_save")
                 .help("Does not save the results into the provided files.")
                 .hidden_short_help(true)
-                .hidden_long_help(true),
+                .hidden_long_help(true)
+                .global(true),
+        )
+        .subcommand(
+            App::new("check")
+                .about("Reports cross-references that would not be converted, without saving anything")
+                .args(input_args()),
         )
         .get_matches();
 
+    // A check takes the same inputs and options
+    let (matches, check) = match matches.subcommand_matches("check") {
+        Some(m) => (m, true),
+        None => (&matches, false),
+    };
+
     // Setup the logger.
     // First determine the log level.
     let min_log_level = match matches.value_of("verbose").unwrap() {
@@ -74,49 +150,297 @@ fn main() {
     debug!(slog_scope::logger(), "Logger setup.");
 
     // Setup configuration variables
-    let doc_input_file = Path::new(matches.value_of("doc_input").unwrap());
-    let fn_input_file = Path::new(matches.value_of("fn_input").unwrap());
+    let mut doc_input_file = PathBuf::from(matches.value_of("doc_input").unwrap());
+    let mut fn_input_file = notes_file(matches, "fn_input");
+    let mut en_input_file = notes_file(matches, "en_input");
+    let mut settings_file = Some(PathBuf::from("./word/settings.xml")).filter(|f| f.is_file());
+    let mut styles_file = Some(PathBuf::from("./word/styles.xml")).filter(|f| f.is_file());
+    let mut numbering_file = Some(PathBuf::from("./word/numbering.xml")).filter(|f| f.is_file());
     let no_save = matches.is_present("no_save");
+    let citation_style =
+        CitationStyle::from_name(matches.value_of("citation_style").unwrap()).unwrap_or_default();
+    let options = autocref::Options {
+        first_note: first_note(matches),
+        reference_styles: match matches.values_of("reference_style") {
+            Some(v) => v.map(String::from).collect(),
+            None => Vec::new(),
+        },
+        lenient: matches.is_present("lenient"),
+        citation_style,
+        grammar: grammar(matches, &doc_input_file, citation_style),
+        allow_dangling: matches.is_present("allow_dangling"),
+        fix_signals: matches.is_present("fix_signals"),
+    };
 
-    // Load the inputs
-    let doc_input =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "load_file()")), || {
-            load_file(doc_input_file)
-        }) {
-            Ok(i) => i,
+    // A .docx file is processed as a whole package
+    if is_docx(&doc_input_file) {
+        if matches.occurrences_of("fn_input") > 0 || matches.occurrences_of("en_input") > 0 {
+            eprintln!("Notes files cannot be provided with a .docx file");
+            process::exit(1);
+        }
+
+        let docx_input =
+            match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "load_docx()")), || {
+                load_docx(&doc_input_file)
+            }) {
+                Ok(i) => i,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            };
+
+        if check {
+            let found = slog_scope::scope(
+                &slog_scope::logger().new(o!("fn" => "check_docx()")),
+                || autocref::check_docx(&docx_input, &options),
+            );
+            drop(_guard);
+            report(found);
+        }
+
+        let docx_output = match slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "autocref_docx()")),
+            || autocref::autocref_docx(&docx_input, &options),
+        ) {
+            Ok(o) => o,
             Err(e) => {
-                eprintln!("document.xml load error: {}", e);
+                drop(_guard);
+                eprintln!("{}", e);
                 process::exit(1);
             }
         };
 
-    let fn_input =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "load_file()")), || {
-            load_file(fn_input_file)
-        }) {
-            Ok(i) => i,
-            Err(e) => {
-                eprintln!("footnotes.xml load error: {}", e);
+        if !no_save {
+            if let Err(e) = save_docx(&doc_input_file, &docx_output) {
+                eprintln!("{}", e);
                 process::exit(1);
             }
-        };
+        }
 
-    // Run the main program
-    let (doc_output, fn_output) =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "autocref()")), || {
-            autocref::autocref(&doc_input, &fn_input)
-        }) {
-            Ok(o) => o,
+        return;
+    }
+
+    // Inside an unzipped package, the package's relationships say where the
+    // parts are (unless the files were provided)
+    if matches.occurrences_of("doc_input") == 0 && Path::new("_rels/.rels").exists() {
+        let parts = match slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "locate_parts()")),
+            || autocref::package::locate_parts(load_part),
+        ) {
+            Ok(p) => p,
             Err(e) => {
-                drop(_guard);
-                eprintln!("Application error: {}", e);
+                eprintln!("{}", e);
                 process::exit(1);
             }
         };
 
+        doc_input_file = PathBuf::from(parts.document);
+        fn_input_file = parts.footnotes.map(PathBuf::from);
+        en_input_file = parts.endnotes.map(PathBuf::from);
+        settings_file = parts.settings.map(PathBuf::from).filter(|f| f.is_file());
+        styles_file = parts.styles.map(PathBuf::from).filter(|f| f.is_file());
+        numbering_file = parts.numbering.map(PathBuf::from).filter(|f| f.is_file());
+    }
+
+    // Load the inputs
+    let doc_input = load_or_exit(&doc_input_file);
+    let fn_input = fn_input_file.as_deref().map(load_or_exit);
+    let en_input = en_input_file.as_deref().map(load_or_exit);
+    let settings_input = settings_file.as_deref().map(load_or_exit);
+    let styles_input = styles_file.as_deref().map(load_or_exit);
+    let numbering_input = numbering_file.as_deref().map(load_or_exit);
+
+    let parts = autocref::Parts {
+        document: &doc_input,
+        footnotes: fn_input.as_deref(),
+        endnotes: en_input.as_deref(),
+        settings: settings_input.as_deref(),
+        styles: styles_input.as_deref(),
+        numbering: numbering_input.as_deref(),
+    };
+    if check {
+        let found = slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "check_parts()")),
+            || autocref::check_parts(&parts, &options),
+        );
+        drop(_guard);
+        report(found);
+    }
+
+    // Run the main program
+    let output = match slog_scope::scope(
+        &slog_scope::logger().new(o!("fn" => "autocref_parts()")),
+        || autocref::autocref_parts(&parts, &options),
+    ) {
+        Ok(o) => o,
+        Err(e) => {
+            drop(_guard);
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
     // Save the output (unless the no-save flag is on)
     if !no_save {
-        save_file(doc_input_file, &doc_output);
-        save_file(fn_input_file, &fn_output);
+        let outputs = [
+            (Some(&doc_input_file), Some(&output.document)),
+            (fn_input_file.as_ref(), output.footnotes.as_ref()),
+            (en_input_file.as_ref(), output.endnotes.as_ref()),
+        ];
+        for (file, contents) in outputs {
+            if let (Some(f), Some(o)) = (file, contents) {
+                if let Err(e) = save_file(f, o) {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+/// The files to process, which a check takes, too.
+fn input_args() -> [Arg<'static>; 3] {
+    [
+        Arg::with_name("doc_input")
+            .value_name("DOCX OR DOCUMENT.XML FILE")
+            .help("The .docx or document.xml file to process")
+            .default_value("./word/document.xml")
+            .index(1),
+        Arg::with_name("fn_input")
+            .value_name("FOOTNOTES.XML FILE")
+            .help("The footnotes.xml file to process")
+            .default_value("./word/footnotes.xml")
+            .index(2),
+        Arg::with_name("en_input")
+            .value_name("ENDNOTES.XML FILE")
+            .help("The endnotes.xml file to process")
+            .default_value("./word/endnotes.xml")
+            .index(3),
+    ]
+}
+
+/// Print the problems that a check found and exit.
+///
+/// The exit code is 1 if there were any, so that a check can fail a build.
+fn report(found: Result<Vec<Diagnostic>, Diagnostic>) -> ! {
+    let found = match found {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    for diagnostic in &found {
+        println!("{}\n", diagnostic);
+    }
+    if found.is_empty() {
+        println!("No problems found");
+        process::exit(0);
+    }
+    println!("Found {} problem(s)", found.len());
+    process::exit(1);
+}
+
+/// Load a file, or print why it could not be loaded and exit.
+fn load_or_exit(path: &Path) -> String {
+    match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "load_file()")), || {
+        load_file(path)
+    }) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Determine which notes file to process.
+///
+/// A file provided on the command line is always used. The default file is
+/// used only if it exists, since a document need not have both footnotes and
+/// endnotes.
+fn notes_file(matches: &clap::ArgMatches, name: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(matches.value_of(name).unwrap());
+    if matches.occurrences_of(name) > 0 || path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Determine the number of the first footnote, if one was provided.
+///
+/// This can be provided directly or as an offset (the number of footnotes
+/// before the first one).
+fn first_note(matches: &clap::ArgMatches) -> Option<u32> {
+    let number = |name: &str| {
+        matches.value_of(name).map(|n| match n.parse::<u32>() {
+            Ok(n) => n,
+            Err(e) => {
+                eprintln!("Invalid {} {:?}: {}", name.replace('_', " "), n, e);
+                process::exit(1);
+            }
+        })
+    };
+
+    match number("offset") {
+        Some(offset) => Some(offset.saturating_add(1)),
+        None => number("first_note"),
+    }
+}
+
+/// Read the grammar in the configuration file, if there is one.
+///
+/// The file can be provided on the command line. Otherwise, it is the nearest
+/// `autocref.toml` in the current directory or one of its parents, or else the
+/// nearest one next to the `.docx` file or in one of that directory's parents.
+/// So a project's file is found from the unzipped contents of its document,
+/// too. A citation style provided on the command line overrides the file's.
+fn grammar(
+    matches: &clap::ArgMatches,
+    doc_input_file: &Path,
+    citation_style: CitationStyle,
+) -> Option<Grammar> {
+    let config_file = match matches.value_of("config") {
+        Some(f) => PathBuf::from(f),
+        None => {
+            let mut dirs: Vec<PathBuf> = env::current_dir().into_iter().collect();
+            if is_docx(doc_input_file) {
+                let docx = std::fs::canonicalize(doc_input_file).ok();
+                dirs.extend(
+                    docx.as_deref()
+                        .and_then(Path::parent)
+                        .map(Path::to_path_buf),
+                );
+            }
+            dirs.iter()
+                .flat_map(|d| d.ancestors())
+                .map(|d| d.join(CONFIG_FILE))
+                .find(|f| f.is_file())?
+        }
+    };
+
+    let config_input = load_or_exit(&config_file);
+
+    let citation_style =
+        Some(citation_style).filter(|_| matches.occurrences_of("citation_style") > 0);
+    match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "grammar()")), || {
+        config::grammar(&config_input, citation_style)
+    }) {
+        Ok(g) => Some(g),
+        Err(e) => {
+            eprintln!("{}", e.in_part(&config_file.to_string_lossy()));
+            process::exit(1);
+        }
+    }
+}
+
+/// Determine whether a path refers to a `.docx` file.
+fn is_docx(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => ext.eq_ignore_ascii_case("docx"),
+        None => false,
     }
 }
@@ -0,0 +1,531 @@
+//! This module contains the functionality for determining how notes are
+//! numbered.
+//!
+//! A Word document can say where note numbering starts (`w:numStart`), when it
+//! restarts (`w:numRestart`), and how the numbers look (`w:numFmt`). These
+//! settings can be in `settings.xml`, which applies to the whole document, and
+//! in the properties of each section (`w:sectPr`) in `document.xml`, which
+//! override the document's settings.
+
+use crate::{
+    diagnostic::Diagnostic,
+    numfmt::NumFmt,
+    parser::NoteKind,
+    xml::{self, Kind},
+};
+use slog::{debug, trace, warn};
+use std::ops::Range;
+
+/// An element in an input.
+///
+/// This is where the element is and, unless it is empty, where its contents
+/// are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Element {
+    range: Range<usize>,
+    contents: Option<Range<usize>>,
+}
+
+/// Find the elements with a local name (*e.g.*, `sectPr`) in an input.
+///
+/// The input can be a piece of a part (see [`xml::fragment_events`]). An
+/// element inside another with the same name (*e.g.*, the old section
+/// properties in a `w:sectPrChange`) is part of the outer one, and an element
+/// that does not end in the input is skipped.
+fn elements(input: &str, name: &str) -> Result<Vec<Element>, Diagnostic> {
+    let mut elements = Vec::new();
+    let mut open: Option<Range<usize>> = None;
+    let mut depth = 0;
+    for event in xml::fragment_events(input) {
+        let event = event?;
+        if event.name != name {
+            continue;
+        }
+        match event.kind {
+            Kind::Empty if depth == 0 => elements.push(Element {
+                range: event.range,
+                contents: None,
+            }),
+            Kind::Start => {
+                if depth == 0 {
+                    open = Some(event.range);
+                }
+                depth += 1;
+            }
+            Kind::End if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(start) = open.take() {
+                        elements.push(Element {
+                            range: start.start..event.range.end,
+                            contents: Some(start.end..event.range.start),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(elements)
+}
+
+/// Get the namespace prefix (*e.g.*, `w:`) of the element with a local name
+/// whose tag starts `input`.
+///
+/// New elements are written with the same prefix, so that they are in the
+/// same namespace however the document's xml was written.
+fn prefix<'a>(input: &'a str, name: &str) -> &'a str {
+    input
+        .trim_start_matches(['<', '/'])
+        .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .next()
+        .and_then(|q| q.strip_suffix(name))
+        .unwrap_or_default()
+}
+
+/// When note numbering restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    Continuous,
+    EachSect,
+    EachPage,
+}
+
+/// The numbering properties for one kind of note.
+///
+/// Properties that are not set fall back to those of the document and then to
+/// Word's defaults (starting at 1, never restarting, and decimal numbers for
+/// footnotes and lowercase Roman numerals for endnotes).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoteProperties {
+    pub num_start: Option<u32>,
+    pub num_restart: Option<Restart>,
+    pub num_fmt: Option<NumFmt>,
+}
+
+impl NoteProperties {
+    /// Parse the contents of a `w:footnotePr` or `w:endnotePr` element.
+    fn parse(input: &str) -> Result<NoteProperties, Diagnostic> {
+        let mut properties = NoteProperties::default();
+        for event in xml::events(input) {
+            let event = event?;
+            if !matches!(event.kind, Kind::Start | Kind::Empty) {
+                continue;
+            }
+            let val = match event.attribute("val") {
+                Some(v) => v,
+                None => continue,
+            };
+            match event.name {
+                "numStart" => properties.num_start = val.parse::<u32>().ok(),
+                "numRestart" => {
+                    properties.num_restart = Some(match val {
+                        "eachSect" => Restart::EachSect,
+                        "eachPage" => Restart::EachPage,
+                        _ => Restart::Continuous,
+                    })
+                }
+                "numFmt" => {
+                    properties.num_fmt = NumFmt::from_val(val);
+                    if properties.num_fmt.is_none() {
+                        warn!(
+                            slog_scope::logger(),
+                            "The note number format {} is not supported; using the default", val
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(properties)
+    }
+
+    /// Fill in any properties that are not set from `base`.
+    pub fn or(self, base: NoteProperties) -> NoteProperties {
+        NoteProperties {
+            num_start: self.num_start.or(base.num_start),
+            num_restart: self.num_restart.or(base.num_restart),
+            num_fmt: self.num_fmt.or(base.num_fmt),
+        }
+    }
+
+    /// The number that notes start at.
+    pub fn start(&self) -> u32 {
+        self.num_start.unwrap_or(1)
+    }
+
+    /// When note numbering restarts.
+    pub fn restart(&self) -> Restart {
+        self.num_restart.unwrap_or(Restart::Continuous)
+    }
+
+    /// How the numbers of a kind of note look.
+    pub fn format(&self, kind: NoteKind) -> NumFmt {
+        match (self.num_fmt, kind) {
+            (Some(f), _) => f,
+            (None, NoteKind::Footnote) => NumFmt::Decimal,
+            (None, NoteKind::Endnote) => NumFmt::LowerRoman,
+        }
+    }
+}
+
+/// The numbering properties for both kinds of notes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoteSettings {
+    pub footnotes: NoteProperties,
+    pub endnotes: NoteProperties,
+}
+
+impl NoteSettings {
+    /// Parse the note properties in `settings.xml` or a section's properties.
+    pub fn parse(input: &str) -> Result<NoteSettings, Diagnostic> {
+        let properties = |name: &str| match elements(input, name)?.first() {
+            Some(Element {
+                contents: Some(contents),
+                ..
+            }) => NoteProperties::parse(&input[contents.clone()]),
+            _ => Ok(NoteProperties::default()),
+        };
+
+        Ok(NoteSettings {
+            footnotes: properties("footnotePr")?,
+            endnotes: properties("endnotePr")?,
+        })
+    }
+
+    /// Fill in any properties that are not set from `base`.
+    pub fn or(self, base: NoteSettings) -> NoteSettings {
+        NoteSettings {
+            footnotes: self.footnotes.or(base.footnotes),
+            endnotes: self.endnotes.or(base.endnotes),
+        }
+    }
+
+    /// Get the properties for one kind of note.
+    pub fn get(&self, kind: NoteKind) -> NoteProperties {
+        match kind {
+            NoteKind::Footnote => self.footnotes,
+            NoteKind::Endnote => self.endnotes,
+        }
+    }
+}
+
+/// Determine the document-wide note settings.
+///
+/// These come from `settings.xml`, if the document has one.
+pub fn settings(settings_input: Option<&str>) -> Result<NoteSettings, Diagnostic> {
+    debug!(slog_scope::logger(), "Determining note settings...");
+
+    let settings = match settings_input {
+        Some(s) => NoteSettings::parse(s)?,
+        None => NoteSettings::default(),
+    };
+
+    debug!(slog_scope::logger(), "Note settings are {:?}", settings);
+    Ok(settings)
+}
+
+/// Collect the note settings of each section that ends in a chunk of
+/// `document.xml`.
+///
+/// A section's properties come at the end of the section—either in the last
+/// paragraph's properties or at the end of the body—so each `w:sectPr` in the
+/// document marks the end of another section.
+pub fn sections(doc_input: &str) -> Result<Vec<NoteSettings>, Diagnostic> {
+    let mut sections = Vec::new();
+    for sect_pr in elements(doc_input, "sectPr")? {
+        let section = match sect_pr.contents {
+            Some(contents) => NoteSettings::parse(&doc_input[contents])?,
+            None => NoteSettings::default(),
+        };
+        trace!(slog_scope::logger(), "Found section with {:?}", section);
+        sections.push(section);
+    }
+    Ok(sections)
+}
+
+/// Collect the number formats that one kind of note uses in a document.
+///
+/// Each section can have its own format, so there can be more than one.
+pub fn formats(
+    settings: &NoteSettings,
+    doc_input: &str,
+    kind: NoteKind,
+) -> Result<Vec<NumFmt>, Diagnostic> {
+    let mut formats = Vec::new();
+    let sections = sections(doc_input)?;
+    let properties: Vec<NoteProperties> = if sections.is_empty() {
+        vec![settings.get(kind)]
+    } else {
+        sections.iter().map(|s| s.or(*settings).get(kind)).collect()
+    };
+    for p in properties {
+        let format = p.format(kind);
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+
+    debug!(
+        slog_scope::logger(),
+        "{:?} number formats: {:?}", kind, formats
+    );
+    Ok(formats)
+}
+
+/// Set the number of the first footnote in `document.xml`.
+///
+/// Numbering starts according to the first section's properties, so this sets
+/// the `w:numStart` in that section's `w:footnotePr`, adding the elements as
+/// needed. A document without any section properties gets them at the end of
+/// its body.
+pub fn set_first_note(doc_input: &str, first_note: u32) -> Result<String, Diagnostic> {
+    debug!(
+        slog_scope::logger(),
+        "Setting the first footnote to {}...", first_note
+    );
+
+    let (start, end, sect_pr) = match elements(doc_input, "sectPr")?.first() {
+        Some(Element {
+            range,
+            contents: Some(contents),
+        }) => {
+            let p = prefix(&doc_input[range.start..], "sectPr");
+            let sect_pr = format!(
+                "{}{}{}",
+                &doc_input[range.start..contents.start],
+                set_num_start(&doc_input[contents.clone()], p, first_note)?,
+                &doc_input[contents.end..range.end]
+            );
+            (range.start, range.end, sect_pr)
+        }
+        Some(Element {
+            range,
+            contents: None,
+        }) => {
+            let tag = &doc_input[range.clone()];
+            let p = prefix(tag, "sectPr");
+            let sect_pr = format!(
+                "{}><{p}footnotePr>{}</{p}footnotePr></{p}sectPr>",
+                tag.trim_end_matches("/>").trim_end(),
+                num_start(p, first_note)
+            );
+            (range.start, range.end, sect_pr)
+        }
+        None => {
+            let mut body_end = None;
+            for event in xml::events(doc_input) {
+                let event = event?;
+                if event.kind == Kind::End && event.name == "body" {
+                    body_end = Some(event.range.start);
+                }
+            }
+            match body_end {
+                Some(i) => {
+                    let p = prefix(&doc_input[i..], "body");
+                    let sect_pr = format!(
+                        "<{p}sectPr><{p}footnotePr>{}</{p}footnotePr></{p}sectPr>",
+                        num_start(p, first_note)
+                    );
+                    (i, i, sect_pr)
+                }
+                None => {
+                    warn!(
+                        slog_scope::logger(),
+                        "The document has no body; not setting the first footnote"
+                    );
+                    return Ok(doc_input.to_string());
+                }
+            }
+        }
+    };
+
+    trace!(slog_scope::logger(), "New section properties: {}", sect_pr);
+    let mut doc_output = String::with_capacity(doc_input.len() + sect_pr.len());
+    doc_output.push_str(&doc_input[..start]);
+    doc_output.push_str(&sect_pr);
+    doc_output.push_str(&doc_input[end..]);
+
+    debug!(slog_scope::logger(), "First footnote set.");
+    Ok(doc_output)
+}
+
+/// The `w:numStart` element for the first footnote, with the prefix `p`.
+fn num_start(p: &str, first_note: u32) -> String {
+    format!(r#"<{p}numStart {p}val="{}"/>"#, first_note)
+}
+
+/// Set the `w:numStart` in the contents of a `w:sectPr` element, whose
+/// elements have the prefix `p`.
+///
+/// Word expects the elements in a particular order: `w:footnotePr` comes after
+/// any header and footer references, and `w:numStart` comes before
+/// `w:numRestart`.
+fn set_num_start(sect_pr: &str, p: &str, first_note: u32) -> Result<String, Diagnostic> {
+    let footnote_pr = match elements(sect_pr, "footnotePr")?.into_iter().next() {
+        Some(f) => f,
+        None => {
+            let mut i = 0;
+            for event in xml::events(sect_pr) {
+                let event = event?;
+                if matches!(event.kind, Kind::Empty | Kind::End)
+                    && matches!(event.name, "headerReference" | "footerReference")
+                {
+                    i = event.range.end;
+                }
+            }
+            return Ok(format!(
+                "{}<{p}footnotePr>{}</{p}footnotePr>{}",
+                &sect_pr[..i],
+                num_start(p, first_note),
+                &sect_pr[i..]
+            ));
+        }
+    };
+
+    let new_footnote_pr = match footnote_pr.contents {
+        Some(contents) => {
+            let old = &sect_pr[contents.clone()];
+            let (start, end) = match elements(old, "numStart")?.first() {
+                Some(n) => (n.range.start, n.range.end),
+                None => {
+                    let i = elements(old, "numRestart")?
+                        .first()
+                        .map_or(old.len(), |n| n.range.start);
+                    (i, i)
+                }
+            };
+            format!(
+                "{}{}{}{}{}",
+                &sect_pr[footnote_pr.range.start..contents.start],
+                &old[..start],
+                num_start(p, first_note),
+                &old[end..],
+                &sect_pr[contents.end..footnote_pr.range.end]
+            )
+        }
+        None => format!(
+            "<{p}footnotePr>{}</{p}footnotePr>",
+            num_start(p, first_note)
+        ),
+    };
+
+    Ok(format!(
+        "{}{}{}",
+        &sect_pr[..footnote_pr.range.start],
+        new_footnote_pr,
+        &sect_pr[footnote_pr.range.end..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_settings() {
+        let input = r#"<w:settings><w:footnotePr><w:numFmt w:val="decimal"/><w:numStart w:val="150"/><w:footnote w:id="-1"/><w:footnote w:id="0"/></w:footnotePr><w:endnotePr><w:numRestart w:val="eachSect"/></w:endnotePr></w:settings>"#;
+
+        let settings = settings(Some(input)).unwrap();
+        assert_eq!(settings.footnotes.start(), 150);
+        assert_eq!(settings.footnotes.restart(), Restart::Continuous);
+        assert_eq!(settings.endnotes.start(), 1);
+        assert_eq!(settings.endnotes.restart(), Restart::EachSect);
+        assert_eq!(
+            settings.footnotes.format(NoteKind::Footnote),
+            NumFmt::Decimal
+        );
+        assert_eq!(
+            settings.endnotes.format(NoteKind::Endnote),
+            NumFmt::LowerRoman
+        );
+
+        // Any prefix will do.
+        let input = r#"<ns0:settings><ns0:footnotePr><ns0:numStart ns0:val="7"/></ns0:footnotePr></ns0:settings>"#;
+        assert_eq!(super::settings(Some(input)).unwrap().footnotes.start(), 7);
+    }
+
+    #[test]
+    fn section_overrides() {
+        let input = r#"<w:p><w:pPr><w:sectPr><w:footnotePr><w:numRestart w:val="eachSect"/></w:footnotePr><w:pgSz w:w="12240"/></w:sectPr></w:pPr></w:p><w:p/><w:sectPr><w:footnotePr><w:numStart w:val="5"/></w:footnotePr></w:sectPr>"#;
+        let base = NoteSettings {
+            footnotes: NoteProperties {
+                num_start: Some(10),
+                num_restart: None,
+                num_fmt: Some(NumFmt::UpperLetter),
+            },
+            endnotes: NoteProperties::default(),
+        };
+
+        let sections: Vec<NoteSettings> = sections(input)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.or(base))
+            .collect();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].footnotes.start(), 10);
+        assert_eq!(sections[0].footnotes.restart(), Restart::EachSect);
+        assert_eq!(sections[1].footnotes.start(), 5);
+        assert_eq!(sections[1].footnotes.restart(), Restart::Continuous);
+
+        assert_eq!(
+            formats(&base, input, NoteKind::Footnote).unwrap(),
+            [NumFmt::UpperLetter]
+        );
+        let input =
+            r#"<w:sectPr><w:footnotePr><w:numFmt w:val="lowerRoman"/></w:footnotePr></w:sectPr>"#;
+        assert_eq!(
+            formats(&base, input, NoteKind::Footnote).unwrap(),
+            [NumFmt::LowerRoman]
+        );
+    }
+
+    #[test]
+    fn first_note() {
+        // Replace an existing start.
+        let input = r#"<w:body><w:p/><w:sectPr w:rsidR="1"><w:footnotePr><w:numFmt w:val="decimal"/><w:numStart w:val="3"/><w:numRestart w:val="eachSect"/></w:footnotePr></w:sectPr></w:body>"#;
+        assert_eq!(
+            set_first_note(input, 12).unwrap(),
+            r#"<w:body><w:p/><w:sectPr w:rsidR="1"><w:footnotePr><w:numFmt w:val="decimal"/><w:numStart w:val="12"/><w:numRestart w:val="eachSect"/></w:footnotePr></w:sectPr></w:body>"#
+        );
+
+        // Add a start before the restart.
+        let input = r#"<w:body><w:sectPr><w:footnotePr><w:numRestart w:val="eachSect"/></w:footnotePr></w:sectPr></w:body>"#;
+        assert_eq!(
+            set_first_note(input, 12).unwrap(),
+            r#"<w:body><w:sectPr><w:footnotePr><w:numStart w:val="12"/><w:numRestart w:val="eachSect"/></w:footnotePr></w:sectPr></w:body>"#
+        );
+
+        // Add footnote properties after the headers and footers, and only to
+        // the first section.
+        let input = r#"<w:body><w:p><w:pPr><w:sectPr><w:headerReference w:type="default" r:id="rId1"/><w:pgSz w:w="12240"/></w:sectPr></w:pPr></w:p><w:sectPr/></w:body>"#;
+        assert_eq!(
+            set_first_note(input, 12).unwrap(),
+            r#"<w:body><w:p><w:pPr><w:sectPr><w:headerReference w:type="default" r:id="rId1"/><w:footnotePr><w:numStart w:val="12"/></w:footnotePr><w:pgSz w:w="12240"/></w:sectPr></w:pPr></w:p><w:sectPr/></w:body>"#
+        );
+
+        // Add section properties.
+        let input = r#"<w:body><w:p/></w:body>"#;
+        assert_eq!(
+            set_first_note(input, 12).unwrap(),
+            r#"<w:body><w:p/><w:sectPr><w:footnotePr><w:numStart w:val="12"/></w:footnotePr></w:sectPr></w:body>"#
+        );
+        let input = r#"<w:body><w:p/><w:sectPr /></w:body>"#;
+        assert_eq!(
+            set_first_note(input, 12).unwrap(),
+            r#"<w:body><w:p/><w:sectPr><w:footnotePr><w:numStart w:val="12"/></w:footnotePr></w:sectPr></w:body>"#
+        );
+
+        // Use the document's prefix.
+        let input = r#"<ns0:body><ns0:p/><ns0:sectPr ns0:rsidR="1"><ns0:footnotePr><ns0:numRestart ns0:val="eachSect"/></ns0:footnotePr></ns0:sectPr></ns0:body>"#;
+        assert_eq!(
+            set_first_note(input, 12).unwrap(),
+            r#"<ns0:body><ns0:p/><ns0:sectPr ns0:rsidR="1"><ns0:footnotePr><ns0:numStart ns0:val="12"/><ns0:numRestart ns0:val="eachSect"/></ns0:footnotePr></ns0:sectPr></ns0:body>"#
+        );
+        let input = r#"<ns0:body><ns0:p/></ns0:body>"#;
+        assert_eq!(
+            set_first_note(input, 12).unwrap(),
+            r#"<ns0:body><ns0:p/><ns0:sectPr><ns0:footnotePr><ns0:numStart ns0:val="12"/></ns0:footnotePr></ns0:sectPr></ns0:body>"#
+        );
+    }
+}
@@ -0,0 +1,489 @@
+//! This module contains the functionality for numbered paragraphs.
+//!
+//! Word numbers headings (and other paragraphs) with the lists in
+//! `numbering.xml`. A paragraph is in a list through its own `w:numPr` or its
+//! style's (see [`crate::styles`]). Each level of a list has a number format, a
+//! starting number, and a text in which `%1` through `%9` stand for the current
+//! numbers of the levels (*e.g.*, "%1.%2" for "3.2"). Word does not save the
+//! numbers it displays, so they are worked out here, the way Word counts them.
+//! Cross-references to headings and paragraphs (*e.g.*, "*infra* Part II.A"
+//! or "*supra* ¶ 12") are written with those numbers.
+
+use crate::{
+    diagnostic::{self, Diagnostic},
+    numfmt::NumFmt,
+    styles::ParagraphStyle,
+    xml::{self, Kind},
+};
+use slog::{debug, trace};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+/// The number of levels in a list.
+const LEVELS: usize = 9;
+
+/// A level of a list.
+///
+/// The format is `None` for bullets and levels that display no number.
+#[derive(Debug, Clone, Default)]
+struct Level {
+    start: u32,
+    num_fmt: Option<NumFmt>,
+    text: String,
+    legal: bool,
+    style: Option<String>,
+}
+
+/// An abstract list definition (`w:abstractNum`).
+///
+/// A definition that only links to a list style (`w:numStyleLink`) takes its
+/// levels from the definition of that style (`w:styleLink`).
+#[derive(Debug, Default)]
+struct AbstractNum {
+    levels: Vec<Level>,
+    style_link: Option<String>,
+    num_style_link: Option<String>,
+}
+
+/// A list (`w:num`), which is an abstract list definition plus the starting
+/// numbers it overrides.
+#[derive(Debug, Default)]
+struct Num {
+    abstract_id: String,
+    overrides: Vec<(usize, u32)>,
+}
+
+/// The lists in `numbering.xml`.
+#[derive(Debug, Default)]
+struct Lists {
+    abstract_nums: HashMap<String, AbstractNum>,
+    nums: HashMap<String, Num>,
+}
+
+impl Lists {
+    /// Read the lists in `numbering.xml`.
+    fn read(numbering_input: &str) -> Result<Lists, Diagnostic> {
+        let mut lists = Lists::default();
+        let mut abstract_num: Option<(String, AbstractNum)> = None;
+        let mut level: Option<(usize, Level)> = None;
+        let mut num: Option<(String, Num)> = None;
+        let mut override_level: Option<usize> = None;
+
+        for event in xml::events(numbering_input) {
+            let event = event?;
+            let val = event.attribute("val");
+            let number = |v: Option<&str>| v.and_then(|v| v.parse::<usize>().ok());
+
+            match (event.kind, event.name) {
+                (Kind::Start, "abstractNum") => {
+                    let id = event.attribute("abstractNumId").unwrap_or_default();
+                    abstract_num = Some((id.to_string(), AbstractNum::default()));
+                }
+                (Kind::End, "abstractNum") => {
+                    if let Some((id, a)) = abstract_num.take() {
+                        lists.abstract_nums.insert(id, a);
+                    }
+                }
+                (Kind::Start, "num") => {
+                    let id = event.attribute("numId").unwrap_or_default();
+                    num = Some((id.to_string(), Num::default()));
+                }
+                (Kind::End, "num") => {
+                    if let Some((id, n)) = num.take() {
+                        lists.nums.insert(id, n);
+                    }
+                }
+                _ if num.is_some() => {
+                    let (_, n) = num.as_mut().unwrap();
+                    match (event.kind, event.name) {
+                        (Kind::Start | Kind::Empty, "abstractNumId") => {
+                            n.abstract_id = val.unwrap_or_default().to_string()
+                        }
+                        (Kind::Start, "lvlOverride") => {
+                            override_level = number(event.attribute("ilvl"))
+                        }
+                        (Kind::Start | Kind::Empty, "startOverride") => {
+                            if let (Some(l), Some(s)) = (override_level, number(val)) {
+                                n.overrides.push((l, s as u32));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                (Kind::Start, "lvl") if abstract_num.is_some() => {
+                    let ilvl = number(event.attribute("ilvl")).unwrap_or_default();
+                    let l = Level {
+                        num_fmt: Some(NumFmt::Decimal),
+                        ..Default::default()
+                    };
+                    level = Some((ilvl, l));
+                }
+                (Kind::End, "lvl") => {
+                    if let (Some((_, a)), Some((ilvl, l))) = (abstract_num.as_mut(), level.take()) {
+                        if ilvl < LEVELS {
+                            if a.levels.len() <= ilvl {
+                                a.levels.resize(ilvl + 1, Level::default());
+                            }
+                            a.levels[ilvl] = l;
+                        }
+                    }
+                }
+                (Kind::Start | Kind::Empty, name) if level.is_some() => {
+                    let (_, l) = level.as_mut().unwrap();
+                    match name {
+                        "start" => l.start = number(val).unwrap_or_default() as u32,
+                        "numFmt" => {
+                            l.num_fmt = match val.unwrap_or("decimal") {
+                                "bullet" | "none" => None,
+                                v => Some(NumFmt::from_val(v).unwrap_or_else(|| {
+                                    debug!(
+                                        slog_scope::logger(),
+                                        "Treating the list format {:?} as decimal", v
+                                    );
+                                    NumFmt::Decimal
+                                })),
+                            }
+                        }
+                        "lvlText" => l.text = val.unwrap_or_default().to_string(),
+                        "isLgl" => l.legal = !matches!(val, Some("0" | "false" | "off")),
+                        "pStyle" => l.style = val.map(String::from),
+                        _ => {}
+                    }
+                }
+                (Kind::Start | Kind::Empty, "styleLink") => {
+                    if let Some((_, a)) = abstract_num.as_mut() {
+                        a.style_link = val.map(String::from);
+                    }
+                }
+                (Kind::Start | Kind::Empty, "numStyleLink") => {
+                    if let Some((_, a)) = abstract_num.as_mut() {
+                        a.num_style_link = val.map(String::from);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(lists)
+    }
+
+    /// Get the abstract id and the levels of a list.
+    fn levels(&self, num_id: &str) -> Option<(&str, &[Level])> {
+        let num = self.nums.get(num_id)?;
+        let abstract_num = self.abstract_nums.get(&num.abstract_id)?;
+        match &abstract_num.num_style_link {
+            Some(link) if abstract_num.levels.is_empty() => self
+                .abstract_nums
+                .iter()
+                .find(|(_, a)| a.style_link.as_ref() == Some(link))
+                .map(|(id, a)| (id.as_str(), a.levels.as_slice())),
+            _ => Some((num.abstract_id.as_str(), abstract_num.levels.as_slice())),
+        }
+    }
+}
+
+/// The current numbers of a list's levels, and the full number of the last
+/// paragraph at each level.
+#[derive(Debug, Default)]
+struct Counters {
+    values: [Option<u32>; LEVELS],
+    starts: [Option<u32>; LEVELS],
+    full: [Option<String>; LEVELS],
+}
+
+/// A numbered paragraph.
+///
+/// This has the paragraph's number as Word displays it, without punctuation
+/// at the end or words before it (*e.g.*, "B" for "B." and "I" for "Part I"),
+/// and its number in full context (*e.g.*, "II.B" for a "B." under a "II.").
+/// Numbers written with words before them (`prefixed`) need a field that
+/// leaves those words out. A paragraph with an outline level is a heading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Numbered {
+    pub number: String,
+    pub full: String,
+    pub heading: bool,
+    pub prefixed: bool,
+    /// Where a bookmark around the paragraph's contents starts and ends.
+    pub bookmark: Range<usize>,
+}
+
+/// A paragraph that is being read.
+#[derive(Debug, Default)]
+struct OpenParagraph<'a> {
+    in_properties: bool,
+    in_change: bool,
+    style: Option<&'a str>,
+    outline_level: Option<usize>,
+    num_id: Option<&'a str>,
+    ilvl: Option<usize>,
+    numbered: Option<usize>,
+}
+
+/// Find the numbered paragraphs in `document.xml`, with their numbers.
+///
+/// The paragraphs are numbered in the order they appear, with the lists in
+/// `numbering_input` and the paragraph styles from `paragraph_styles`. Each
+/// list counts on from where the last paragraph with the same abstract list
+/// definition left off, unless the list overrides the starting number. A
+/// paragraph restarts the levels below its own. Only the paragraph's own
+/// properties count, not those of a tracked change (`w:pPrChange`).
+pub fn paragraphs(
+    document: &str,
+    paragraph_styles: &HashMap<String, ParagraphStyle>,
+    numbering_input: Option<&str>,
+) -> Result<Vec<Numbered>, Diagnostic> {
+    let lists = match numbering_input {
+        Some(n) => Lists::read(n).map_err(|e| e.in_part(diagnostic::NUMBERING_PART))?,
+        None => {
+            debug!(slog_scope::logger(), "No numbering; no numbered paragraphs");
+            return Ok(Vec::new());
+        }
+    };
+
+    let mut numbered: Vec<Numbered> = Vec::new();
+    let mut counters: HashMap<&str, Counters> = HashMap::new();
+    let mut used_nums: HashSet<&str> = HashSet::new();
+    let mut open: Vec<OpenParagraph> = Vec::new();
+
+    for event in xml::events(document) {
+        let event = event?;
+        let number = || event.attribute("val").and_then(|v| v.parse::<usize>().ok());
+
+        match (event.kind, event.name) {
+            (Kind::Start, "p") => open.push(OpenParagraph::default()),
+            (Kind::End, "p") => {
+                if let Some(i) = open.pop().and_then(|p| p.numbered) {
+                    numbered[i].bookmark.end = event.range.start;
+                }
+            }
+            (Kind::Start, "pPr") => {
+                if let Some(p) = open.last_mut() {
+                    p.in_properties = true;
+                }
+            }
+            (Kind::Start, "pPrChange") => {
+                if let Some(p) = open.last_mut() {
+                    p.in_change = true;
+                }
+            }
+            (Kind::End, "pPrChange") => {
+                if let Some(p) = open.last_mut() {
+                    p.in_change = false;
+                }
+            }
+            (Kind::Start | Kind::Empty, name) => {
+                let p = match open.last_mut() {
+                    Some(p) if p.in_properties && !p.in_change => p,
+                    _ => continue,
+                };
+                match name {
+                    "pStyle" => p.style = event.attribute("val"),
+                    "outlineLvl" => p.outline_level = number(),
+                    "numId" => p.num_id = event.attribute("val"),
+                    "ilvl" => p.ilvl = number(),
+                    _ => {}
+                }
+            }
+            (Kind::End, "pPr") => {
+                let p = match open.last_mut() {
+                    Some(p) if p.in_properties => p,
+                    _ => continue,
+                };
+                p.in_properties = false;
+
+                // Find the paragraph's list and level
+                let style = p.style.and_then(|s| paragraph_styles.get(s));
+                let num_id = match p.num_id.or(style.and_then(|s| s.num_id.as_deref())) {
+                    Some(n) if n != "0" => n,
+                    _ => continue,
+                };
+                let (abstract_id, levels) = match lists.levels(num_id) {
+                    Some(l) => l,
+                    None => continue,
+                };
+                let ilvl = p
+                    .ilvl
+                    .or(style.and_then(|s| s.ilvl))
+                    .or_else(|| {
+                        levels
+                            .iter()
+                            .position(|l| l.style.is_some() && l.style.as_deref() == p.style)
+                    })
+                    .unwrap_or_default();
+                let level = match levels.get(ilvl) {
+                    Some(l) => l,
+                    None => continue,
+                };
+
+                // Count the paragraph
+                let c = counters.entry(abstract_id).or_default();
+                if used_nums.insert(num_id) {
+                    for (l, start) in &lists.nums[num_id].overrides {
+                        if *l < LEVELS {
+                            c.values[*l] = None;
+                            c.starts[*l] = Some(*start);
+                        }
+                    }
+                }
+                let start = |c: &Counters, l: usize| {
+                    c.starts[l]
+                        .or(levels.get(l).map(|l| l.start))
+                        .unwrap_or_default()
+                };
+                c.values[ilvl] = Some(match c.values[ilvl] {
+                    Some(v) => v + 1,
+                    None => start(c, ilvl),
+                });
+                for l in ilvl + 1..LEVELS {
+                    c.values[l] = None;
+                    c.full[l] = None;
+                }
+
+                // Work out the number that Word displays
+                if level.num_fmt.is_none() {
+                    c.full[ilvl] = None;
+                    continue;
+                }
+                let mut text = level.text.clone();
+                for l in (0..LEVELS).rev() {
+                    let placeholder = format!("%{}", l + 1);
+                    if !text.contains(&placeholder) {
+                        continue;
+                    }
+                    let value = c.values[l].unwrap_or_else(|| start(c, l));
+                    let num_fmt = match levels.get(l).and_then(|l| l.num_fmt) {
+                        Some(_) if level.legal => Some(NumFmt::Decimal),
+                        f => f,
+                    };
+                    let formatted = num_fmt.map(|f| f.format(value)).unwrap_or_default();
+                    text = text.replace(&placeholder, &formatted);
+                }
+
+                // Words before the number (*e.g.*, "Part I") are not part of
+                // it.
+                let prefix = level.text.find('%').map_or("", |i| &level.text[..i]);
+                let prefixed = prefix.ends_with(char::is_whitespace);
+                if prefixed {
+                    text = text[prefix.len()..].to_string();
+                }
+                let own = text.trim().trim_end_matches('.').trim_end().to_string();
+                if own.is_empty() {
+                    c.full[ilvl] = None;
+                    continue;
+                }
+
+                // A level whose text leaves out the levels above it is
+                // preceded by their number in full context
+                let full = match ilvl.checked_sub(1).and_then(|l| c.full[l].as_ref()) {
+                    Some(parent) if !level.text.contains("%1") => {
+                        let separator = if own.starts_with('(') { "" } else { "." };
+                        format!("{}{}{}", parent, separator, own)
+                    }
+                    _ => own.clone(),
+                };
+                c.full[ilvl] = Some(full.clone());
+
+                let outline_level = p
+                    .outline_level
+                    .or(style.and_then(|s| s.outline_level))
+                    .filter(|l| *l < LEVELS);
+                trace!(
+                    slog_scope::logger(),
+                    "Paragraph {} (full {}, outline level {:?}) at {}",
+                    own,
+                    full,
+                    outline_level,
+                    event.range.end
+                );
+                p.numbered = Some(numbered.len());
+                numbered.push(Numbered {
+                    number: own,
+                    full,
+                    heading: outline_level.is_some(),
+                    prefixed,
+                    bookmark: event.range.end..event.range.end,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    debug!(
+        slog_scope::logger(),
+        "Found {} numbered paragraphs",
+        numbered.len()
+    );
+    Ok(numbered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::styles;
+
+    const NUMBERING: &str = r#"<w:numbering><w:abstractNum w:abstractNumId="0"><w:lvl w:ilvl="0"><w:start w:val="1"/><w:numFmt w:val="upperRoman"/><w:lvlText w:val="%1."/><w:pStyle w:val="Heading1"/></w:lvl><w:lvl w:ilvl="1"><w:start w:val="1"/><w:numFmt w:val="upperLetter"/><w:lvlText w:val="%2."/><w:pStyle w:val="Heading2"/></w:lvl><w:lvl w:ilvl="2"><w:start w:val="1"/><w:numFmt w:val="decimal"/><w:lvlText w:val="%3."/><w:pStyle w:val="Heading3"/></w:lvl></w:abstractNum><w:abstractNum w:abstractNumId="1"><w:lvl w:ilvl="0"><w:start w:val="1"/><w:numFmt w:val="decimal"/><w:lvlText w:val="Section %1"/></w:lvl><w:lvl w:ilvl="1"><w:start w:val="1"/><w:numFmt w:val="decimal"/><w:lvlText w:val="%1.%2"/></w:lvl></w:abstractNum><w:abstractNum w:abstractNumId="2"><w:lvl w:ilvl="0"><w:numFmt w:val="bullet"/><w:lvlText w:val="•"/></w:lvl></w:abstractNum><w:num w:numId="1"><w:abstractNumId w:val="0"/></w:num><w:num w:numId="2"><w:abstractNumId w:val="1"/><w:lvlOverride w:ilvl="0"><w:startOverride w:val="3"/></w:lvlOverride></w:num><w:num w:numId="3"><w:abstractNumId w:val="2"/></w:num></w:numbering>"#;
+
+    const STYLES: &str = r#"<w:styles><w:style w:type="paragraph" w:styleId="Heading1"><w:name w:val="heading 1"/><w:pPr><w:numPr><w:numId w:val="1"/></w:numPr><w:outlineLvl w:val="0"/></w:pPr></w:style><w:style w:type="paragraph" w:styleId="Heading2"><w:name w:val="heading 2"/><w:basedOn w:val="Heading1"/><w:pPr><w:outlineLvl w:val="1"/></w:pPr></w:style></w:styles>"#;
+
+    fn paragraph(properties: &str, text: &str) -> String {
+        format!(
+            "<w:p><w:pPr>{}</w:pPr><w:r><w:t>{}</w:t></w:r></w:p>",
+            properties, text
+        )
+    }
+
+    #[test]
+    fn heading_numbers() {
+        let heading = |level: u32| format!(r#"<w:pStyle w:val="Heading{}"/>"#, level);
+        let document = [
+            paragraph(&heading(1), "Introduction"),
+            paragraph(&heading(1), "Background"),
+            paragraph(&heading(2), "History"),
+            paragraph(
+                r#"<w:pStyle w:val="Heading2"/><w:pPrChange><w:pPr><w:pStyle w:val="Heading1"/></w:pPr></w:pPrChange>"#,
+                "Practice",
+            ),
+            paragraph(r#"<w:numPr><w:numId w:val="3"/></w:numPr>"#, "Bullet"),
+            paragraph(r#"<w:numPr><w:numId w:val="2"/></w:numPr>"#, "Scope"),
+            paragraph(
+                r#"<w:numPr><w:ilvl w:val="1"/><w:numId w:val="2"/></w:numPr>"#,
+                "Terms",
+            ),
+            paragraph(&heading(1), "Analysis"),
+            paragraph(&heading(2), "Text"),
+        ]
+        .concat();
+
+        let styles = styles::paragraph_styles(Some(STYLES)).unwrap();
+        let numbered = paragraphs(&document, &styles, Some(NUMBERING)).unwrap();
+        let numbers: Vec<(&str, &str, bool, bool)> = numbered
+            .iter()
+            .map(|n| (n.number.as_str(), n.full.as_str(), n.heading, n.prefixed))
+            .collect();
+        assert_eq!(
+            numbers,
+            [
+                ("I", "I", true, false),
+                ("II", "II", true, false),
+                ("A", "II.A", true, false),
+                ("B", "II.B", true, false),
+                ("3", "3", false, true),
+                ("3.1", "3.1", false, false),
+                ("III", "III", true, false),
+                ("A", "III.A", true, false),
+            ]
+        );
+
+        // The bookmark goes around the paragraph's contents
+        let first = &numbered[0].bookmark;
+        assert_eq!(
+            &document[first.clone()],
+            "<w:r><w:t>Introduction</w:t></w:r>"
+        );
+
+        assert!(paragraphs(&document, &styles, None).unwrap().is_empty());
+    }
+}
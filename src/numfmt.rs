@@ -0,0 +1,230 @@
+//! This module contains the functionality for note number formats.
+//!
+//! Word can display note numbers in formats other than decimal numbers (the
+//! `w:numFmt` of a `w:footnotePr` or `w:endnotePr`). Cross-references in notes
+//! are written in those formats, and the fields' results should be, too.
+
+/// The number formats for notes that AutoCref supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumFmt {
+    Decimal,
+    DecimalZero,
+    DecimalFullWidth,
+    LowerRoman,
+    UpperRoman,
+    LowerLetter,
+    UpperLetter,
+    Chicago,
+    HindiNumbers,
+}
+
+/// The symbols used by the Chicago Manual of Style format, in order.
+const CHICAGO_SYMBOLS: [char; 4] = ['*', '†', '‡', '§'];
+
+/// The Roman numerals and their values, largest first.
+const ROMAN_NUMERALS: [(u32, &str); 13] = [
+    (1000, "m"),
+    (900, "cm"),
+    (500, "d"),
+    (400, "cd"),
+    (100, "c"),
+    (90, "xc"),
+    (50, "l"),
+    (40, "xl"),
+    (10, "x"),
+    (9, "ix"),
+    (5, "v"),
+    (4, "iv"),
+    (1, "i"),
+];
+
+impl NumFmt {
+    /// Get the format for a `w:numFmt` value.
+    ///
+    /// This returns `None` for formats that AutoCref does not support.
+    pub fn from_val(val: &str) -> Option<NumFmt> {
+        match val {
+            "decimal" => Some(NumFmt::Decimal),
+            "decimalZero" => Some(NumFmt::DecimalZero),
+            "decimalFullWidth" | "decimalFullWidth2" => Some(NumFmt::DecimalFullWidth),
+            "lowerRoman" => Some(NumFmt::LowerRoman),
+            "upperRoman" => Some(NumFmt::UpperRoman),
+            "lowerLetter" => Some(NumFmt::LowerLetter),
+            "upperLetter" => Some(NumFmt::UpperLetter),
+            "chicago" => Some(NumFmt::Chicago),
+            "hindiNumbers" | "arabicIndic" => Some(NumFmt::HindiNumbers),
+            _ => None,
+        }
+    }
+
+    /// Format a number the way Word displays it.
+    ///
+    /// Numbers that a format cannot represent (*e.g.*, 0 in Roman numerals)
+    /// are formatted as decimal numbers.
+    pub fn format(&self, number: u32) -> String {
+        match self {
+            NumFmt::Decimal => number.to_string(),
+            NumFmt::DecimalZero => format!("{:02}", number),
+            NumFmt::DecimalFullWidth => map_digits(number, '\u{FF10}'),
+            NumFmt::HindiNumbers => map_digits(number, '\u{0660}'),
+            NumFmt::LowerRoman | NumFmt::UpperRoman if (1..4000).contains(&number) => {
+                let mut remaining = number;
+                let mut output = String::new();
+                for (value, numeral) in ROMAN_NUMERALS {
+                    while remaining >= value {
+                        output.push_str(numeral);
+                        remaining -= value;
+                    }
+                }
+                match self {
+                    NumFmt::UpperRoman => output.to_uppercase(),
+                    _ => output,
+                }
+            }
+            NumFmt::LowerLetter | NumFmt::UpperLetter if number > 0 => {
+                let base = match self {
+                    NumFmt::UpperLetter => b'A',
+                    _ => b'a',
+                };
+                let letter = (base + ((number - 1) % 26) as u8) as char;
+                letter.to_string().repeat(((number - 1) / 26 + 1) as usize)
+            }
+            NumFmt::Chicago if number > 0 => {
+                let symbol = CHICAGO_SYMBOLS[((number - 1) % 4) as usize];
+                symbol.to_string().repeat(((number - 1) / 4 + 1) as usize)
+            }
+            _ => number.to_string(),
+        }
+    }
+
+    /// Parse a number written in this format.
+    ///
+    /// This returns `None` if the text is not a number in this format.
+    pub fn parse(&self, text: &str) -> Option<u32> {
+        let number = match self {
+            NumFmt::Decimal | NumFmt::DecimalZero => text.parse::<u32>().ok()?,
+            NumFmt::DecimalFullWidth => unmap_digits(text, '\u{FF10}')?,
+            NumFmt::HindiNumbers => unmap_digits(text, '\u{0660}')?,
+            NumFmt::LowerRoman | NumFmt::UpperRoman => {
+                let lower = text.to_lowercase();
+                let mut remaining = lower.as_str();
+                let mut number = 0;
+                for (value, numeral) in ROMAN_NUMERALS {
+                    while let Some(rest) = remaining.strip_prefix(numeral) {
+                        number += value;
+                        remaining = rest;
+                    }
+                }
+                if !remaining.is_empty() {
+                    return None;
+                }
+                number
+            }
+            NumFmt::LowerLetter | NumFmt::UpperLetter | NumFmt::Chicago => {
+                let first = text.chars().next()?;
+                if !text.chars().all(|c| c == first) {
+                    return None;
+                }
+                let position = match self {
+                    NumFmt::Chicago => CHICAGO_SYMBOLS.iter().position(|s| *s == first)? as u32,
+                    _ if first.is_ascii_alphabetic() => {
+                        (first.to_ascii_lowercase() as u8 - b'a') as u32
+                    }
+                    _ => return None,
+                };
+                let cycle = match self {
+                    NumFmt::Chicago => 4,
+                    _ => 26,
+                };
+                (text.chars().count() as u32 - 1) * cycle + position + 1
+            }
+        };
+
+        // Only accept the canonical way of writing the number (*e.g.*, not
+        // "iiii" for 4).
+        if self.format(number) == text {
+            Some(number)
+        } else {
+            None
+        }
+    }
+
+    /// A regex pattern that matches the numbers in this format.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            NumFmt::Decimal | NumFmt::DecimalZero => "[0-9]{1,9}",
+            NumFmt::DecimalFullWidth => "[０-９]{1,9}",
+            NumFmt::HindiNumbers => "[٠-٩]{1,9}",
+            NumFmt::LowerRoman => r"[ivxlcdm]{1,15}\b",
+            NumFmt::UpperRoman => r"[IVXLCDM]{1,15}\b",
+            NumFmt::LowerLetter => r"[a-z]{1,9}\b",
+            NumFmt::UpperLetter => r"[A-Z]{1,9}\b",
+            NumFmt::Chicago => "[*†‡§]{1,9}",
+        }
+    }
+}
+
+/// Write a number's decimal digits using the digits starting at `zero`.
+fn map_digits(number: u32, zero: char) -> String {
+    number
+        .to_string()
+        .chars()
+        .map(|d| char::from_u32(zero as u32 + d.to_digit(10).unwrap()).unwrap())
+        .collect()
+}
+
+/// Read a number written with the digits starting at `zero`.
+fn unmap_digits(text: &str, zero: char) -> Option<u32> {
+    let decimal: Option<String> = text
+        .chars()
+        .map(|c| match (c as u32).checked_sub(zero as u32) {
+            Some(d) if d < 10 => char::from_digit(d, 10),
+            _ => None,
+        })
+        .collect();
+    decimal?.parse::<u32>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_numbers() {
+        assert_eq!(NumFmt::Decimal.format(12), "12");
+        assert_eq!(NumFmt::DecimalZero.format(7), "07");
+        assert_eq!(NumFmt::LowerRoman.format(14), "xiv");
+        assert_eq!(NumFmt::UpperRoman.format(1994), "MCMXCIV");
+        assert_eq!(NumFmt::LowerLetter.format(3), "c");
+        assert_eq!(NumFmt::UpperLetter.format(28), "BB");
+        assert_eq!(NumFmt::Chicago.format(2), "†");
+        assert_eq!(NumFmt::Chicago.format(6), "††");
+        assert_eq!(NumFmt::HindiNumbers.format(12), "١٢");
+        assert_eq!(NumFmt::DecimalFullWidth.format(3), "３");
+        assert_eq!(NumFmt::LowerRoman.format(0), "0");
+    }
+
+    #[test]
+    fn parse_numbers() {
+        let formats = [
+            NumFmt::Decimal,
+            NumFmt::DecimalZero,
+            NumFmt::DecimalFullWidth,
+            NumFmt::LowerRoman,
+            NumFmt::UpperRoman,
+            NumFmt::LowerLetter,
+            NumFmt::UpperLetter,
+            NumFmt::Chicago,
+            NumFmt::HindiNumbers,
+        ];
+        for format in formats {
+            for number in 1..200 {
+                assert_eq!(format.parse(&format.format(number)), Some(number));
+            }
+        }
+
+        assert_eq!(NumFmt::LowerRoman.parse("iiii"), None);
+        assert_eq!(NumFmt::LowerLetter.parse("that"), None);
+        assert_eq!(NumFmt::Chicago.parse("*†"), None);
+    }
+}
@@ -0,0 +1,665 @@
+//! This module contains the functionality for reading and writing `.docx`
+//! packages.
+//!
+//! A `.docx` file is a zip archive of mostly `.xml` files (called "parts").
+//! AutoCref only changes a few of those parts. Every other entry is copied into
+//! the new package as is—in the same order and with the same compression—so
+//! that the output differs from the input only where markup was added.
+//!
+//! The parts AutoCref needs are not always in the same place. The package's
+//! relationships (`_rels/.rels` and `word/_rels/document.xml.rels`) and content
+//! types (`[Content_Types].xml`) say where the main document, footnotes, and
+//! endnotes actually are. This module contains a small model of those, too.
+
+use crate::diagnostic::{Diagnostic, Kind};
+use lazy_static::lazy_static;
+use regex::Regex;
+use slog::{debug, trace, warn};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read, Write},
+};
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+/// The name of the package's content-types part.
+pub const CONTENT_TYPES_PART: &str = "[Content_Types].xml";
+
+/// The conventional path of the main document part.
+const DEFAULT_DOCUMENT_PART: &str = "word/document.xml";
+
+/// The bases of relationship types, both transitional and strict.
+const RELATIONSHIP_BASES: [&str; 2] = [
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships/",
+    "http://purl.oclc.org/ooxml/officeDocument/relationships/",
+];
+
+/// Content types of main-document parts.
+const DOCUMENT_CONTENT_TYPES: [&str; 4] = [
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.template.main+xml",
+    "application/vnd.ms-word.document.macroEnabled.main+xml",
+    "application/vnd.ms-word.template.macroEnabledTemplate.main+xml",
+];
+
+/// Content type of footnotes parts.
+const FOOTNOTES_CONTENT_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.footnotes+xml";
+
+/// Content type of endnotes parts.
+const ENDNOTES_CONTENT_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.endnotes+xml";
+
+/// Content type of settings parts.
+const SETTINGS_CONTENT_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.settings+xml";
+
+/// Content type of styles parts.
+const STYLES_CONTENT_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml";
+
+/// Content type of numbering parts.
+const NUMBERING_CONTENT_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml";
+
+lazy_static! {
+    static ref RE_RELATIONSHIP: Regex = Regex::new(r#"<(?:\w+:)?Relationship\b[^>]*>"#).unwrap();
+    static ref RE_DEFAULT: Regex = Regex::new(r#"<(?:\w+:)?Default\b[^>]*>"#).unwrap();
+    static ref RE_OVERRIDE: Regex = Regex::new(r#"<(?:\w+:)?Override\b[^>]*>"#).unwrap();
+    static ref RE_ATTRIBUTE: Regex =
+        Regex::new(r#"([\w:.-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+}
+
+/// An opened `.docx` package.
+///
+/// The package keeps the original archive around for copying unchanged
+/// entries, along with any replacement contents for the parts that AutoCref
+/// has changed.
+pub struct Package<'a> {
+    archive: ZipArchive<Cursor<&'a [u8]>>,
+    replacements: HashMap<String, String>,
+}
+
+impl<'a> Package<'a> {
+    /// Open a package from the bytes of a `.docx` file.
+    pub fn open(input: &'a [u8]) -> Result<Package<'a>, Diagnostic> {
+        debug!(slog_scope::logger(), "Opening package...");
+
+        match ZipArchive::new(Cursor::new(input)) {
+            Ok(archive) => {
+                debug!(
+                    slog_scope::logger(),
+                    "Package opened with {} entries.",
+                    archive.len()
+                );
+                Ok(Package {
+                    archive,
+                    replacements: HashMap::new(),
+                })
+            }
+            Err(e) => {
+                let err_msg = format!("error opening the .docx package—{}", e);
+                Err(Diagnostic::error(Kind::Package, err_msg))
+            }
+        }
+    }
+
+    /// Read a part of the package into a string.
+    ///
+    /// Part names are the paths of the entries within the archive (*e.g.*,
+    /// `word/document.xml`). A missing part is not an error; it returns
+    /// `None`.
+    pub fn read_part(&mut self, name: &str) -> Result<Option<String>, Diagnostic> {
+        if let Some(contents) = self.replacements.get(name) {
+            return Ok(Some(contents.clone()));
+        }
+
+        // Part names are case-insensitive, but zip entry names are not.
+        let entry_name = match self.archive.file_names().find(|n| *n == name).or_else(|| {
+            self.archive
+                .file_names()
+                .find(|n| n.eq_ignore_ascii_case(name))
+        }) {
+            Some(n) => n.to_string(),
+            None => {
+                trace!(slog_scope::logger(), "Part {} not found", name);
+                return Ok(None);
+            }
+        };
+
+        let mut file = match self.archive.by_name(&entry_name) {
+            Ok(f) => f,
+            Err(zip::result::ZipError::FileNotFound) => {
+                trace!(slog_scope::logger(), "Part {} not found", name);
+                return Ok(None);
+            }
+            Err(e) => {
+                let err_msg = format!("error reading the part {}—{}", name, e);
+                return Err(Diagnostic::error(Kind::Package, err_msg).in_part(name));
+            }
+        };
+
+        let mut contents = String::with_capacity(file.size() as usize);
+        match file.read_to_string(&mut contents) {
+            Ok(_) => {
+                trace!(slog_scope::logger(), "Part {} read", name);
+                Ok(Some(contents))
+            }
+            Err(e) => {
+                let err_msg = format!("error reading the part {}—{}", name, e);
+                Err(Diagnostic::error(Kind::Package, err_msg).in_part(name))
+            }
+        }
+    }
+
+    /// Replace the contents of a part.
+    ///
+    /// The new contents are written when the package is written.
+    pub fn replace_part(&mut self, name: &str, contents: String) {
+        trace!(slog_scope::logger(), "Replacing part {}", name);
+        self.replacements.insert(name.to_string(), contents);
+    }
+
+    /// Write the package into a new `.docx` file.
+    ///
+    /// Entries are written in their original order. Replaced parts are
+    /// compressed with the same method and carry the same timestamp and
+    /// permissions as the originals. Every other entry is copied without being
+    /// decompressed.
+    pub fn write(mut self) -> Result<Vec<u8>, Diagnostic> {
+        debug!(slog_scope::logger(), "Writing package...");
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for i in 0..self.archive.len() {
+            let file = match self.archive.by_index_raw(i) {
+                Ok(f) => f,
+                Err(e) => {
+                    let err_msg = format!("error reading package entry {}—{}", i, e);
+                    return Err(Diagnostic::error(Kind::Package, err_msg));
+                }
+            };
+
+            let replacement = self
+                .replacements
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(file.name()));
+            let result = match replacement {
+                Some((_, contents)) => {
+                    trace!(slog_scope::logger(), "Writing new part {}", file.name());
+                    let mut options = FileOptions::default()
+                        .compression_method(file.compression())
+                        .last_modified_time(file.last_modified());
+                    if let Some(mode) = file.unix_mode() {
+                        options = options.unix_permissions(mode);
+                    }
+                    let name = file.name().to_string();
+                    drop(file);
+
+                    writer
+                        .start_file(name, options)
+                        .and_then(|_| Ok(writer.write_all(contents.as_bytes())?))
+                }
+                None => {
+                    trace!(slog_scope::logger(), "Copying part {}", file.name());
+                    writer.raw_copy_file(file)
+                }
+            };
+
+            if let Err(e) = result {
+                let err_msg = format!("error writing package entry {}—{}", i, e);
+                return Err(Diagnostic::error(Kind::Package, err_msg));
+            }
+        }
+
+        match writer.finish() {
+            Ok(cursor) => {
+                debug!(slog_scope::logger(), "Package written.");
+                Ok(cursor.into_inner())
+            }
+            Err(e) => {
+                let err_msg = format!("error writing the .docx package—{}", e);
+                Err(Diagnostic::error(Kind::Package, err_msg))
+            }
+        }
+    }
+}
+
+/// A relationship from one part (or the package itself) to another.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Relationship {
+    pub id: String,
+    pub rel_type: String,
+    pub target: String,
+    pub external: bool,
+}
+
+impl Relationship {
+    /// Determine whether the relationship has the given type (*e.g.*,
+    /// "footnotes"), in either its transitional or strict form.
+    pub fn is_type(&self, name: &str) -> bool {
+        RELATIONSHIP_BASES
+            .iter()
+            .any(|base| self.rel_type.strip_prefix(base) == Some(name))
+    }
+}
+
+/// The content types of a package's parts.
+///
+/// Parts get their content type either from an override for that specific
+/// part or from a default for the part's extension.
+#[derive(Debug, Default)]
+pub struct ContentTypes {
+    defaults: HashMap<String, String>,
+    overrides: HashMap<String, String>,
+}
+
+impl ContentTypes {
+    /// Parse the contents of `[Content_Types].xml`.
+    pub fn parse(input: &str) -> ContentTypes {
+        let mut content_types = ContentTypes::default();
+
+        for mat in RE_DEFAULT.find_iter(input) {
+            let attributes = attributes(mat.as_str());
+            if let (Some(ext), Some(content_type)) =
+                (attributes.get("Extension"), attributes.get("ContentType"))
+            {
+                content_types
+                    .defaults
+                    .insert(ext.to_ascii_lowercase(), content_type.clone());
+            }
+        }
+
+        for mat in RE_OVERRIDE.find_iter(input) {
+            let attributes = attributes(mat.as_str());
+            if let (Some(part), Some(content_type)) =
+                (attributes.get("PartName"), attributes.get("ContentType"))
+            {
+                content_types.overrides.insert(
+                    part.trim_start_matches('/').to_ascii_lowercase(),
+                    content_type.clone(),
+                );
+            }
+        }
+
+        content_types
+    }
+
+    /// Get the content type of a part.
+    pub fn content_type(&self, part: &str) -> Option<&str> {
+        let part = part.to_ascii_lowercase();
+        if let Some(content_type) = self.overrides.get(&part) {
+            return Some(content_type);
+        }
+        let ext = part.rsplit_once('.')?.1;
+        self.defaults.get(ext).map(|c| c.as_str())
+    }
+
+    /// Find the first part with an override for one of the given content
+    /// types.
+    fn find_override(&self, content_types: &[&str]) -> Option<String> {
+        let mut parts: Vec<&String> = self
+            .overrides
+            .iter()
+            .filter(|(_, c)| content_types.contains(&c.as_str()))
+            .map(|(p, _)| p)
+            .collect();
+        parts.sort();
+        parts.first().map(|p| p.to_string())
+    }
+}
+
+/// The locations of the parts of a Word document that AutoCref works on.
+#[derive(Debug, PartialEq, Eq)]
+pub struct WordParts {
+    pub document: String,
+    pub footnotes: Option<String>,
+    pub endnotes: Option<String>,
+    pub settings: Option<String>,
+    pub styles: Option<String>,
+    pub numbering: Option<String>,
+}
+
+/// Locate the main document, footnotes, endnotes, settings, styles, and
+/// numbering parts.
+///
+/// The main document is the target of the package's `officeDocument`
+/// relationship. The others are the targets of the main document's `footnotes`,
+/// `endnotes`, `settings`, `styles`, and `numbering` relationships. Content
+/// types are used to check those targets and to find parts when a relationship
+/// is missing. If neither says anything, this falls back to the conventional
+/// `word/` paths.
+///
+/// The `read` function reads a part by name, returning `None` if it does not
+/// exist. This allows the same logic to work on both a [`Package`] and an
+/// unzipped directory.
+pub fn locate_parts<F>(mut read: F) -> Result<WordParts, Diagnostic>
+where
+    F: FnMut(&str) -> Result<Option<String>, Diagnostic>,
+{
+    debug!(slog_scope::logger(), "Locating document parts...");
+
+    let content_types = match read(CONTENT_TYPES_PART)? {
+        Some(c) => ContentTypes::parse(&c),
+        None => {
+            warn!(slog_scope::logger(), "The package has no content types");
+            ContentTypes::default()
+        }
+    };
+
+    // Find the main document
+    let package_rels = match read(&relationships_part(""))? {
+        Some(r) => parse_relationships("", &r),
+        None => Vec::new(),
+    };
+    let document = match find_target(&package_rels, "officeDocument") {
+        Some(d) => d,
+        None => match content_types.find_override(&DOCUMENT_CONTENT_TYPES) {
+            Some(d) => d,
+            None => DEFAULT_DOCUMENT_PART.to_string(),
+        },
+    };
+    match content_types.content_type(&document) {
+        Some(c) if !DOCUMENT_CONTENT_TYPES.contains(&c) => warn!(
+            slog_scope::logger(),
+            "The main document {} has the unexpected content type {}", document, c
+        ),
+        _ => {}
+    }
+
+    // Find the notes and settings
+    let document_rels = match read(&relationships_part(&document))? {
+        Some(r) => parse_relationships(&document, &r),
+        None => Vec::new(),
+    };
+    let footnotes = locate_related(
+        &mut read,
+        &content_types,
+        &document,
+        &document_rels,
+        "footnotes",
+        FOOTNOTES_CONTENT_TYPE,
+    )?;
+    let endnotes = locate_related(
+        &mut read,
+        &content_types,
+        &document,
+        &document_rels,
+        "endnotes",
+        ENDNOTES_CONTENT_TYPE,
+    )?;
+    let settings = locate_related(
+        &mut read,
+        &content_types,
+        &document,
+        &document_rels,
+        "settings",
+        SETTINGS_CONTENT_TYPE,
+    )?;
+    let styles = locate_related(
+        &mut read,
+        &content_types,
+        &document,
+        &document_rels,
+        "styles",
+        STYLES_CONTENT_TYPE,
+    )?;
+    let numbering = locate_related(
+        &mut read,
+        &content_types,
+        &document,
+        &document_rels,
+        "numbering",
+        NUMBERING_CONTENT_TYPE,
+    )?;
+
+    let parts = WordParts {
+        document,
+        footnotes,
+        endnotes,
+        settings,
+        styles,
+        numbering,
+    };
+    debug!(slog_scope::logger(), "Document parts located: {:?}", parts);
+    Ok(parts)
+}
+
+/// Locate a part related to the main document (footnotes, endnotes, settings,
+/// styles, or numbering).
+fn locate_related<F>(
+    read: &mut F,
+    content_types: &ContentTypes,
+    document: &str,
+    document_rels: &[Relationship],
+    rel_type: &str,
+    content_type: &str,
+) -> Result<Option<String>, Diagnostic>
+where
+    F: FnMut(&str) -> Result<Option<String>, Diagnostic>,
+{
+    if let Some(part) = find_target(document_rels, rel_type) {
+        match content_types.content_type(&part) {
+            Some(c) if c != content_type => {
+                warn!(
+                    slog_scope::logger(),
+                    "Ignoring the {} part {} with the content type {}", rel_type, part, c
+                );
+                return Ok(None);
+            }
+            _ => return Ok(Some(part)),
+        }
+    }
+
+    // Without a relationship, a document with relationships has no such part.
+    if !document_rels.is_empty() {
+        return Ok(None);
+    }
+
+    if let Some(part) = content_types.find_override(&[content_type]) {
+        return Ok(Some(part));
+    }
+
+    let part = resolve_target(document, &format!("{}.xml", rel_type));
+    match read(&part)? {
+        Some(_) => Ok(Some(part)),
+        None => Ok(None),
+    }
+}
+
+/// Find the resolved target of the first internal relationship of a type.
+fn find_target(relationships: &[Relationship], rel_type: &str) -> Option<String> {
+    relationships
+        .iter()
+        .find(|r| !r.external && r.is_type(rel_type))
+        .map(|r| r.target.clone())
+}
+
+/// Get the name of the relationships part for a source part.
+///
+/// The relationships for `word/document.xml` are in
+/// `word/_rels/document.xml.rels`. The package's own relationships (with an
+/// empty source) are in `_rels/.rels`.
+pub fn relationships_part(source: &str) -> String {
+    match source.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/_rels/{}.rels", dir, name),
+        None => format!("_rels/{}.rels", source),
+    }
+}
+
+/// Parse the contents of a relationships part.
+///
+/// Targets of internal relationships are resolved into part names relative to
+/// the package root.
+pub fn parse_relationships(source: &str, input: &str) -> Vec<Relationship> {
+    let mut relationships = Vec::new();
+
+    for mat in RE_RELATIONSHIP.find_iter(input) {
+        let attributes = attributes(mat.as_str());
+        let (id, rel_type, target) = match (
+            attributes.get("Id"),
+            attributes.get("Type"),
+            attributes.get("Target"),
+        ) {
+            (Some(i), Some(r), Some(t)) => (i, r, t),
+            _ => continue,
+        };
+        let external = attributes.get("TargetMode").map(|m| m.as_str()) == Some("External");
+
+        trace!(
+            slog_scope::logger(),
+            "Found relationship {} of type {} to {}",
+            id,
+            rel_type,
+            target
+        );
+        relationships.push(Relationship {
+            id: id.clone(),
+            rel_type: rel_type.clone(),
+            target: if external {
+                target.clone()
+            } else {
+                resolve_target(source, target)
+            },
+            external,
+        });
+    }
+
+    relationships
+}
+
+/// Resolve a relationship target relative to its source part.
+///
+/// Targets starting with a slash are relative to the package root. All others
+/// are relative to the directory containing the source part.
+pub fn resolve_target(source: &str, target: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    if !target.starts_with('/') {
+        if let Some((dir, _)) = source.rsplit_once('/') {
+            segments.extend(dir.split('/'));
+        }
+    }
+
+    for segment in target.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+
+    segments.join("/")
+}
+
+/// Collect the attributes of an element's start tag.
+///
+/// Values have the predefined XML entities decoded.
+fn attributes(tag: &str) -> HashMap<&str, String> {
+    let mut attributes = HashMap::new();
+    for cap in RE_ATTRIBUTE.captures_iter(tag) {
+        let name = cap.get(1).unwrap().as_str();
+        let value = cap.get(2).or_else(|| cap.get(3)).unwrap().as_str();
+        attributes.insert(name, unescape(value));
+    }
+    attributes
+}
+
+/// Decode the predefined XML entities.
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_targets() {
+        assert_eq!(
+            resolve_target("word/document.xml", "footnotes.xml"),
+            "word/footnotes.xml"
+        );
+        assert_eq!(
+            resolve_target("word/document.xml", "/word/notes/foot.xml"),
+            "word/notes/foot.xml"
+        );
+        assert_eq!(
+            resolve_target("word/document.xml", "../customXml/item1.xml"),
+            "customXml/item1.xml"
+        );
+        assert_eq!(
+            resolve_target("", "word/document2.xml"),
+            "word/document2.xml"
+        );
+    }
+
+    #[test]
+    fn relationships_parts() {
+        assert_eq!(relationships_part(""), "_rels/.rels");
+        assert_eq!(
+            relationships_part("word/document2.xml"),
+            "word/_rels/document2.xml.rels"
+        );
+    }
+
+    #[test]
+    fn locate_renamed_parts() {
+        let parts: HashMap<&str, &str> = HashMap::from([
+            (
+                "[Content_Types].xml",
+                r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="xml" ContentType="application/xml"/><Override PartName="/word/document2.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/><Override PartName="/word/notes.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.footnotes+xml"/><Override PartName="/word/settings2.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.settings+xml"/></Types>"#,
+            ),
+            (
+                "_rels/.rels",
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Id="rId1" Target="/word/document2.xml"/></Relationships>"#,
+            ),
+            (
+                "word/_rels/document2.xml.rels",
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId9" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com/?a=1&amp;b=2" TargetMode="External"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/footnotes" Target="notes.xml"/><Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/settings" Target="settings2.xml"/></Relationships>"#,
+            ),
+            ("word/notes.xml", "<w:footnotes/>"),
+        ]);
+
+        let located = locate_parts(|name| Ok(parts.get(name).map(|p| p.to_string()))).unwrap();
+        assert_eq!(
+            located,
+            WordParts {
+                document: "word/document2.xml".to_string(),
+                footnotes: Some("word/notes.xml".to_string()),
+                endnotes: None,
+                settings: Some("word/settings2.xml".to_string()),
+                styles: None,
+                numbering: None,
+            }
+        );
+    }
+
+    #[test]
+    fn locate_parts_without_relationships() {
+        let parts: HashMap<&str, &str> = HashMap::from([
+            ("word/document.xml", "<w:document/>"),
+            ("word/footnotes.xml", "<w:footnotes/>"),
+            ("word/styles.xml", "<w:styles/>"),
+            ("word/numbering.xml", "<w:numbering/>"),
+        ]);
+
+        let located = locate_parts(|name| Ok(parts.get(name).map(|p| p.to_string()))).unwrap();
+        assert_eq!(
+            located,
+            WordParts {
+                document: "word/document.xml".to_string(),
+                footnotes: Some("word/footnotes.xml".to_string()),
+                endnotes: None,
+                settings: None,
+                styles: Some("word/styles.xml".to_string()),
+                numbering: Some("word/numbering.xml".to_string()),
+            }
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! This module contains the functionality for reading the text of paragraphs.
+//!
+//! Word splits a paragraph's text into runs (`w:r`), and it does so for all
+//! sorts of reasons—spell-checking, revisions, or just editing. So a
+//! cross-reference like "note 12" can be spread over several runs. To find
+//! those cross-references, this module puts each paragraph's text back
+//! together, while keeping track of where each piece of that text came from.
+
+use crate::{
+    diagnostic::Diagnostic,
+    xml::{self, Kind},
+};
+use std::ops::Range;
+
+/// A piece of a paragraph's text, which is the contents of a `w:t` element.
+///
+/// A non-breaking hyphen (`w:noBreakHyphen`) is a piece, too. Its text is the
+/// character (U+2011), and its input is the whole element.
+#[derive(Debug, PartialEq, Eq)]
+struct Segment<'a> {
+    /// Where the piece starts in the paragraph's text.
+    text_start: usize,
+    /// Where the piece is in the input.
+    input: Range<usize>,
+    /// The properties (`w:rPr`) of the run that the piece is in.
+    props: &'a str,
+    /// Whether only the boundaries between runs separate the piece from the
+    /// one before it.
+    joined: bool,
+}
+
+/// The properties of the runs that a range of a paragraph's text starts and
+/// ends in.
+///
+/// These are needed to split the runs around that range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunProps<'a> {
+    pub first: &'a str,
+    pub last: &'a str,
+}
+
+/// The text of a paragraph, as it would be read, and the id of its style.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Paragraph<'a> {
+    pub text: String,
+    pub style: Option<&'a str>,
+    segments: Vec<Segment<'a>>,
+    /// Whether only the boundaries between runs have followed the last
+    /// segment so far.
+    joinable: bool,
+}
+
+impl<'a> Paragraph<'a> {
+    /// Create a new, empty paragraph.
+    fn new() -> Paragraph<'a> {
+        Paragraph {
+            text: String::new(),
+            style: None,
+            segments: Vec::new(),
+            joinable: false,
+        }
+    }
+
+    /// Find where a (non-empty) range of the paragraph's text is in the input.
+    ///
+    /// Because the text can be spread over several runs, the range in the input
+    /// can include the markup between them.
+    pub fn input_range(&self, range: &Range<usize>) -> Range<usize> {
+        let first = self.segment(range.start);
+        let last_index = self.segment_index(range.end - 1);
+        let last = &self.segments[last_index];
+        let end = if range.end >= self.segment_end(last_index) {
+            last.input.end
+        } else {
+            last.input.start + range.end - last.text_start
+        };
+        first.input.start + range.start - first.text_start..end
+    }
+
+    /// Get the properties of the runs that a (non-empty) range of the
+    /// paragraph's text starts and ends in.
+    pub fn run_props(&self, range: &Range<usize>) -> RunProps<'a> {
+        RunProps {
+            first: self.segment(range.start).props,
+            last: self.segment(range.end - 1).props,
+        }
+    }
+
+    /// Determine whether a (non-empty) range of the paragraph's text is only
+    /// text and the boundaries between runs in the input.
+    ///
+    /// Such a range can be replaced without leaving anything unbalanced or
+    /// losing anything besides the runs' boundaries (and spelling-error marks).
+    /// Text that is spread over, say, a hyperlink's runs and the runs after it
+    /// cannot.
+    pub fn is_splittable(&self, range: &Range<usize>) -> bool {
+        let first = self.segment_index(range.start);
+        let last = self.segment_index(range.end - 1);
+        self.segments[first + 1..=last].iter().all(|s| s.joined)
+    }
+
+    /// Determine whether a (non-empty) range of the paragraph's text is in
+    /// italics.
+    ///
+    /// Only direct formatting (`w:i` in the runs' properties) counts.
+    pub fn is_italic(&self, range: &Range<usize>) -> bool {
+        let first = self.segment_index(range.start);
+        let last = self.segment_index(range.end - 1);
+        self.segments[first..=last]
+            .iter()
+            .all(|s| is_italic(s.props))
+    }
+
+    /// Find where a segment ends in the paragraph's text.
+    fn segment_end(&self, index: usize) -> usize {
+        match self.segments.get(index + 1) {
+            Some(s) => s.text_start,
+            None => self.text.len(),
+        }
+    }
+
+    /// Find the segment that contains a position in the paragraph's text.
+    fn segment(&self, position: usize) -> &Segment<'a> {
+        &self.segments[self.segment_index(position)]
+    }
+
+    /// Find the index of the segment that contains a position in the
+    /// paragraph's text.
+    fn segment_index(&self, position: usize) -> usize {
+        self.segments
+            .partition_point(|s| s.text_start <= position)
+            .saturating_sub(1)
+    }
+}
+
+/// Determine whether a run's properties turn on italics.
+fn is_italic(props: &str) -> bool {
+    xml::events(props)
+        .filter_map(|e| e.ok())
+        .find(|e| matches!(e.kind, Kind::Start | Kind::Empty) && e.name == "i")
+        .is_some_and(|e| !matches!(e.attribute("val"), Some("0" | "false" | "off")))
+}
+
+/// Read the text of each paragraph in the input.
+///
+/// Only text in runs is included, along with non-breaking hyphens. Fields,
+/// tabs, and the like are skipped. A paragraph within another (*e.g.*, in a
+/// text box) is read separately, and the paragraphs are returned in the order
+/// they end.
+pub fn paragraphs(input: &str) -> Result<Vec<Paragraph<'_>>, Diagnostic> {
+    let mut paragraphs = Vec::new();
+
+    // The paragraphs being read, innermost last, and the elements they are in.
+    let mut open: Vec<Paragraph> = Vec::new();
+    let mut elements: Vec<&str> = Vec::new();
+
+    // Where the current run's properties and text start.
+    let mut props = "";
+    let mut props_start: Option<usize> = None;
+    let mut text_start: Option<usize> = None;
+
+    for event in xml::events(input) {
+        let event = event?;
+        let parent = elements.last().copied().unwrap_or_default();
+
+        // Everything but text and the markup between runs keeps the next
+        // piece of text from being joined to the last one.
+        let in_props = props_start.is_some();
+        let boundary = match event.kind {
+            Kind::Text => true,
+            Kind::Start | Kind::Empty => {
+                in_props
+                    || event.name == "r"
+                    || (event.name == "rPr" || event.name == "t") && parent == "r"
+                    || event.name == "proofErr"
+                    || event.name == "lastRenderedPageBreak"
+                    || event.name == "noBreakHyphen" && parent == "r"
+            }
+            Kind::End => in_props || event.name == "t" || event.name == "r",
+            Kind::Other => false,
+        };
+
+        match event.kind {
+            Kind::Start if event.name == "p" => {
+                if let Some(outer) = open.last_mut() {
+                    outer.joinable = false;
+                }
+                open.push(Paragraph::new());
+            }
+            Kind::End if event.name == "p" => {
+                if let Some(paragraph) = open.pop() {
+                    if !paragraph.segments.is_empty() {
+                        paragraphs.push(paragraph);
+                    }
+                }
+            }
+            // Only the paragraph's own style counts, not that of a tracked
+            // change (`w:pPrChange`).
+            Kind::Start | Kind::Empty
+                if event.name == "pStyle"
+                    && parent == "pPr"
+                    && !elements.contains(&"pPrChange") =>
+            {
+                if let Some(paragraph) = open.last_mut() {
+                    paragraph.style = event.attribute("val");
+                }
+            }
+            Kind::Start | Kind::Empty if event.name == "r" => props = "",
+            Kind::Start if event.name == "rPr" && parent == "r" => {
+                props_start = Some(event.range.start)
+            }
+            Kind::Empty if event.name == "rPr" && parent == "r" => {
+                props = &input[event.range.clone()]
+            }
+            Kind::End if event.name == "rPr" && parent == "rPr" => {
+                if let Some(start) = props_start.take() {
+                    props = &input[start..event.range.end];
+                }
+            }
+            Kind::Start if event.name == "t" && parent == "r" => text_start = Some(event.range.end),
+            Kind::End if event.name == "t" && parent == "t" => {
+                let start = match text_start.take() {
+                    Some(s) => s,
+                    None => continue,
+                };
+                let paragraph = match open.last_mut() {
+                    Some(p) => p,
+                    None => continue,
+                };
+                if start < event.range.start {
+                    paragraph.segments.push(Segment {
+                        text_start: paragraph.text.len(),
+                        input: start..event.range.start,
+                        props,
+                        joined: paragraph.joinable,
+                    });
+                    paragraph.text.push_str(&input[start..event.range.start]);
+                }
+                paragraph.joinable = true;
+            }
+            Kind::Empty if event.name == "noBreakHyphen" && parent == "r" => {
+                if let Some(paragraph) = open.last_mut() {
+                    paragraph.segments.push(Segment {
+                        text_start: paragraph.text.len(),
+                        input: event.range.clone(),
+                        props,
+                        joined: paragraph.joinable,
+                    });
+                    paragraph.text.push('\u{2011}');
+                    paragraph.joinable = true;
+                }
+            }
+            _ => {}
+        }
+
+        if !boundary {
+            if let Some(paragraph) = open.last_mut() {
+                paragraph.joinable = false;
+            }
+        }
+
+        match event.kind {
+            Kind::Start => elements.push(event.name),
+            Kind::End => {
+                elements.pop();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(paragraphs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_runs() {
+        let input = r#"<w:p><w:r><w:rPr><w:i/></w:rPr><w:t>supra</w:t></w:r><w:r><w:t xml:space="preserve"> note 1</w:t></w:r><w:proofErr w:type="spellStart"/><w:r w:rsidR="00A1"><w:rPr><w:b/></w:rPr><w:t>2.</w:t></w:r></w:p>"#;
+
+        let paragraphs = paragraphs(input).unwrap();
+        assert_eq!(paragraphs.len(), 1);
+        let paragraph = &paragraphs[0];
+        assert_eq!(paragraph.text, "supra note 12.");
+
+        let range = 11..13;
+        assert_eq!(
+            &input[paragraph.input_range(&range)],
+            r#"1</w:t></w:r><w:proofErr w:type="spellStart"/><w:r w:rsidR="00A1"><w:rPr><w:b/></w:rPr><w:t>2"#
+        );
+        assert_eq!(
+            paragraph.run_props(&range),
+            RunProps {
+                first: "",
+                last: "<w:rPr><w:b/></w:rPr>",
+            }
+        );
+        assert_eq!(paragraph.run_props(&(0..5)).first, "<w:rPr><w:i/></w:rPr>");
+
+        assert!(paragraph.is_splittable(&range));
+        assert!(paragraph.is_italic(&(0..5)));
+        assert!(!paragraph.is_italic(&(0..6)));
+
+        let input = r#"<w:p><w:pPr><w:pStyle w:val="Caption"/><w:pPrChange><w:pPr><w:pStyle w:val="Normal"/></w:pPr></w:pPrChange></w:pPr><w:hyperlink><w:r><w:t>note 1</w:t></w:r></w:hyperlink><w:r><w:t>2</w:t></w:r><w:r><w:tab/><w:t>3</w:t></w:r></w:p>"#;
+        let paragraph = &super::paragraphs(input).unwrap()[0];
+        assert_eq!(paragraph.text, "note 123");
+        assert_eq!(paragraph.style, Some("Caption"));
+        assert!(paragraph.is_splittable(&(0..6)));
+        assert!(!paragraph.is_splittable(&(5..7)));
+        assert!(!paragraph.is_splittable(&(6..8)));
+
+        let input =
+            r#"<w:p><w:r><w:t>notes 1</w:t><w:noBreakHyphen/></w:r><w:r><w:t>2</w:t></w:r></w:p>"#;
+        let paragraph = &super::paragraphs(input).unwrap()[0];
+        assert_eq!(paragraph.text, "notes 1\u{2011}2");
+        assert_eq!(
+            &input[paragraph.input_range(&(6..11))],
+            "1</w:t><w:noBreakHyphen/></w:r><w:r><w:t>2"
+        );
+        assert_eq!(
+            &input[paragraph.input_range(&(7..10))],
+            "<w:noBreakHyphen/>"
+        );
+        assert!(paragraph.is_splittable(&(6..11)));
+    }
+}
@@ -1,14 +1,94 @@
 //! This module contains the parser that prepares the tokens for markup.
 
-use crate::lexer::{Token, TokenType};
-use slog::{debug, o, trace};
+use crate::{
+    captions::CaptionKind,
+    diagnostic::{self, Diagnostic, Kind},
+    grammar::{self, Direction},
+    lexer::{Token, TokenType},
+    notes::{self, NoteSettings, Restart},
+    numbering::Numbered,
+    numfmt::NumFmt,
+    paragraph::RunProps,
+};
+use slog::{debug, info, o, trace, warn};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// The kinds of notes in a document.
+///
+/// Footnotes and endnotes are numbered separately, and cross-references in one
+/// kind of note refer to other notes of the same kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NoteKind {
+    Footnote,
+    Endnote,
+}
+
+impl NoteKind {
+    /// The kind's name, for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteKind::Footnote => "footnote",
+            NoteKind::Endnote => "endnote",
+        }
+    }
+
+    /// The name of the part with this kind of note, for diagnostics.
+    pub fn part(&self) -> &'static str {
+        match self {
+            NoteKind::Footnote => diagnostic::FOOTNOTES_PART,
+            NoteKind::Endnote => diagnostic::ENDNOTES_PART,
+        }
+    }
+}
+
+/// What Word displays for a note.
+///
+/// Most notes are numbered. But a note can instead have a custom mark (*e.g.*,
+/// an author's "*" footnote), which does not take up a number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Label<'a> {
+    Number(u32),
+    Mark(&'a str),
+}
+
+impl fmt::Display for Label<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Label::Number(n) => write!(f, "{}", n),
+            Label::Mark(m) => write!(f, "{}", m),
+        }
+    }
+}
 
 /// The types of branches in the syntax tree.
 #[derive(Debug, PartialEq, Eq)]
 pub enum Branch<'a> {
     Text(Text<'a>),
-    FootnoteRef(FootnoteRef<'a>),
-    CrossRef(CrossRef),
+    NoteRef(NoteRef<'a>),
+    CrossRef(CrossRef<'a>),
+    ParagraphRef(ParagraphRef<'a>),
+    Caption(Caption<'a>),
+    CaptionRef(CaptionRef<'a>),
+    Signal(Signal<'a>),
+}
+
+impl<'a> Branch<'a> {
+    /// The branch's contents, as they are in its part.
+    pub fn contents(&self) -> &'a str {
+        match self {
+            Branch::Text(t) => t.contents,
+            Branch::NoteRef(n) => n.contents,
+            Branch::CrossRef(c) => c.contents,
+            Branch::ParagraphRef(p) => p.contents,
+            Branch::Caption(c) => c.contents,
+            Branch::CaptionRef(c) => c.contents,
+            Branch::Signal(s) => s.contents,
+        }
+    }
 }
 
 /// Contents of a text branch.
@@ -19,93 +99,516 @@ pub struct Text<'a> {
 
 impl Text<'_> {
     /// Create a new [`Text`] branch.
-    fn new(contents: &str) -> Text {
+    fn new(contents: &str) -> Text<'_> {
         Text { contents }
     }
 }
 
-/// Contents of a footnote-reference branch.
+/// Contents of a note-reference branch.
 ///
-/// A footnote-reference branch requires both the footnote's number and the
-/// contents.
+/// A note-reference branch requires the kind of note, the note's ordinal (its
+/// position among all notes of that kind, starting at 1), the [`Label`] Word
+/// displays for it, and the contents. Because numbering can restart, only the
+/// ordinal is unique.
 #[derive(Debug, PartialEq, Eq)]
-pub struct FootnoteRef<'a> {
-    pub number: u32,
+pub struct NoteRef<'a> {
+    pub kind: NoteKind,
+    pub ordinal: u32,
+    pub label: Label<'a>,
     pub contents: &'a str,
 }
 
-impl FootnoteRef<'_> {
-    /// Create a new [`FootnoteRef`] branch.
-    fn new(number: u32, contents: &str) -> FootnoteRef {
-        FootnoteRef { number, contents }
+impl<'a> NoteRef<'a> {
+    /// Create a new [`NoteRef`] branch.
+    fn new(kind: NoteKind, ordinal: u32, label: Label<'a>, contents: &'a str) -> NoteRef<'a> {
+        NoteRef {
+            kind,
+            ordinal,
+            label,
+            contents,
+        }
     }
 }
 
 /// Contents of a CrossRef branch.
 ///
-/// Because a cross-reference branch consists of only the referred-footnote's
-/// number, there is no need for a separate content field—the content is the
-/// number.
+/// A cross-reference branch consists of the referred-note's kind and label
+/// (usually a number) as written, plus the chapter (section) it was qualified
+/// with, if any. Because the label can be spread over several runs, the branch
+/// also keeps its contents (the label and the markup within it) and the
+/// properties of the runs it starts and ends in. The section a cross-reference
+/// is in comes from the id of the note containing it or, for a cross-reference
+/// in the body text, is recorded directly. The direction of its signal (if any)
+/// and where the signal's word starts (see [`Signal`]) are kept to check that
+/// it points the right way. Once the notes are numbered, `target` is set to the
+/// ordinal of the note referred to, and `result` to the label as Word will
+/// display it. The text around the cross-reference (`context`) and where it
+/// starts in its part (`offset`) are kept for messages about it.
 #[derive(Debug, PartialEq, Eq)]
-pub struct CrossRef {
-    pub number: u32,
+pub struct CrossRef<'a> {
+    pub kind: NoteKind,
+    pub label: String,
+    pub contents: &'a str,
+    pub runs: RunProps<'a>,
+    pub chapter: Option<u32>,
+    pub note_id: Option<&'a str>,
+    pub section: Option<usize>,
+    pub direction: Option<Direction>,
+    pub signal: Option<usize>,
+    pub target: Option<u32>,
+    pub result: Option<String>,
+    pub context: &'a str,
+    pub offset: Option<usize>,
 }
 
-impl CrossRef {
-    /// Create a new [`CrossRef`] branhc.
-    fn new(number: u32) -> CrossRef {
-        CrossRef { number }
+impl<'a> CrossRef<'a> {
+    /// Create a new [`CrossRef`] branch.
+    fn new(
+        kind: NoteKind,
+        label: String,
+        contents: &'a str,
+        runs: RunProps<'a>,
+        chapter: Option<u32>,
+        note_id: Option<&'a str>,
+        context: &'a str,
+    ) -> CrossRef<'a> {
+        CrossRef {
+            kind,
+            label,
+            contents,
+            runs,
+            chapter,
+            note_id,
+            section: None,
+            direction: None,
+            signal: None,
+            target: None,
+            result: None,
+            context,
+            offset: None,
+        }
     }
 }
 
-/// The complex type that the [`parser`] returns.
+/// Contents of a ParagraphRef branch.
 ///
-/// The parser returns two trees—one for each `.xml` file—and a vector
-/// containing all of the footnotes that are referenced. This vector allows the
-/// program to add bookmark markup only to those footnote references that need
-/// it.
-type ParseResults<'a> = (Vec<Branch<'a>>, Vec<Branch<'a>>, Vec<u32>);
+/// A paragraph-reference branch consists of the number of a numbered paragraph
+/// (or heading) as written, its contents, and the properties of the runs it
+/// starts and ends in (as with a [`CrossRef`]). A reference written as a
+/// heading reference (*e.g.*, "Part II") is a `heading` reference. Once the
+/// paragraphs are matched, `target` is set to the index of the numbered
+/// paragraph referred to (see [`Numbered`]), `result` to its number as Word
+/// will display it, and `switches` to the `REF` field's switches that display
+/// it that way.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParagraphRef<'a> {
+    pub heading: bool,
+    pub label: String,
+    pub contents: &'a str,
+    pub runs: RunProps<'a>,
+    pub target: Option<usize>,
+    pub result: Option<String>,
+    pub switches: String,
+}
+
+impl<'a> ParagraphRef<'a> {
+    /// Create a new [`ParagraphRef`] branch from a `HeadingRef` or
+    /// `ParagraphRef` token.
+    fn new(token: &'a Token<'a>) -> ParagraphRef<'a> {
+        ParagraphRef {
+            heading: token.token_type == TokenType::HeadingRef,
+            label: match &token.text {
+                Some(t) => t.clone(),
+                None => token.contents.to_string(),
+            },
+            contents: token.contents,
+            runs: token.runs.unwrap_or(RunProps {
+                first: "",
+                last: "",
+            }),
+            target: None,
+            result: None,
+            switches: String::new(),
+        }
+    }
+}
+
+/// Contents of a Caption branch.
+///
+/// A caption branch consists of the kind of caption, its number as written,
+/// its contents, and the properties of the runs it starts and ends in (as with
+/// a [`CrossRef`]). `sequence` is the number that Word will display for it:
+/// its position among the captions of the same kind.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Caption<'a> {
+    pub kind: CaptionKind,
+    pub label: String,
+    pub contents: &'a str,
+    pub runs: RunProps<'a>,
+    pub sequence: u32,
+}
+
+/// Contents of a CaptionRef branch.
+///
+/// A caption-reference branch consists of the kind of caption referred to and
+/// its number as written, plus the contents and runs (as with a
+/// [`CrossRef`]). Once the captions are matched, `target` is set to the
+/// sequence number of the caption referred to (see [`Caption`]), and `result`
+/// to that number as Word will display it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CaptionRef<'a> {
+    pub kind: CaptionKind,
+    pub label: String,
+    pub contents: &'a str,
+    pub runs: RunProps<'a>,
+    pub target: Option<u32>,
+    pub result: Option<String>,
+}
+
+impl<'a> CaptionRef<'a> {
+    /// Create a new [`CaptionRef`] branch from a `TableRef` or `FigureRef`
+    /// token.
+    fn new(token: &'a Token<'a>) -> CaptionRef<'a> {
+        CaptionRef {
+            kind: match token.token_type {
+                TokenType::FigureRef => CaptionKind::Figure,
+                _ => CaptionKind::Table,
+            },
+            label: match &token.text {
+                Some(t) => t.clone(),
+                None => token.contents.to_string(),
+            },
+            contents: token.contents,
+            runs: token.runs.unwrap_or(RunProps {
+                first: "",
+                last: "",
+            }),
+            target: None,
+            result: None,
+        }
+    }
+}
+
+/// Contents of a Signal branch.
+///
+/// A signal branch consists of the word of a cross-reference's signal that
+/// says which way it points (*e.g.*, "supra" or "below") and where it starts
+/// in its part, which the cross-references after it (or before it) refer to.
+/// If the word points the wrong way and is to be changed, `result` is set to
+/// the word for the other way.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Signal<'a> {
+    pub contents: &'a str,
+    pub offset: Option<usize>,
+    pub result: Option<String>,
+}
+
+impl<'a> Signal<'a> {
+    /// Create a new [`Signal`] branch from a `Signal` token.
+    fn new(token: &'a Token<'a>) -> Signal<'a> {
+        Signal {
+            contents: token.contents,
+            offset: token.offset,
+            result: None,
+        }
+    }
+}
+
+/// A numbered note.
+///
+/// This records where each note ended up: its id, its ordinal, the [`Label`]
+/// Word displays for it and that label's format, and the index of the section
+/// it is in.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Note<'a> {
+    pub kind: NoteKind,
+    pub id: &'a str,
+    pub ordinal: u32,
+    pub label: Label<'a>,
+    pub num_fmt: NumFmt,
+    pub section: usize,
+}
+
+impl Note<'_> {
+    /// The note's label as Word displays it.
+    pub fn display(&self) -> String {
+        match self.label {
+            Label::Number(n) => self.num_fmt.format(n),
+            Label::Mark(m) => m.to_string(),
+        }
+    }
+
+    /// Determine whether a label as written refers to this note.
+    ///
+    /// A number can be written in the note's format or as a decimal number.
+    pub fn matches(&self, label: &str) -> bool {
+        match self.label {
+            Label::Number(n) => {
+                self.num_fmt.parse(label) == Some(n) || label.parse::<u32>().ok() == Some(n)
+            }
+            Label::Mark(m) => m == label,
+        }
+    }
+}
+
+/// What the [`parser`] returns.
+///
+/// The parser returns a tree for each `.xml` file—`document.xml` plus
+/// `footnotes.xml` and `endnotes.xml` if the document has them—along with what
+/// is referred to, so that only the note references, numbered paragraphs, and
+/// captions that need it get bookmark markup.
+#[derive(Debug)]
+pub struct Parsed<'a> {
+    pub doc: Vec<Branch<'a>>,
+    pub footnotes: Option<Vec<Branch<'a>>>,
+    pub endnotes: Option<Vec<Branch<'a>>>,
+    /// The kind and ordinal of every note that is referred to.
+    pub refd_notes: Vec<(NoteKind, u32)>,
+    /// The index of every numbered paragraph that is referred to.
+    pub refd_paragraphs: Vec<usize>,
+    /// The kind and sequence number of every caption that is referred to.
+    pub refd_captions: Vec<(CaptionKind, u32)>,
+    /// The problems with cross-references that did not stop the parser.
+    pub problems: Vec<Diagnostic>,
+}
 
 /// The main parser function.
+///
+/// The notes are parsed first to learn which note ids exist. Then the document
+/// is parsed, which numbers the notes and the captions. Finally, each
+/// cross-reference is resolved to the note it refers to, each heading reference
+/// to one of the numbered `paragraphs`, and each reference to a table or
+/// figure to its caption.
+///
+/// A cross-reference to a note that does not exist (*e.g.*, "*supra* note
+/// 412" in an article with 300 notes) is an error, which names the note that
+/// the cross-reference is in and quotes the text around it. With
+/// `allow_dangling`, the cross-reference is left as text instead, and the
+/// error is returned with the other problems that do not stop the parser. A
+/// cross-reference whose signal points the wrong way (*e.g.*, "*supra* note
+/// 40" in note 22) or that refers to its own note is a warning, and it is
+/// converted all the same. With `fix_signals`, a signal that points the wrong
+/// way is changed instead, where it can be.
 pub fn parser<'a>(
     doc_tokens: &'a [Token<'a>],
-    fn_tokens: &'a [Token<'a>],
-) -> Result<ParseResults<'a>, String> {
+    fn_tokens: Option<&'a [Token<'a>]>,
+    en_tokens: Option<&'a [Token<'a>]>,
+    settings: &NoteSettings,
+    paragraphs: &[Numbered],
+    allow_dangling: bool,
+    fix_signals: bool,
+) -> Result<Parsed<'a>, Diagnostic> {
     debug!(slog_scope::logger(), "Starting parser...");
 
-    let doc_branches =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "parse_fr()")), || {
-            parse_fr(doc_tokens)
-        }) {
-            Ok(b) => b,
-            Err(e) => return Err(e),
-        };
+    let (mut fn_branches, fn_ids) = match fn_tokens {
+        Some(t) => {
+            let (branches, ids) =
+                slog_scope::scope(&slog_scope::logger().new(o!("fn" => "parse_cr()")), || {
+                    parse_cr(t, NoteKind::Footnote)
+                        .map_err(|e| e.in_part(diagnostic::FOOTNOTES_PART))
+                })?;
+            (Some(branches), ids)
+        }
+        None => (None, HashSet::new()),
+    };
 
-    let (fn_branches, refd_fns) =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "parse_cr()")), || {
-            parse_cr(fn_tokens)
-        }) {
-            Ok(b) => b,
-            Err(e) => return Err(e),
-        };
+    let (mut en_branches, en_ids) = match en_tokens {
+        Some(t) => {
+            let (branches, ids) =
+                slog_scope::scope(&slog_scope::logger().new(o!("fn" => "parse_cr()")), || {
+                    parse_cr(t, NoteKind::Endnote).map_err(|e| e.in_part(diagnostic::ENDNOTES_PART))
+                })?;
+            (Some(branches), ids)
+        }
+        None => (None, HashSet::new()),
+    };
+
+    // Cross-references in the body text refer to footnotes, unless the
+    // document only has endnotes.
+    let body_kind = match fn_tokens {
+        Some(_) => NoteKind::Footnote,
+        None => NoteKind::Endnote,
+    };
+    let (mut doc_branches, notes) =
+        slog_scope::scope(&slog_scope::logger().new(o!("fn" => "parse_fr()")), || {
+            parse_fr(doc_tokens, &fn_ids, &en_ids, settings, body_kind)
+                .map_err(|e| e.in_part(diagnostic::DOCUMENT_PART))
+        })?;
+
+    // The captions' numbers as written, with the numbers Word will display
+    let captions: Vec<(CaptionKind, String, u32)> = doc_branches
+        .iter()
+        .filter_map(|b| match b {
+            Branch::Caption(c) => Some((c.kind, c.label.clone(), c.sequence)),
+            _ => None,
+        })
+        .collect();
+
+    let mut refd_notes: Vec<(NoteKind, u32)> = Vec::new();
+    let mut refd_paragraphs: Vec<usize> = Vec::new();
+    let mut refd_captions: Vec<(CaptionKind, u32)> = Vec::new();
+    let mut dangling: Vec<Diagnostic> = Vec::new();
+    let mut misdirected: Vec<Diagnostic> = Vec::new();
+    for branches in [
+        Some(&mut doc_branches),
+        fn_branches.as_mut(),
+        en_branches.as_mut(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "resolve_cr()")),
+            || resolve_cr(branches, &notes, &mut refd_notes, &mut dangling),
+        )?;
+        slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "resolve_signals()")),
+            || resolve_signals(branches, &notes, fix_signals, &mut misdirected),
+        );
+        slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "resolve_pr()")),
+            || resolve_pr(branches, paragraphs, &mut refd_paragraphs),
+        );
+        slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "resolve_captions()")),
+            || resolve_captions(branches, &captions, &mut refd_captions),
+        );
+    }
+
+    if !dangling.is_empty() {
+        if !allow_dangling {
+            if dangling.len() == 1 {
+                return Err(dangling.remove(0));
+            }
+            let err_msg = format!(
+                "Found {} cross-references to notes that do not exist",
+                dangling.len()
+            );
+            return Err(Diagnostic::error(Kind::Reference, err_msg).with_related(dangling));
+        }
+        for d in &dangling {
+            warn!(
+                slog_scope::logger(),
+                "{}: {:?}; leaving it as text",
+                d.message,
+                d.context.as_deref().unwrap_or_default()
+            );
+        }
+    }
+    for d in &misdirected {
+        warn!(
+            slog_scope::logger(),
+            "{}: {:?}",
+            d.message,
+            d.context.as_deref().unwrap_or_default()
+        );
+    }
+    let mut problems = dangling;
+    problems.append(&mut misdirected);
 
     debug!(slog_scope::logger(), "Parser finished.");
-    Ok((doc_branches, fn_branches, refd_fns))
+    Ok(Parsed {
+        doc: doc_branches,
+        footnotes: fn_branches,
+        endnotes: en_branches,
+        refd_notes,
+        refd_paragraphs,
+        refd_captions,
+        problems,
+    })
 }
 
-/// Parse the footnote references.
+/// Number the notes, without resolving any cross-references.
+///
+/// The notes are numbered as in [`parser`], which is what checking a document
+/// (see [`crate::check`]) needs to name the note that each problem is in.
+pub fn notes<'a>(
+    doc_tokens: &'a [Token<'a>],
+    fn_tokens: Option<&'a [Token<'a>]>,
+    en_tokens: Option<&'a [Token<'a>]>,
+    settings: &NoteSettings,
+) -> Result<Vec<Note<'a>>, Diagnostic> {
+    let ids = |tokens: Option<&'a [Token<'a>]>| -> HashSet<&'a str> {
+        tokens
+            .unwrap_or_default()
+            .iter()
+            .filter(|t| t.token_type == TokenType::Note)
+            .filter_map(|t| t.id)
+            .collect()
+    };
+    let body_kind = match fn_tokens {
+        Some(_) => NoteKind::Footnote,
+        None => NoteKind::Endnote,
+    };
+    let (_, notes) =
+        slog_scope::scope(&slog_scope::logger().new(o!("fn" => "parse_fr()")), || {
+            parse_fr(
+                doc_tokens,
+                &ids(fn_tokens),
+                &ids(en_tokens),
+                settings,
+                body_kind,
+            )
+            .map_err(|e| e.in_part(diagnostic::DOCUMENT_PART))
+        })?;
+    Ok(notes)
+}
+
+/// Parse the note references.
 ///
 /// This function parses the tokens produced from the `document.xml` file.
 /// Tokens with the [`TokenType`] `Other` are simply pushed as is. Tokens with
-/// the [`TokenType`] `FootnoteRef` get a footnote number added, too.
+/// the [`TokenType`] `FootnoteRef` or `EndnoteRef` get a note number added,
+/// too. Footnotes and endnotes are numbered separately.
+///
+/// Numbers are assigned by the order of the references' ids, and only to ids
+/// that match a note in `footnotes.xml` or `endnotes.xml` (`fn_ids` and
+/// `en_ids`). A reference to an id that has no note is left as text. So is a
+/// repeated reference to an id—as happens with text boxes, whose contents
+/// Word writes twice—so that each note is numbered and bookmarked only once.
+///
+/// Numbering starts and restarts according to the document's `settings` and
+/// the properties of each section. Notes that restart on each page cannot be
+/// numbered without laying out the pages, so they are numbered continuously.
 ///
-/// Note, this function assumes that the starting footnote is 1. Use of Supra's
-/// offset functionality will break this.
-fn parse_fr<'a>(tokens: &'a [Token<'a>]) -> Result<Vec<Branch<'a>>, String> {
+/// Cross-references in the body text are parsed as in the notes (see
+/// [`parse_cr`]). They refer to notes of the kind `body_kind`, and they record
+/// the section they are in. The captions of tables and figures are numbered
+/// the way Word's `SEQ` fields count them: in order, separately for each kind.
+///
+/// Along with the branches, this function returns every numbered [`Note`].
+fn parse_fr<'a>(
+    tokens: &'a [Token<'a>],
+    fn_ids: &HashSet<&str>,
+    en_ids: &HashSet<&str>,
+    settings: &NoteSettings,
+    body_kind: NoteKind,
+) -> Result<(Vec<Branch<'a>>, Vec<Note<'a>>), Diagnostic> {
     debug!(slog_scope::logger(), "Starting document parser...");
 
+    // Collect the note settings for each section. A document without any
+    // section properties has just the one section with the document's
+    // settings.
+    let mut sections: Vec<NoteSettings> = Vec::new();
+    for token in tokens.iter().filter(|t| t.token_type == TokenType::Other) {
+        sections.extend(
+            notes::sections(token.contents)?
+                .into_iter()
+                .map(|s| s.or(*settings)),
+        );
+    }
+    let section_settings = |section: usize| match sections.get(section) {
+        Some(s) => *s,
+        None => *settings,
+    };
+    debug!(slog_scope::logger(), "Found {} sections", sections.len());
+
     let mut parse: Vec<Branch> = Vec::new();
-    let mut footnote_number = 1;
+    let mut notes: Vec<Note> = Vec::new();
+    let mut numbered: HashSet<(NoteKind, &str)> = HashSet::new();
+    let mut section = 0;
+    let mut warned_each_page = false;
+    let mut sequences: HashMap<CaptionKind, u32> = HashMap::new();
 
     for token in tokens {
         match token.token_type {
@@ -116,48 +619,244 @@ fn parse_fr<'a>(tokens: &'a [Token<'a>]) -> Result<Vec<Branch<'a>>, String> {
                     "Pushing branch type Text containing {:?}",
                     token.contents
                 );
-                parse.push(Branch::Text(Text::new(token.contents)))
+                parse.push(Branch::Text(Text::new(token.contents)));
+
+                // Any section properties end a section.
+                section += notes::sections(token.contents)?.len();
             }
-            TokenType::FootnoteRef => {
-                // Push the branch with a footnote number.
+            TokenType::FootnoteRef | TokenType::EndnoteRef => {
+                // Determine which kind of note this is.
+                let (kind, ids) = match token.token_type {
+                    TokenType::EndnoteRef => (NoteKind::Endnote, en_ids),
+                    _ => (NoteKind::Footnote, fn_ids),
+                };
+                let id = token.id.unwrap_or_default();
+
+                // Skip references without a note and repeated references.
+                if !ids.contains(id) {
+                    warn!(
+                        slog_scope::logger(),
+                        "The {:?} reference with id {} has no matching note; skipping it", kind, id
+                    );
+                    parse.push(Branch::Text(Text::new(token.contents)));
+                    continue;
+                }
+                if !numbered.insert((kind, id)) {
+                    debug!(
+                        slog_scope::logger(),
+                        "The {:?} with id {} was already numbered; skipping it", kind, id
+                    );
+                    parse.push(Branch::Text(Text::new(token.contents)));
+                    continue;
+                }
+
+                let ordinal = notes
+                    .iter()
+                    .rev()
+                    .find(|n| n.kind == kind)
+                    .map_or(1, |p| p.ordinal + 1);
+
+                let label = match token.mark {
+                    // A note with a custom mark does not take up a number.
+                    Some(mark) => {
+                        info!(
+                            slog_scope::logger(),
+                            "The {:?} with id {} has the custom mark {:?}; not numbering it",
+                            kind,
+                            id,
+                            mark
+                        );
+                        Label::Mark(mark)
+                    }
+                    // Otherwise, determine the note's number from the previous
+                    // numbered note of the same kind and the section's
+                    // properties.
+                    None => {
+                        let properties = section_settings(section).get(kind);
+                        if properties.restart() == Restart::EachPage && !warned_each_page {
+                            warn!(
+                                slog_scope::logger(),
+                                "{:?} numbering restarts on each page, which cannot be determined; numbering continuously",
+                                kind
+                            );
+                            warned_each_page = true;
+                        }
+                        let previous = notes.iter().rev().find_map(|n| match n.label {
+                            Label::Number(number) if n.kind == kind => Some((n.section, number)),
+                            _ => None,
+                        });
+                        let number = match previous {
+                            Some((p_section, p_number))
+                                if p_section == section
+                                    || properties.restart() != Restart::EachSect =>
+                            {
+                                p_number + 1
+                            }
+                            Some(_) => properties.start(),
+                            None if properties.restart() == Restart::EachSect => properties.start(),
+                            None => section_settings(0).get(kind).start(),
+                        };
+                        Label::Number(number)
+                    }
+                };
+
+                // Push the branch with a note label.
                 trace!(
                     slog_scope::logger(),
-                    "Pushing branch type FootnoteRef with footnote number {} and containing {}",
-                    footnote_number,
+                    "Pushing branch type NoteRef for {:?} {} (label {}, id {}, section {}) and containing {}",
+                    kind,
+                    ordinal,
+                    label,
+                    id,
+                    section,
                     token.contents
                 );
-                parse.push(Branch::FootnoteRef(FootnoteRef::new(
-                    footnote_number,
+                parse.push(Branch::NoteRef(NoteRef::new(
+                    kind,
+                    ordinal,
+                    label,
                     token.contents,
                 )));
+                notes.push(Note {
+                    kind,
+                    id,
+                    ordinal,
+                    label,
+                    num_fmt: section_settings(section).get(kind).format(kind),
+                    section,
+                });
+            }
+            TokenType::Chapter => {
+                // The chapter is also carried by its cross-references.
+                parse.push(Branch::Text(Text::new(token.contents)))
+            }
+            TokenType::CrossRef => {
+                let cross_ref = CrossRef {
+                    section: Some(section),
+                    ..cross_ref(token, body_kind, None)?
+                };
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type CrossRef for {:?} {} (chapter {:?}) in section {}",
+                    body_kind,
+                    cross_ref.label,
+                    cross_ref.chapter,
+                    section,
+                );
+                parse.push(Branch::CrossRef(cross_ref));
+            }
+            TokenType::HeadingRef | TokenType::ParagraphRef => {
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type ParagraphRef for {:?}",
+                    token.text
+                );
+                parse.push(Branch::ParagraphRef(ParagraphRef::new(token)));
+            }
+            TokenType::TableCaption | TokenType::FigureCaption => {
+                let kind = match token.token_type {
+                    TokenType::FigureCaption => CaptionKind::Figure,
+                    _ => CaptionKind::Table,
+                };
+                let sequence = sequences.entry(kind).or_default();
+                *sequence += 1;
+                let label = match &token.text {
+                    Some(t) => t.clone(),
+                    None => token.contents.to_string(),
+                };
+                if label.parse::<u32>().ok() != Some(*sequence) {
+                    warn!(
+                        slog_scope::logger(),
+                        "The caption {} {} is out of order; Word will number it {}",
+                        kind.label(),
+                        label,
+                        sequence
+                    );
+                }
 
-                // Increment the footnote number for the next footnote.
-                footnote_number += 1;
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type Caption for {} {} (number {})",
+                    kind.label(),
+                    label,
+                    sequence
+                );
+                parse.push(Branch::Caption(Caption {
+                    kind,
+                    label,
+                    contents: token.contents,
+                    runs: token.runs.unwrap_or(RunProps {
+                        first: "",
+                        last: "",
+                    }),
+                    sequence: *sequence,
+                }));
+            }
+            TokenType::TableRef | TokenType::FigureRef => {
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type CaptionRef for {:?}",
+                    token.text
+                );
+                parse.push(Branch::CaptionRef(CaptionRef::new(token)));
+            }
+            TokenType::Signal => {
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type Signal containing {:?}",
+                    token.contents
+                );
+                parse.push(Branch::Signal(Signal::new(token)));
             }
             _ => {}
         }
     }
 
     debug!(slog_scope::logger(), "Document parser finished.");
-    Ok(parse)
+    Ok((parse, notes))
 }
 
 /// Parse the cross-reference.
 ///
-/// This function parses the tokens produced from the `footnotes.xml` file.
-/// Tokens with the [`TokenType`] `Other` are simply pushed as is. Tokens with
-/// the [`TokenType`] `CrossRef` are parsed into a u32 number. This function
-/// also returns a vector of all of the cross-referenced footnotes, which is
-/// used to determine which footnote references in `document.xml` need bookmark
-/// markup added.
-fn parse_cr<'a>(tokens: &'a [Token<'a>]) -> Result<(Vec<Branch<'a>>, Vec<u32>), String> {
-    debug!(slog_scope::logger(), "Starting footnotes parser...");
+/// This function parses the tokens produced from the `footnotes.xml` or
+/// `endnotes.xml` file. Tokens with the [`TokenType`] `Other` are simply pushed
+/// as is. `Note` tokens are, too, but they also record the id of the note that
+/// the cross-references after them are in. Tokens with the [`TokenType`]
+/// `CrossRef` contain a label (a number in some format or a custom mark),
+/// which refers to another note of the same kind. A `Chapter` token is pushed
+/// as text, and its number qualifies the cross-references that follow it in
+/// the same match.
+///
+/// Along with the branches, this function returns the ids of the notes in the
+/// file that get numbers.
+fn parse_cr<'a>(
+    tokens: &'a [Token<'a>],
+    kind: NoteKind,
+) -> Result<(Vec<Branch<'a>>, HashSet<&'a str>), Diagnostic> {
+    debug!(slog_scope::logger(), "Starting {:?} parser...", kind);
 
     let mut parse: Vec<Branch> = Vec::new();
-    let mut referred_fns: Vec<u32> = Vec::new();
+    let mut note_ids: HashSet<&str> = HashSet::new();
+    let mut note_id: Option<&str> = None;
 
     for token in tokens {
         match token.token_type {
+            TokenType::Note => {
+                // Collect the ids of the numbered notes. The cross-references
+                // that follow are in this note.
+                let id = token.id.unwrap_or_default();
+                trace!(slog_scope::logger(), "Found {:?} with id {}", kind, id);
+                note_id = Some(id);
+                note_ids.insert(id);
+
+                // Push the branch as is.
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type Text containing {:?}",
+                    token.contents
+                );
+                parse.push(Branch::Text(Text::new(token.contents)))
+            }
             TokenType::Other => {
                 // Push the branch as is.
                 trace!(
@@ -167,39 +866,521 @@ fn parse_cr<'a>(tokens: &'a [Token<'a>]) -> Result<(Vec<Branch<'a>>, Vec<u32>),
                 );
                 parse.push(Branch::Text(Text::new(token.contents)))
             }
+            TokenType::Chapter => {
+                // The chapter is also carried by its cross-references.
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type Text containing chapter {:?}",
+                    token.contents
+                );
+                parse.push(Branch::Text(Text::new(token.contents)))
+            }
             TokenType::CrossRef => {
-                // Determine the number referred to.
-                let footnote_number = match token.contents.parse::<u32>() {
-                    Ok(n) => n,
-                    Err(e) => {
-                        let err_msg = format!("Error parsing cross references: {}", e);
-                        return Err(err_msg);
-                    }
-                };
+                // Push the new branch. The label is matched to a note once the
+                // notes are numbered.
+                let cross_ref = cross_ref(token, kind, note_id)?;
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type CrossRef for {:?} {} (chapter {:?}) in note {:?}",
+                    kind,
+                    cross_ref.label,
+                    cross_ref.chapter,
+                    note_id,
+                );
+                parse.push(Branch::CrossRef(cross_ref))
+            }
+            TokenType::HeadingRef | TokenType::ParagraphRef => {
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type ParagraphRef for {:?} in note {:?}",
+                    token.text,
+                    note_id,
+                );
+                parse.push(Branch::ParagraphRef(ParagraphRef::new(token)))
+            }
+            TokenType::TableRef | TokenType::FigureRef => {
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type CaptionRef for {:?} in note {:?}",
+                    token.text,
+                    note_id,
+                );
+                parse.push(Branch::CaptionRef(CaptionRef::new(token)))
+            }
+            TokenType::Signal => {
+                trace!(
+                    slog_scope::logger(),
+                    "Pushing branch type Signal containing {:?} in note {:?}",
+                    token.contents,
+                    note_id,
+                );
+                parse.push(Branch::Signal(Signal::new(token)))
+            }
+            _ => {}
+        }
+    }
+
+    debug!(slog_scope::logger(), "{:?} parser finished.", kind);
+    Ok((parse, note_ids))
+}
 
-                // Determine if that footnote has been referenced before. If it
-                // hasn't, add it to the list of referenced foototes.
-                if !referred_fns.contains(&footnote_number) {
-                    trace!(
+/// Create a [`CrossRef`] branch from a `CrossRef` token.
+///
+/// The chapter the cross-reference is qualified with, if any, is parsed into a
+/// number.
+fn cross_ref<'a>(
+    token: &'a Token<'a>,
+    kind: NoteKind,
+    note_id: Option<&'a str>,
+) -> Result<CrossRef<'a>, Diagnostic> {
+    let chapter = match &token.chapter {
+        Some(c) => match c.parse::<u32>() {
+            Ok(c) => Some(c),
+            Err(e) => {
+                let err_msg = format!("Error parsing cross references: {}", e);
+                let mut diagnostic = Diagnostic::error(Kind::Reference, err_msg);
+                if let Some(o) = token.offset {
+                    diagnostic = diagnostic.at(o);
+                }
+                if let Some(c) = &token.context {
+                    diagnostic = diagnostic.with_context(c);
+                }
+                return Err(diagnostic);
+            }
+        },
+        None => None,
+    };
+    let label = match &token.text {
+        Some(t) => t.clone(),
+        None => token.contents.to_string(),
+    };
+    let runs = token.runs.unwrap_or(RunProps {
+        first: "",
+        last: "",
+    });
+
+    Ok(CrossRef {
+        direction: token.direction,
+        signal: token.signal,
+        offset: token.offset,
+        ..CrossRef::new(
+            kind,
+            label,
+            token.contents,
+            runs,
+            chapter,
+            note_id,
+            token.context.as_deref().unwrap_or_default(),
+        )
+    })
+}
+
+/// Resolve the cross-references to the notes they refer to.
+///
+/// A cross-reference refers to the note of the same kind with the number (or
+/// custom mark) written. When numbering restarts, several notes can share a
+/// number. Then a cross-reference with a chapter refers to the note in that
+/// chapter (sections are numbered from 1), and one without a chapter refers to
+/// the note in its own section.
+///
+/// This function also adds each referenced note to `referred_notes`, which is
+/// used to determine which note references in `document.xml` need bookmark
+/// markup added. A cross-reference to a note that does not exist is added to
+/// `dangling`, with where it is, the note that it is in, and the text around
+/// it.
+fn resolve_cr(
+    tree: &mut [Branch],
+    notes: &[Note],
+    referred_notes: &mut Vec<(NoteKind, u32)>,
+    dangling: &mut Vec<Diagnostic>,
+) -> Result<(), Diagnostic> {
+    debug!(slog_scope::logger(), "Resolving cross-references...");
+
+    for branch in tree.iter_mut() {
+        let cross_ref = match branch {
+            Branch::CrossRef(c) => c,
+            _ => continue,
+        };
+
+        // Find the section that the cross-reference is in.
+        let containing = containing(cross_ref, notes);
+        let section = cross_ref.section.or_else(|| containing.map(|n| n.section));
+
+        // Find the notes the cross-reference might refer to.
+        let candidates: Vec<&Note> = notes
+            .iter()
+            .filter(|n| n.kind == cross_ref.kind && n.matches(&cross_ref.label))
+            .filter(|n| match cross_ref.chapter {
+                Some(c) => n.section + 1 == c as usize,
+                None => true,
+            })
+            .collect();
+
+        let target = match candidates.len() {
+            0 => {
+                let chapter = match cross_ref.chapter {
+                    Some(c) => format!(" in chapter {}", c),
+                    None => String::new(),
+                };
+                let err_msg = format!(
+                    "{} refers to {} {}{}, which does not exist",
+                    place(cross_ref, containing),
+                    cross_ref.kind.name(),
+                    cross_ref.label,
+                    chapter
+                );
+                let diagnostic = Diagnostic::error(Kind::Reference, err_msg);
+                dangling.push(locate(diagnostic, cross_ref, containing));
+                None
+            }
+            1 => Some(candidates[0]),
+            _ => match candidates.iter().find(|n| Some(n.section) == section) {
+                Some(n) => Some(*n),
+                None => {
+                    warn!(
                         slog_scope::logger(),
-                        "Adding footnote {} to used cross-references",
-                        footnote_number
+                        "The cross-reference to {:?} {} is ambiguous; add a chapter to it",
+                        cross_ref.kind,
+                        cross_ref.label
                     );
-                    referred_fns.push(footnote_number);
+                    None
                 }
+            },
+        };
 
-                // Push the new branch.
+        if let Some(note) = target {
+            trace!(
+                slog_scope::logger(),
+                "Resolved cross-reference to {:?} {} as {:?} {}",
+                cross_ref.kind,
+                cross_ref.label,
+                note.kind,
+                note.ordinal
+            );
+            cross_ref.target = Some(note.ordinal);
+            cross_ref.result = Some(note.display());
+
+            // Determine if that note has been referenced before. If it hasn't,
+            // add it to the list of referenced notes.
+            if !referred_notes.contains(&(note.kind, note.ordinal)) {
                 trace!(
                     slog_scope::logger(),
-                    "Pushing branch type CrossRef for footnote {}",
-                    footnote_number,
+                    "Adding {:?} {} to used cross-references",
+                    note.kind,
+                    note.ordinal
                 );
-                parse.push(Branch::CrossRef(CrossRef::new(footnote_number)))
+                referred_notes.push((note.kind, note.ordinal));
             }
-            _ => {}
         }
     }
 
-    debug!(slog_scope::logger(), "Footnote parser finished.");
-    Ok((parse, referred_fns))
+    debug!(slog_scope::logger(), "Cross-references resolved.");
+    Ok(())
+}
+
+/// Check that the resolved cross-references point the way their signals say.
+///
+/// In a note, a cross-reference points the way of the note it refers to from
+/// the note it is in. In the body text, it points the way of that note's
+/// reference from where the cross-reference is. A cross-reference in a note
+/// that refers to that same note, or whose signal points the wrong way
+/// (*e.g.*, "*supra* note 40" in note 22, or "*infra* note 84" in the body
+/// text after note 84's reference), is added to `misdirected` as a warning.
+/// With `fix_signals`, a signal that points the wrong way for every
+/// cross-reference that has it (*e.g.*, the "*supra*" in "*supra* notes
+/// 40–41" in note 22) is changed to point the other way instead (see
+/// [`grammar::opposite`]), as long as its word is a [`Signal`] of its own. The
+/// word is the same length either way, so its run keeps its formatting
+/// (*e.g.*, the italics).
+fn resolve_signals(
+    tree: &mut [Branch],
+    notes: &[Note],
+    fix_signals: bool,
+    misdirected: &mut Vec<Diagnostic>,
+) {
+    debug!(slog_scope::logger(), "Checking signals...");
+
+    // The cross-references that point the wrong way, with their signals, and
+    // the signals that some cross-reference needs as they are
+    let mut wrong: Vec<(Option<usize>, Diagnostic)> = Vec::new();
+    let mut keep: HashSet<usize> = HashSet::new();
+
+    // Where each note's reference is in the body text
+    let marks: HashMap<(NoteKind, u32), usize> = tree
+        .iter()
+        .enumerate()
+        .filter_map(|(i, b)| match b {
+            Branch::NoteRef(n) => Some(((n.kind, n.ordinal), i)),
+            _ => None,
+        })
+        .collect();
+
+    for (i, branch) in tree.iter().enumerate() {
+        let cross_ref = match branch {
+            Branch::CrossRef(c) => c,
+            _ => continue,
+        };
+        let containing = containing(cross_ref, notes);
+
+        // Whether the note referred to comes before or after the
+        // cross-reference
+        let order = match (cross_ref.target, containing, cross_ref.note_id) {
+            (Some(t), Some(c), _) => Some(t.cmp(&c.ordinal)),
+            (Some(t), None, None) => marks.get(&(cross_ref.kind, t)).map(|m| m.cmp(&i)),
+            _ => None,
+        };
+        let order = match order {
+            Some(o) => o,
+            None => {
+                keep.extend(cross_ref.signal);
+                continue;
+            }
+        };
+
+        let place = place(cross_ref, containing);
+        let result = cross_ref.result.as_deref().unwrap_or(&cross_ref.label);
+        let warning_msg = match (cross_ref.direction, order) {
+            (_, Ordering::Equal) => {
+                keep.extend(cross_ref.signal);
+                format!("{} refers to itself", place)
+            }
+            (Some(Direction::Supra), Ordering::Greater) => format!(
+                "{} refers to {} {} as supra, but that note comes after it",
+                place,
+                cross_ref.kind.name(),
+                result
+            ),
+            (Some(Direction::Infra), Ordering::Less) => format!(
+                "{} refers to {} {} as infra, but that note comes before it",
+                place,
+                cross_ref.kind.name(),
+                result
+            ),
+            _ => {
+                keep.extend(cross_ref.signal);
+                continue;
+            }
+        };
+        let diagnostic = Diagnostic::warning(Kind::Reference, warning_msg);
+        wrong.push((cross_ref.signal, locate(diagnostic, cross_ref, containing)));
+    }
+
+    // Change the signals that only point the wrong way
+    let mut fixed: HashSet<usize> = HashSet::new();
+    if fix_signals {
+        for branch in tree.iter_mut() {
+            let signal = match branch {
+                Branch::Signal(s) => s,
+                _ => continue,
+            };
+            let offset = match signal.offset {
+                Some(o) if !keep.contains(&o) => o,
+                _ => continue,
+            };
+            let problem = match wrong.iter().find(|(s, _)| *s == Some(offset)) {
+                Some((_, d)) => d,
+                None => continue,
+            };
+            if let Some(word) = grammar::opposite(signal.contents) {
+                info!(
+                    slog_scope::logger(),
+                    "{}; changing {:?} to {:?}", problem.message, signal.contents, word
+                );
+                signal.result = Some(word);
+                fixed.insert(offset);
+            }
+        }
+    }
+
+    misdirected.extend(
+        wrong
+            .into_iter()
+            .filter(|(s, _)| !s.is_some_and(|s| fixed.contains(&s)))
+            .map(|(_, d)| d),
+    );
+    debug!(slog_scope::logger(), "Signals checked.");
+}
+
+/// Find the note that a cross-reference is in, if any.
+fn containing<'n, 'a>(cross_ref: &CrossRef, notes: &'n [Note<'a>]) -> Option<&'n Note<'a>> {
+    let id = cross_ref.note_id?;
+    notes
+        .iter()
+        .find(|n| n.kind == cross_ref.kind && n.id == id)
+}
+
+/// Name the place a cross-reference is in (*e.g.*, "Footnote 22"), for
+/// messages about it.
+fn place(cross_ref: &CrossRef, containing: Option<&Note>) -> String {
+    match (cross_ref.note_id, containing) {
+        (Some(_), Some(n)) => format!("{:?} {}", n.kind, n.display()),
+        (Some(id), None) => format!("The {} with id {}", cross_ref.kind.name(), id),
+        (None, _) => "The body text".to_string(),
+    }
+}
+
+/// Add where a cross-reference is to a [`Diagnostic`] about it: the part, the
+/// offset, the note it is in, and the text around it.
+fn locate(diagnostic: Diagnostic, cross_ref: &CrossRef, containing: Option<&Note>) -> Diagnostic {
+    let part = match cross_ref.note_id {
+        Some(_) => cross_ref.kind.part(),
+        None => diagnostic::DOCUMENT_PART,
+    };
+    let mut diagnostic = diagnostic.in_part(part).with_context(cross_ref.context);
+    if let Some(o) = cross_ref.offset {
+        diagnostic = diagnostic.at(o);
+    }
+    if let Some(Label::Number(n)) = containing.map(|n| n.label) {
+        diagnostic = diagnostic.in_note(n);
+    }
+    diagnostic
+}
+
+/// Resolve the paragraph references to the numbered paragraphs they refer to.
+///
+/// A paragraph reference refers to the paragraph with that number in full
+/// context (*e.g.*, "II.A" or "4.2(b)"), which a `REF` field displays with its
+/// `\w` switch. Failing that, it refers to the only paragraph with that number
+/// on its own (*e.g.*, "A" or "12"), which the `\r` switch displays, in the
+/// context of wherever the field is. A heading reference (*e.g.*, "Section 3")
+/// prefers headings to other paragraphs with the same number. Words before the
+/// number (*e.g.*, the "Part" in "Part I") are left out with the `\t` switch,
+/// and every field links to its paragraph (`\h`).
+///
+/// This function also adds each referenced paragraph to
+/// `referred_paragraphs`, which is used to determine which paragraphs need
+/// bookmark markup added.
+fn resolve_pr(tree: &mut [Branch], paragraphs: &[Numbered], referred_paragraphs: &mut Vec<usize>) {
+    debug!(slog_scope::logger(), "Resolving paragraph references...");
+
+    for branch in tree.iter_mut() {
+        let paragraph_ref = match branch {
+            Branch::ParagraphRef(p) => p,
+            _ => continue,
+        };
+
+        let find = |number: fn(&Numbered) -> &str| -> Vec<usize> {
+            let mut found: Vec<usize> = paragraphs
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| number(p) == paragraph_ref.label)
+                .map(|(i, _)| i)
+                .collect();
+            if paragraph_ref.heading && found.iter().any(|i| paragraphs[*i].heading) {
+                found.retain(|i| paragraphs[*i].heading);
+            }
+            found
+        };
+        let full = find(|p| &p.full);
+        let (candidates, switch) = if full.is_empty() {
+            (find(|p| &p.number), r"\r")
+        } else {
+            (full, r"\w")
+        };
+
+        let index = match candidates[..] {
+            [i] => i,
+            [] => {
+                debug!(
+                    slog_scope::logger(),
+                    "No paragraph is numbered {}", paragraph_ref.label
+                );
+                continue;
+            }
+            _ => {
+                warn!(
+                    slog_scope::logger(),
+                    "The reference to paragraph {} is ambiguous; write its number in full",
+                    paragraph_ref.label
+                );
+                continue;
+            }
+        };
+
+        let paragraph = &paragraphs[index];
+        trace!(
+            slog_scope::logger(),
+            "Resolved reference to {} as paragraph {}",
+            paragraph_ref.label,
+            index
+        );
+        paragraph_ref.target = Some(index);
+        paragraph_ref.result = Some(paragraph_ref.label.clone());
+        paragraph_ref.switches = match paragraph.prefixed {
+            true => format!(r"{} \t \h", switch),
+            false => format!(r"{} \h", switch),
+        };
+        if !referred_paragraphs.contains(&index) {
+            referred_paragraphs.push(index);
+        }
+    }
+
+    debug!(slog_scope::logger(), "Paragraph references resolved.");
+}
+
+/// Resolve the references to tables and figures to the captions they refer
+/// to.
+///
+/// A reference refers to the caption of the same kind with the number written
+/// (see [`Caption`]). Its field displays the number that Word will give that
+/// caption, which differs from the written number if the captions were out of
+/// order.
+///
+/// This function also adds each referenced caption to `referred_captions`,
+/// which is used to determine which captions need bookmark markup added.
+fn resolve_captions(
+    tree: &mut [Branch],
+    captions: &[(CaptionKind, String, u32)],
+    referred_captions: &mut Vec<(CaptionKind, u32)>,
+) {
+    debug!(slog_scope::logger(), "Resolving references to captions...");
+
+    for branch in tree.iter_mut() {
+        let caption_ref = match branch {
+            Branch::CaptionRef(c) => c,
+            _ => continue,
+        };
+
+        let candidates: Vec<u32> = captions
+            .iter()
+            .filter(|(kind, label, _)| *kind == caption_ref.kind && *label == caption_ref.label)
+            .map(|(_, _, sequence)| *sequence)
+            .collect();
+        let sequence = match candidates[..] {
+            [s] => s,
+            [] => {
+                debug!(
+                    slog_scope::logger(),
+                    "No caption is {} {}",
+                    caption_ref.kind.label(),
+                    caption_ref.label
+                );
+                continue;
+            }
+            _ => {
+                warn!(
+                    slog_scope::logger(),
+                    "The reference to {} {} is ambiguous; several captions have that number",
+                    caption_ref.kind.label(),
+                    caption_ref.label
+                );
+                continue;
+            }
+        };
+
+        trace!(
+            slog_scope::logger(),
+            "Resolved reference to {} {} as number {}",
+            caption_ref.kind.label(),
+            caption_ref.label,
+            sequence
+        );
+        caption_ref.target = Some(sequence);
+        caption_ref.result = Some(sequence.to_string());
+        if !referred_captions.contains(&(caption_ref.kind, sequence)) {
+            referred_captions.push((caption_ref.kind, sequence));
+        }
+    }
+
+    debug!(slog_scope::logger(), "References to captions resolved.");
 }
@@ -1,47 +1,88 @@
 //! The module contains functionality for rendeing the new xml contents.
 
-use crate::parser::Branch;
-use slog::{debug, o};
+use crate::{
+    captions::CaptionKind,
+    diagnostic::Diagnostic,
+    numbering::Numbered,
+    paragraph::RunProps,
+    parser::{Branch, Caption, CaptionRef, CrossRef, NoteKind, ParagraphRef, Signal},
+};
+use slog::{debug, o, trace, warn};
 use std::collections::HashMap;
 
+/// The complex type that [`render`] returns.
+///
+/// This is the new contents of `document.xml` and, if the document has them,
+/// `footnotes.xml` and `endnotes.xml`.
+type RenderResults = (String, Option<String>, Option<String>);
+
+/// The reference ids for each cross-referenced note, keyed by the note's kind
+/// and ordinal.
+type RefIds = HashMap<(NoteKind, u32), String>;
+
 /// The main render function.
+#[allow(clippy::too_many_arguments)]
 pub fn render(
     doc_tree: &[Branch],
-    refd_notes: Vec<u32>,
+    refd_notes: Vec<(NoteKind, u32)>,
+    paragraphs: &[Numbered],
+    refd_paragraphs: &[usize],
+    refd_captions: &[(CaptionKind, u32)],
     starting_bookmark: u32,
-    fn_tree: &[Branch],
-) -> Result<(String, String), String> {
+    fn_tree: Option<&[Branch]>,
+    en_tree: Option<&[Branch]>,
+) -> Result<RenderResults, Diagnostic> {
     debug!(slog_scope::logger(), "Beginning rendering...");
 
     // Render document.xml
-    let (doc_output, ref_ids) = match slog_scope::scope(
+    let (doc_output, ref_ids) = slog_scope::scope(
         &slog_scope::logger().new(o!("fn" => "render_doc()")),
-        || render_doc(doc_tree, refd_notes, starting_bookmark),
-    ) {
-        Ok(t) => t,
-        Err(e) => return Err(e),
-    };
+        || {
+            render_doc(
+                doc_tree,
+                refd_notes,
+                ParagraphBookmarks::new(paragraphs, refd_paragraphs),
+                refd_captions,
+                starting_bookmark,
+            )
+        },
+    )?;
 
     // Render footnotes.xml
-    let fn_output =
-        match slog_scope::scope(&slog_scope::logger().new(o!("fn" => "render_fn()")), || {
-            render_fn(fn_tree, ref_ids)
-        }) {
-            Ok(f) => f,
-            Err(e) => return Err(e),
-        };
+    let fn_output = match fn_tree {
+        Some(t) => Some(slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "render_fn()")),
+            || render_fn(t, &ref_ids),
+        )?),
+        None => None,
+    };
+
+    // Render endnotes.xml
+    let en_output = match en_tree {
+        Some(t) => Some(slog_scope::scope(
+            &slog_scope::logger().new(o!("fn" => "render_fn()")),
+            || render_fn(t, &ref_ids),
+        )?),
+        None => None,
+    };
 
     debug!(slog_scope::logger(), "Rendering finished.");
-    Ok((doc_output, fn_output))
+    Ok((doc_output, fn_output, en_output))
 }
 
 /// Render the `document.xml` contents.
 ///
 /// This function produces the new `document.xml` contents, with bookmark markup
-/// added to footnote references. It also builds a [`HashMap`] in which the keys
-/// are footnote numbers and the values are the Word reference ids (*e.g.*,
-/// "_Ref000000001"). This [`HashMap`] is later used for the cross-reference
-/// markup.
+/// added to note references and field markup added to the cross-references in
+/// the body text (see [`render_fn`]). The numbered paragraphs that are
+/// referred to are bookmarked around their contents (see
+/// [`ParagraphBookmarks`]). The numbers in captions become `SEQ` fields, and
+/// those that are referred to (`refd_captions`) are bookmarked, too (see
+/// [`render_caption`]). The bookmark ids count up from `starting_bookmark` in
+/// the order the bookmarks appear. It also builds a [`HashMap`] in which the
+/// keys are note kinds and ordinals and the values are the Word reference ids
+/// (*e.g.*, "_Ref000000001"). This [`HashMap`] is later used for the
+/// cross-reference markup.
 ///
 /// **The Markup for Bookmarks**
 ///
@@ -62,37 +103,56 @@ pub fn render(
 /// bookmark.
 fn render_doc(
     tree: &[Branch],
-    refd_notes: Vec<u32>,
+    refd_notes: Vec<(NoteKind, u32)>,
+    mut paragraph_bookmarks: ParagraphBookmarks,
+    refd_captions: &[(CaptionKind, u32)],
     mut starting_bookmark: u32,
-) -> Result<(String, HashMap<u32, String>), String> {
+) -> Result<(String, RefIds), Diagnostic> {
     debug!(slog_scope::logger(), "Beginning document rendering...");
 
     // This `String` is given a 500kB capacity to minimize re-allocation.
     let mut doc_output = String::with_capacity(512000);
 
     // This is the collection of reference ids (*e.g.*, "_Ref000000001") for
-    // each cross-referenced footnote
-    let mut ref_ids: HashMap<u32, String> = HashMap::new();
+    // each cross-referenced note
+    let mut ref_ids: RefIds = HashMap::new();
+
+    // Where each branch starts in the input, for the paragraph bookmarks
+    let mut position = 0;
 
     for branch in tree {
+        // A paragraph's bookmark can start or end inside text, but not inside
+        // the other branches
+        let start = position;
+        position += branch.contents().len();
+        if !matches!(branch, Branch::Text(_)) {
+            paragraph_bookmarks.mark(&mut doc_output, start, &mut starting_bookmark);
+        }
+
         match branch {
-            Branch::Text(text) => doc_output.push_str(text.contents),
-            Branch::FootnoteRef(footnote_ref) => {
-                // Determine if this footnote reference is ever referred to. If
-                // it is, it needs a bookmark.
-                if refd_notes.contains(&footnote_ref.number) {
+            Branch::Text(text) => paragraph_bookmarks.copy(
+                &mut doc_output,
+                text.contents,
+                start,
+                &mut starting_bookmark,
+            ),
+            Branch::NoteRef(note_ref) => {
+                // Determine if this note reference is ever referred to. If it
+                // is, it needs a bookmark.
+                let key = (note_ref.kind, note_ref.ordinal);
+                if refd_notes.contains(&key) {
                     // First create a unique reference id
-                    let ref_id = create_ref_id(footnote_ref.number);
+                    let ref_id = create_ref_id(note_ref.kind, note_ref.ordinal);
 
                     // Add that reference id to the collection
-                    ref_ids.insert(footnote_ref.number, ref_id.clone());
+                    ref_ids.insert(key, ref_id.clone());
 
                     // Add the markup
                     doc_output.push_str(&format!(
                         r#"<w:bookmarkStart w:id="{}" w:name="{}"/>"#,
                         starting_bookmark, ref_id
                     ));
-                    doc_output.push_str(footnote_ref.contents);
+                    doc_output.push_str(note_ref.contents);
                     doc_output
                         .push_str(&format!(r#"<w:bookmarkEnd w:id="{}"/>"#, starting_bookmark));
 
@@ -101,21 +161,44 @@ fn render_doc(
                 } else {
                     // If it's not ever referred to, just add what was already
                     // there
-                    doc_output.push_str(footnote_ref.contents);
+                    doc_output.push_str(note_ref.contents);
                 }
             }
-            _ => {}
+            Branch::CrossRef(cross_ref) => {
+                // A cross-reference in the body text can come before the note
+                // it refers to, so its reference id is created directly. Every
+                // resolved cross-reference's note is bookmarked.
+                let ref_id = cross_ref
+                    .target
+                    .filter(|t| refd_notes.contains(&(cross_ref.kind, *t)))
+                    .map(|t| create_ref_id(cross_ref.kind, t));
+                render_cross_ref(&mut doc_output, cross_ref, ref_id.as_deref());
+            }
+            Branch::ParagraphRef(paragraph_ref) => {
+                render_paragraph_ref(&mut doc_output, paragraph_ref)
+            }
+            Branch::Caption(caption) => {
+                if refd_captions.contains(&(caption.kind, caption.sequence)) {
+                    render_caption(&mut doc_output, caption, Some(starting_bookmark));
+                    starting_bookmark += 1;
+                } else {
+                    render_caption(&mut doc_output, caption, None);
+                }
+            }
+            Branch::CaptionRef(caption_ref) => render_caption_ref(&mut doc_output, caption_ref),
+            Branch::Signal(signal) => render_signal(&mut doc_output, signal),
         }
     }
+    paragraph_bookmarks.mark(&mut doc_output, position, &mut starting_bookmark);
 
     debug!(slog_scope::logger(), "Document rendering finished.");
     Ok((doc_output, ref_ids))
 }
 
-/// Render the `footnotes.xml` contents.
+/// Render the `footnotes.xml` or `endnotes.xml` contents.
 ///
-/// This function produces the new `footnotes.xml` contents, with markup added
-/// to each cross-reference.
+/// This function produces the new notes contents, with markup added to each
+/// cross-reference.
 ///
 /// **The Markup for Cross-References**
 ///
@@ -127,8 +210,8 @@ fn render_doc(
 /// ```text
 /// </w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>1</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">
 /// ```
-fn render_fn(tree: &[Branch], ref_ids: HashMap<u32, String>) -> Result<String, String> {
-    debug!(slog_scope::logger(), "Beginning footnote rendering...");
+fn render_fn(tree: &[Branch], ref_ids: &RefIds) -> Result<String, Diagnostic> {
+    debug!(slog_scope::logger(), "Beginning note rendering...");
 
     // TODO This should probably be a string with some capacity to avoid
     // reallocations.
@@ -138,29 +221,282 @@ fn render_fn(tree: &[Branch], ref_ids: HashMap<u32, String>) -> Result<String, S
         match branch {
             Branch::Text(text) => fn_output.push_str(text.contents),
             Branch::CrossRef(cross_ref) => {
-                // Add the cross-reference field markup.
-                fn_output.push_str(&format!(
-                    r#"</w:t></w:r><w:fldSimple w:instr=" NOTEREF {} "><w:r><w:t>{}</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">"#,
-                    ref_ids[&cross_ref.number],
-                    cross_ref.number
-                ));
+                let ref_id = cross_ref
+                    .target
+                    .and_then(|t| ref_ids.get(&(cross_ref.kind, t)));
+                render_cross_ref(&mut fn_output, cross_ref, ref_id.map(String::as_str));
+            }
+            Branch::ParagraphRef(paragraph_ref) => {
+                render_paragraph_ref(&mut fn_output, paragraph_ref)
             }
+            Branch::CaptionRef(caption_ref) => render_caption_ref(&mut fn_output, caption_ref),
+            Branch::Signal(signal) => render_signal(&mut fn_output, signal),
             _ => {}
         }
     }
 
-    debug!(slog_scope::logger(), "Footnote rendering finished.");
+    debug!(slog_scope::logger(), "Note rendering finished.");
     Ok(fn_output)
 }
 
+/// Render a cross-reference.
+///
+/// A cross-reference to a bookmarked note (`ref_id`) gets the field markup.
+/// This replaces the cross-reference's contents, splitting the runs it starts
+/// and ends in around the field. Each side keeps its run's properties. A
+/// cross-reference that could not be resolved is left as plain text.
+fn render_cross_ref(output: &mut String, cross_ref: &CrossRef, ref_id: Option<&str>) {
+    match ref_id {
+        Some(ref_id) => render_field(
+            output,
+            &format!("NOTEREF {}", ref_id),
+            cross_ref.result.as_deref().unwrap_or(&cross_ref.label),
+            &cross_ref.runs,
+            None,
+        ),
+        None => {
+            debug!(
+                slog_scope::logger(),
+                "Leaving unresolved cross-reference to {:?} {} as text",
+                cross_ref.kind,
+                cross_ref.label
+            );
+            output.push_str(cross_ref.contents);
+        }
+    }
+}
+
+/// Render the word of a signal, changed if it pointed the wrong way.
+fn render_signal(output: &mut String, signal: &Signal) {
+    match &signal.result {
+        Some(word) => {
+            trace!(
+                slog_scope::logger(),
+                "Changing the signal {:?} to {:?}",
+                signal.contents,
+                word
+            );
+            output.push_str(word);
+        }
+        None => output.push_str(signal.contents),
+    }
+}
+
+/// Render a reference to a numbered paragraph (or heading).
+///
+/// A reference to a paragraph gets a `REF` field for the paragraph's bookmark
+/// (see [`ParagraphBookmarks`]), the same way as a cross-reference to a note. A
+/// reference that could not be resolved is left as plain text.
+fn render_paragraph_ref(output: &mut String, paragraph_ref: &ParagraphRef) {
+    match paragraph_ref.target {
+        Some(target) => render_field(
+            output,
+            &format!(
+                "REF {} {}",
+                create_paragraph_ref_id(target),
+                paragraph_ref.switches
+            ),
+            paragraph_ref
+                .result
+                .as_deref()
+                .unwrap_or(&paragraph_ref.label),
+            &paragraph_ref.runs,
+            None,
+        ),
+        None => {
+            warn!(
+                slog_scope::logger(),
+                "Leaving unresolved reference to paragraph {} as text", paragraph_ref.label
+            );
+            output.push_str(paragraph_ref.contents);
+        }
+    }
+}
+
+/// Render the number in a caption.
+///
+/// The number becomes a `SEQ` field, which Word counts with the other captions
+/// of the same kind (*e.g.*, `SEQ Table \* ARABIC`). A caption that is referred
+/// to gets a bookmark around the field, with the id `bookmark`, so that a `REF`
+/// field displays just the number.
+fn render_caption(output: &mut String, caption: &Caption, bookmark: Option<u32>) {
+    let ref_id = create_caption_ref_id(caption.kind, caption.sequence);
+    render_field(
+        output,
+        &format!(r"SEQ {} \* ARABIC", caption.kind.label()),
+        &caption.sequence.to_string(),
+        &caption.runs,
+        bookmark.map(|b| (b, ref_id.as_str())),
+    )
+}
+
+/// Render a reference to a table or figure.
+///
+/// A reference to a caption gets a `REF` field for the caption's bookmark (see
+/// [`render_caption`]). A reference that could not be resolved is left as
+/// plain text.
+fn render_caption_ref(output: &mut String, caption_ref: &CaptionRef) {
+    match caption_ref.target {
+        Some(target) => render_field(
+            output,
+            &format!(
+                r"REF {} \h",
+                create_caption_ref_id(caption_ref.kind, target)
+            ),
+            caption_ref.result.as_deref().unwrap_or(&caption_ref.label),
+            &caption_ref.runs,
+            None,
+        ),
+        None => {
+            warn!(
+                slog_scope::logger(),
+                "Leaving unresolved reference to {} {} as text",
+                caption_ref.kind.label(),
+                caption_ref.label
+            );
+            output.push_str(caption_ref.contents);
+        }
+    }
+}
+
+/// Render a field with its cached result in place of a cross-reference,
+/// splitting the runs it starts and ends in (see [`render_fn`]). A field can
+/// be bookmarked with a bookmark id and a reference id.
+fn render_field(
+    output: &mut String,
+    instruction: &str,
+    result: &str,
+    runs: &RunProps,
+    bookmark: Option<(u32, &str)>,
+) {
+    let (bookmark_start, bookmark_end) = match bookmark {
+        Some((id, ref_id)) => (
+            format!(r#"<w:bookmarkStart w:id="{}" w:name="{}"/>"#, id, ref_id),
+            format!(r#"<w:bookmarkEnd w:id="{}"/>"#, id),
+        ),
+        None => (String::new(), String::new()),
+    };
+    preserve_space(output);
+    output.push_str(&format!(
+        r#"</w:t></w:r>{}<w:fldSimple w:instr=" {} "><w:r>{}<w:t>{}</w:t></w:r></w:fldSimple>{}<w:r>{}<w:t xml:space="preserve">"#,
+        bookmark_start, instruction, runs.first, result, bookmark_end, runs.last,
+    ))
+}
+
+/// The bookmarks for the numbered paragraphs that are referred to.
+///
+/// Each bookmark goes around the contents of a paragraph (after its
+/// properties), which is where Word puts the bookmarks for its own
+/// cross-references to headings. The bookmarks are added while the document is
+/// rendered, so that they share their ids with the other bookmarks.
+struct ParagraphBookmarks<'a> {
+    /// The paragraphs still to be bookmarked, with their indexes, from last
+    /// to first
+    pending: Vec<(usize, &'a Numbered)>,
+    /// The id of the bookmark that was started and where it ends
+    open: Option<(u32, usize)>,
+}
+
+impl<'a> ParagraphBookmarks<'a> {
+    /// Create the bookmarks for the `paragraphs` whose indexes are in
+    /// `refd_paragraphs`.
+    fn new(paragraphs: &'a [Numbered], refd_paragraphs: &[usize]) -> ParagraphBookmarks<'a> {
+        let mut pending: Vec<(usize, &Numbered)> = refd_paragraphs
+            .iter()
+            .filter_map(|i| paragraphs.get(*i).map(|p| (*i, p)))
+            .collect();
+        pending.sort_by_key(|(_, p)| std::cmp::Reverse(p.bookmark.start));
+        ParagraphBookmarks {
+            pending,
+            open: None,
+        }
+    }
+
+    /// Where the next bookmark starts or ends in the input.
+    fn next(&self) -> Option<usize> {
+        match (self.open, self.pending.last()) {
+            (Some((_, end)), _) => Some(end),
+            (None, Some((_, p))) => Some(p.bookmark.start),
+            (None, None) => None,
+        }
+    }
+
+    /// Add the markup for the bookmarks that start or end at or before
+    /// `position` in the input. A bookmark that is started takes the id
+    /// `bookmark`, which is then iterated.
+    fn mark(&mut self, output: &mut String, position: usize, bookmark: &mut u32) {
+        while self.next().filter(|n| *n <= position).is_some() {
+            match self.open.take() {
+                Some((id, _)) => {
+                    output.push_str(&format!(r#"<w:bookmarkEnd w:id="{}"/>"#, id));
+                }
+                None => {
+                    let (index, paragraph) = self.pending.pop().unwrap();
+                    let ref_id = create_paragraph_ref_id(index);
+                    trace!(
+                        slog_scope::logger(),
+                        "Bookmarking paragraph {} as {}",
+                        paragraph.full,
+                        ref_id
+                    );
+                    output.push_str(&format!(
+                        r#"<w:bookmarkStart w:id="{}" w:name="{}"/>"#,
+                        bookmark, ref_id
+                    ));
+                    self.open = Some((*bookmark, paragraph.bookmark.end));
+                    *bookmark += 1;
+                }
+            }
+        }
+    }
+
+    /// Copy `contents`, which starts at `position` in the input, with the
+    /// markup for the bookmarks that start or end in it.
+    fn copy(&mut self, output: &mut String, contents: &str, position: usize, bookmark: &mut u32) {
+        let end = position + contents.len();
+        let mut copied = position;
+        while let Some(next) = self.next().filter(|n| *n <= end) {
+            let next = next.max(copied);
+            output.push_str(&contents[copied - position..next - position]);
+            self.mark(output, next, bookmark);
+            copied = next;
+        }
+        output.push_str(&contents[copied - position..]);
+    }
+}
+
+/// Preserve the spaces at the end of the text that a run is split after.
+///
+/// The output ends inside a `w:t` element. Without `xml:space="preserve"`,
+/// Word drops the spaces at the end of that element's text, which would run
+/// the text together with the field that follows.
+fn preserve_space(output: &mut String) {
+    let start = match output.rfind("<w:t>").max(output.rfind("<w:t ")) {
+        Some(s) => s,
+        None => return,
+    };
+    let tag_end = match output[start..].find('>') {
+        Some(e) => start + e,
+        None => return,
+    };
+    if !output[start..tag_end].contains("xml:space") {
+        output.insert_str(start + "<w:t".len(), r#" xml:space="preserve""#);
+    }
+}
+
 /// Create a unique reference id.
 ///
-/// This function creates a unique reference id for a footnote reference. It
-/// uses that footnote reference's footnote number to create the id.
-fn create_ref_id(number: u32) -> String {
-    let number_str = number.to_string();
+/// This function creates a unique reference id for a note reference. It uses
+/// that note reference's ordinal—which, unlike its displayed number, never
+/// repeats—to create the id. Endnotes get a different prefix so that they do
+/// not collide with footnotes of the same number.
+fn create_ref_id(kind: NoteKind, ordinal: u32) -> String {
+    let number_str = ordinal.to_string();
     let mut ref_id = String::with_capacity(13);
-    ref_id.push_str("_Ref");
+    ref_id.push_str(match kind {
+        NoteKind::Footnote => "_Ref",
+        NoteKind::Endnote => "_RefEn",
+    });
 
     // Loop through the necessary zeros
     while ref_id.len() < 13 - number_str.len() {
@@ -172,3 +508,15 @@ fn create_ref_id(number: u32) -> String {
 
     ref_id
 }
+
+/// Create the reference id for a numbered paragraph from its index (*e.g.*,
+/// "_RefPara00001").
+fn create_paragraph_ref_id(index: usize) -> String {
+    format!("_RefPara{:05}", index + 1)
+}
+
+/// Create the reference id for a caption from its kind and sequence number
+/// (*e.g.*, "_RefTable00003").
+fn create_caption_ref_id(kind: CaptionKind, sequence: u32) -> String {
+    format!("_Ref{}{:05}", kind.label(), sequence)
+}
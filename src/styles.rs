@@ -0,0 +1,300 @@
+//! This module contains the functionality for reading the document's styles.
+//!
+//! Word formats note references with a character style. Note references are
+//! found by their elements, whatever their style, but a custom-mark
+//! reference's mark can be in the run after the reference, and that run is
+//! only part of the reference if it has a reference style. That style's id is
+//! usually `FootnoteReference` (or `EndnoteReference`), but a localized Word or
+//! a custom template can use another id (*e.g.*, `Funotenzeichen` or
+//! `FootnoteAnchor`). The style's name is more reliable: Word always writes the
+//! built-in names in English. So the ids of the reference styles are found
+//! through the names in `styles.xml`.
+//!
+//! Paragraph styles matter, too: a heading style gives its paragraphs an
+//! outline level and, usually, their numbering (see [`crate::numbering`]), and
+//! a caption style marks the captions of tables and figures (see
+//! [`crate::captions`]).
+
+use crate::{
+    diagnostic::Diagnostic,
+    xml::{self, Kind},
+};
+use slog::{debug, trace, warn};
+use std::collections::HashMap;
+
+/// The ids of the reference styles in documents without a styles part.
+const DEFAULT_REFERENCE_STYLES: [&str; 2] = ["FootnoteReference", "EndnoteReference"];
+
+/// The names (in lowercase) of the character styles for note references.
+///
+/// Word uses "footnote reference" and "endnote reference." LibreOffice uses
+/// the "anchor" and "characters" names.
+const REFERENCE_STYLE_NAMES: [&str; 6] = [
+    "footnote reference",
+    "endnote reference",
+    "footnote anchor",
+    "endnote anchor",
+    "footnote characters",
+    "endnote characters",
+];
+
+/// The names (in lowercase) of the paragraph styles for captions.
+///
+/// Word uses "caption." Pandoc uses "Table Caption" and "Image Caption," which
+/// are usually based on "caption," but not always.
+const CAPTION_STYLE_NAMES: [&str; 3] = ["caption", "table caption", "image caption"];
+
+/// The most levels that a style's `w:basedOn` chain is followed through.
+const MAX_BASED_ON: usize = 10;
+
+/// A style in `styles.xml`.
+#[derive(Debug, Default)]
+struct Style<'a> {
+    id: &'a str,
+    character: bool,
+    paragraph: bool,
+    name: Option<&'a str>,
+    based_on: Option<&'a str>,
+    link: Option<&'a str>,
+    outline_level: Option<usize>,
+    num_id: Option<&'a str>,
+    ilvl: Option<usize>,
+}
+
+/// What a paragraph style gives the paragraphs that have it.
+///
+/// This is the outline level (0 for "heading 1") and the numbering (the
+/// `w:numId` of a list in `numbering.xml` and the level in that list), each
+/// either set by the style itself or inherited from the style it is based on.
+/// A style that is (or is based on) a caption style marks captions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParagraphStyle {
+    pub outline_level: Option<usize>,
+    pub num_id: Option<String>,
+    pub ilvl: Option<usize>,
+    pub caption: bool,
+}
+
+/// Find the ids of the character styles for note references.
+///
+/// A character style is a reference style if it has one of the reference
+/// styles' names, or if it is based on or linked to another reference style.
+/// Without a styles part, or if the styles part has no reference styles, this
+/// returns the usual ids.
+pub fn reference_styles(styles_input: Option<&str>) -> Result<Vec<String>, Diagnostic> {
+    let defaults = || DEFAULT_REFERENCE_STYLES.map(String::from).to_vec();
+    let styles_input = match styles_input {
+        Some(s) => s,
+        None => {
+            debug!(
+                slog_scope::logger(),
+                "No styles; using the default reference styles"
+            );
+            return Ok(defaults());
+        }
+    };
+
+    let styles = read_styles(styles_input)?;
+
+    // Find the reference styles by name, and then the styles derived from
+    // them
+    let mut ids: Vec<String> = styles
+        .iter()
+        .filter(|s| s.character)
+        .filter(|s| {
+            s.name
+                .is_some_and(|n| REFERENCE_STYLE_NAMES.contains(&n.to_lowercase().as_str()))
+        })
+        .map(|s| s.id.to_string())
+        .collect();
+    loop {
+        let derived: Vec<String> = styles
+            .iter()
+            .filter(|s| s.character && !ids.iter().any(|i| i == s.id))
+            .filter(|s| {
+                [s.based_on, s.link]
+                    .iter()
+                    .flatten()
+                    .any(|r| ids.iter().any(|i| i == r))
+            })
+            .map(|s| s.id.to_string())
+            .collect();
+        if derived.is_empty() {
+            break;
+        }
+        ids.extend(derived);
+    }
+
+    if ids.is_empty() {
+        warn!(
+            slog_scope::logger(),
+            "No note reference styles found; using the default reference styles"
+        );
+        return Ok(defaults());
+    }
+
+    trace!(slog_scope::logger(), "Found reference styles {:?}", ids);
+    Ok(ids)
+}
+
+/// Find what each paragraph style gives its paragraphs.
+///
+/// The built-in heading styles have their outline levels in `styles.xml`, but
+/// a style named "heading 1" through "heading 9" is treated as a heading even
+/// without one. Caption styles are found by name (see [`CAPTION_STYLE_NAMES`]).
+/// Without a styles part, no style gives anything.
+pub fn paragraph_styles(
+    styles_input: Option<&str>,
+) -> Result<HashMap<String, ParagraphStyle>, Diagnostic> {
+    let styles_input = match styles_input {
+        Some(s) => s,
+        None => {
+            debug!(slog_scope::logger(), "No styles; no paragraph styles");
+            return Ok(HashMap::new());
+        }
+    };
+    let styles = read_styles(styles_input)?;
+    let find = |id: &str| styles.iter().find(|s| s.paragraph && s.id == id);
+
+    let mut paragraph_styles = HashMap::new();
+    for style in styles.iter().filter(|s| s.paragraph) {
+        // Follow the styles it is based on until each property is set
+        let mut properties = ParagraphStyle::default();
+        let mut current = Some(style);
+        for _ in 0..MAX_BASED_ON {
+            let s = match current {
+                Some(s) => s,
+                None => break,
+            };
+            let heading_level = s
+                .name
+                .and_then(|n| {
+                    n.to_lowercase()
+                        .strip_prefix("heading ")?
+                        .parse::<usize>()
+                        .ok()
+                })
+                .filter(|l| (1..=9).contains(l))
+                .map(|l| l - 1);
+            properties.outline_level = properties
+                .outline_level
+                .or(s.outline_level)
+                .or(heading_level);
+            if properties.num_id.is_none() {
+                properties.num_id = s.num_id.map(String::from);
+                properties.ilvl = properties.ilvl.or(s.ilvl);
+            }
+            properties.caption |= s
+                .name
+                .is_some_and(|n| CAPTION_STYLE_NAMES.contains(&n.to_lowercase().as_str()));
+            current = s.based_on.and_then(find);
+        }
+
+        if properties != ParagraphStyle::default() {
+            trace!(
+                slog_scope::logger(),
+                "Paragraph style {} has {:?}",
+                style.id,
+                properties
+            );
+            paragraph_styles.insert(style.id.to_string(), properties);
+        }
+    }
+
+    Ok(paragraph_styles)
+}
+
+/// Read the styles in `styles.xml`.
+fn read_styles(styles_input: &str) -> Result<Vec<Style<'_>>, Diagnostic> {
+    let mut styles: Vec<Style> = Vec::new();
+    let mut style: Option<Style> = None;
+    for event in xml::events(styles_input) {
+        let event = event?;
+        match event.kind {
+            Kind::Start | Kind::Empty if event.name == "style" => {
+                let current = Style {
+                    id: event.attribute("styleId").unwrap_or_default(),
+                    character: event.attribute("type") == Some("character"),
+                    paragraph: event.attribute("type") == Some("paragraph"),
+                    ..Default::default()
+                };
+                if event.kind == Kind::Empty {
+                    styles.push(current);
+                } else {
+                    style = Some(current);
+                }
+            }
+            Kind::End if event.name == "style" => styles.extend(style.take()),
+            Kind::Start | Kind::Empty => {
+                if let Some(style) = style.as_mut() {
+                    let number = || event.attribute("val").and_then(|v| v.parse().ok());
+                    match event.name {
+                        "name" => style.name = event.attribute("val"),
+                        "basedOn" => style.based_on = event.attribute("val"),
+                        "link" => style.link = event.attribute("val"),
+                        "outlineLvl" => style.outline_level = number(),
+                        "numId" => style.num_id = event.attribute("val"),
+                        "ilvl" => style.ilvl = number(),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(styles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn localized_styles() {
+        let input = r#"<w:styles><w:style w:type="paragraph" w:styleId="Funotentext"><w:name w:val="footnote text"/><w:link w:val="FunotentextZchn"/></w:style><w:style w:type="character" w:styleId="Funotenzeichen"><w:name w:val="footnote reference"/></w:style><w:style w:type="character" w:customStyle="1" w:styleId="MeinZeichen"><w:name w:val="Mein Zeichen"/><w:basedOn w:val="Funotenzeichen"/></w:style><w:style w:type="character" w:styleId="FootnoteAnchor"><w:name w:val="Footnote Anchor"/></w:style></w:styles>"#;
+
+        assert_eq!(
+            reference_styles(Some(input)).unwrap(),
+            ["Funotenzeichen", "FootnoteAnchor", "MeinZeichen"]
+        );
+        assert_eq!(
+            reference_styles(None).unwrap(),
+            ["FootnoteReference", "EndnoteReference"]
+        );
+        assert_eq!(
+            reference_styles(Some("<w:styles/>")).unwrap(),
+            ["FootnoteReference", "EndnoteReference"]
+        );
+    }
+
+    #[test]
+    fn heading_styles() {
+        let input = r#"<w:styles><w:style w:type="paragraph" w:default="1" w:styleId="Normal"><w:name w:val="Normal"/></w:style><w:style w:type="paragraph" w:styleId="Heading1"><w:name w:val="heading 1"/><w:basedOn w:val="Normal"/><w:pPr><w:numPr><w:numId w:val="3"/></w:numPr><w:outlineLvl w:val="0"/></w:pPr></w:style><w:style w:type="paragraph" w:styleId="berschrift2"><w:name w:val="heading 2"/><w:basedOn w:val="Heading1"/><w:pPr><w:numPr><w:ilvl w:val="1"/></w:numPr></w:pPr></w:style><w:style w:type="paragraph" w:customStyle="1" w:styleId="MyHeading"><w:name w:val="My Heading"/><w:basedOn w:val="berschrift2"/><w:pPr><w:outlineLvl w:val="2"/></w:pPr></w:style><w:style w:type="paragraph" w:styleId="Caption"><w:name w:val="caption"/><w:basedOn w:val="Normal"/></w:style><w:style w:type="paragraph" w:customStyle="1" w:styleId="TableCaption"><w:name w:val="Table Caption"/><w:basedOn w:val="Caption"/></w:style></w:styles>"#;
+
+        let styles = paragraph_styles(Some(input)).unwrap();
+        assert_eq!(styles.len(), 5);
+        assert_eq!(
+            styles["Heading1"],
+            ParagraphStyle {
+                outline_level: Some(0),
+                num_id: Some("3".to_string()),
+                ilvl: None,
+                caption: false,
+            }
+        );
+        assert_eq!(
+            styles["berschrift2"],
+            ParagraphStyle {
+                outline_level: Some(1),
+                num_id: Some("3".to_string()),
+                ilvl: Some(1),
+                caption: false,
+            }
+        );
+        assert_eq!(styles["MyHeading"].outline_level, Some(2));
+        assert!(styles["Caption"].caption);
+        assert!(styles["TableCaption"].caption);
+        assert!(!styles["Heading1"].caption);
+        assert!(paragraph_styles(None).unwrap().is_empty());
+    }
+}
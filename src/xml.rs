@@ -0,0 +1,177 @@
+//! This module contains the functionality for reading the xml files.
+//!
+//! The files are read as a stream of events (start tags, end tags, text, and
+//! the like), each of which knows where it is in the input. That way AutoCref
+//! can find what it is looking for from the structure of the xml, however a
+//! program chose to write it, and still change only the parts it needs to.
+//!
+//! Elements and attributes are identified by their local names (*e.g.*, `r`
+//! for `w:r`), so it does not matter which prefix a file uses for the
+//! WordprocessingML namespace.
+
+use crate::diagnostic::{self, Diagnostic};
+use quick_xml::{events::attributes::Attributes, Reader};
+use std::{borrow::Cow, ops::Range};
+
+/// The kinds of xml events that AutoCref cares about.
+///
+/// An empty element (*e.g.*, `<w:tab/>`) is `Empty`. Everything besides
+/// elements and text (declarations, comments, and the like) is `Other`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Start,
+    End,
+    Empty,
+    Text,
+    Other,
+}
+
+/// An xml event.
+///
+/// This has the event's [`Kind`], the local name of the element (empty for
+/// text and other events), and the event's range in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event<'a> {
+    pub kind: Kind,
+    pub name: &'a str,
+    pub range: Range<usize>,
+    /// The contents of a start tag, without the brackets.
+    tag: &'a str,
+    /// The length of the element's qualified name.
+    name_len: usize,
+}
+
+impl<'a> Event<'a> {
+    /// Get the value of an attribute of a start tag by its local name.
+    ///
+    /// The value is returned as written (*i.e.*, still escaped).
+    pub fn attribute(&self, name: &str) -> Option<&'a str> {
+        let tag: &'a str = self.tag;
+        Attributes::new(tag, self.name_len)
+            .filter_map(|a| a.ok())
+            .find(|a| a.key.local_name().as_ref() == name.as_bytes())
+            .and_then(|a| match a.value {
+                Cow::Borrowed(v) => std::str::from_utf8(v).ok(),
+                Cow::Owned(_) => None,
+            })
+    }
+}
+
+/// An iterator over the xml events in an input.
+pub struct Events<'a> {
+    input: &'a str,
+    reader: Reader<&'a [u8]>,
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Result<Event<'a>, Diagnostic>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use quick_xml::events::Event as XmlEvent;
+
+        let start = self.reader.buffer_position() as usize;
+        let event = match self.reader.read_event() {
+            Ok(e) => e,
+            Err(e) => {
+                let err_msg = format!("Error reading xml: {}", e);
+                let offset = self.reader.error_position() as usize;
+                return Some(Err(
+                    Diagnostic::error(diagnostic::Kind::Xml, err_msg).at(offset)
+                ));
+            }
+        };
+        let end = self.reader.buffer_position() as usize;
+
+        let (kind, tag, name_len) = match &event {
+            XmlEvent::Eof => return None,
+            XmlEvent::Start(e) => (
+                Kind::Start,
+                &self.input[start + 1..end - 1],
+                e.name().as_ref().len(),
+            ),
+            XmlEvent::Empty(e) => (
+                Kind::Empty,
+                &self.input[start + 1..end - 2],
+                e.name().as_ref().len(),
+            ),
+            XmlEvent::End(e) => (
+                Kind::End,
+                &self.input[start + 2..end - 1],
+                e.name().as_ref().len(),
+            ),
+            XmlEvent::Text(_) => (Kind::Text, "", 0),
+            _ => (Kind::Other, "", 0),
+        };
+        let qname = &tag[..name_len];
+        let name = match qname.find(':') {
+            Some(i) => &qname[i + 1..],
+            None => qname,
+        };
+
+        Some(Ok(Event {
+            kind,
+            name,
+            range: start..end,
+            tag,
+            name_len,
+        }))
+    }
+}
+
+/// Read the xml events in an input.
+pub fn events(input: &str) -> Events<'_> {
+    Events {
+        input,
+        reader: Reader::from_str(input),
+    }
+}
+
+/// Read the xml events in a piece of an input (*e.g.*, the text between two
+/// tokens), which can end elements that it does not start.
+pub fn fragment_events(input: &str) -> Events<'_> {
+    let mut reader = Reader::from_str(input);
+    reader.config_mut().allow_unmatched_ends = true;
+    Events { input, reader }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_events() {
+        let input = r#"<?xml version="1.0"?><ns0:p><ns0:r ns0:rsidR="00A1"><ns0:footnoteReference ns0:customMarkFollows="1" ns0:id="2" /><ns0:t>a &amp; b</ns0:t></ns0:r></ns0:p>"#;
+
+        let read: Vec<Event> = events(input).collect::<Result<_, _>>().unwrap();
+        let kinds: Vec<Kind> = read.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                Kind::Other,
+                Kind::Start,
+                Kind::Start,
+                Kind::Empty,
+                Kind::Start,
+                Kind::Text,
+                Kind::End,
+                Kind::End,
+                Kind::End,
+            ]
+        );
+        let names: Vec<&str> = read.iter().map(|e| e.name).collect();
+        assert_eq!(
+            names,
+            ["", "p", "r", "footnoteReference", "t", "", "t", "r", "p"]
+        );
+
+        assert_eq!(&input[read[5].range.clone()], "a &amp; b");
+        assert_eq!(&input[read[6].range.clone()], "</ns0:t>");
+        assert_eq!(read[3].attribute("id"), Some("2"));
+        assert_eq!(read[3].attribute("customMarkFollows"), Some("1"));
+        assert_eq!(read[3].attribute("type"), None);
+
+        assert!(events("<w:p></w:r>").any(|e| e.is_err()));
+        assert!(events("</w:t></w:r><w:r>").any(|e| e.is_err()));
+        assert!(fragment_events("</w:t></w:r><w:r>").all(|e| e.is_ok()));
+    }
+}
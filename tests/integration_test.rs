@@ -1,4 +1,43 @@
-use std::path::Path;
+use std::{
+    io::{Cursor, Read, Write},
+    path::Path,
+};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// An italicized "supra" and the space after it, for use in the text of a run.
+///
+/// This ends the run that it is in and starts a new one after it.
+const SUPRA: &str = r#"</w:t></w:r><w:r><w:rPr><w:i /></w:rPr><w:t>supra</w:t></w:r><w:r><w:t xml:space="preserve"> "#;
+
+/// A run with a reference to footnote `id`.
+fn fn_ref(id: u32) -> String {
+    format!(
+        r#"<w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="{}" /></w:r>"#,
+        id
+    )
+}
+
+/// Footnote `id`, with `text` as its only run.
+fn note(id: u32, text: &str) -> String {
+    format!(
+        r#"<w:footnote w:id="{}"><w:p><w:r><w:t xml:space="preserve">{}</w:t></w:r></w:p></w:footnote>"#,
+        id, text
+    )
+}
+
+/// The parts of a document with just its body and footnotes.
+///
+/// Tests that need the other parts set them with struct update syntax.
+fn parts<'a>(document: &'a str, footnotes: &'a str) -> autocref::Parts<'a> {
+    autocref::Parts {
+        document,
+        footnotes: Some(footnotes),
+        endnotes: None,
+        settings: None,
+        styles: None,
+        numbering: None,
+    }
+}
 
 #[test]
 fn test_autocref() {
@@ -13,3 +52,881 @@ fn test_autocref() {
     assert_eq!(doc_output, doc_target);
     assert_eq!(fn_output, fn_target);
 }
+
+/// Build a `.docx` package in memory from a list of entries.
+fn build_docx(entries: &[(&str, &[u8], CompressionMethod)]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents, method) in entries {
+        writer
+            .start_file(*name, FileOptions::default().compression_method(*method))
+            .unwrap();
+        writer.write_all(contents).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn test_autocref_docx() {
+    let doc_input = std::fs::read("./tests/test-docs/doc-orig.xml").unwrap();
+    let doc_target =
+        autocref::fs::load_file(Path::new("./tests/test-docs/doc-target.xml")).unwrap();
+    let fn_input = std::fs::read("./tests/test-docs/fn-orig.xml").unwrap();
+    let fn_target = autocref::fs::load_file(Path::new("./tests/test-docs/fn-target.xml")).unwrap();
+
+    let docx_input = build_docx(&[
+        (
+            "[Content_Types].xml",
+            b"<Types/>",
+            CompressionMethod::Deflated,
+        ),
+        ("word/document.xml", &doc_input, CompressionMethod::Deflated),
+        (
+            "word/media/image1.png",
+            b"not really a png",
+            CompressionMethod::Stored,
+        ),
+        ("word/footnotes.xml", &fn_input, CompressionMethod::Stored),
+    ]);
+
+    let docx_output = autocref::autocref_docx(&docx_input, &autocref::Options::default()).unwrap();
+    let mut archive = ZipArchive::new(Cursor::new(docx_output)).unwrap();
+
+    let expected = [
+        ("[Content_Types].xml", CompressionMethod::Deflated),
+        ("word/document.xml", CompressionMethod::Deflated),
+        ("word/media/image1.png", CompressionMethod::Stored),
+        ("word/footnotes.xml", CompressionMethod::Stored),
+    ];
+    assert_eq!(archive.len(), expected.len());
+    for (i, (name, method)) in expected.iter().enumerate() {
+        let file = archive.by_index(i).unwrap();
+        assert_eq!(file.name(), *name);
+        assert_eq!(file.compression(), *method);
+    }
+
+    let mut doc_output = String::new();
+    archive
+        .by_name("word/document.xml")
+        .unwrap()
+        .read_to_string(&mut doc_output)
+        .unwrap();
+    let mut fn_output = String::new();
+    archive
+        .by_name("word/footnotes.xml")
+        .unwrap()
+        .read_to_string(&mut fn_output)
+        .unwrap();
+
+    assert_eq!(doc_output, doc_target);
+    assert_eq!(fn_output, fn_target);
+}
+
+#[test]
+fn test_autocref_endnotes() {
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:t>One.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r><w:r><w:t>Two.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="EndnoteReference" /></w:rPr><w:endnoteReference w:id="2" /></w:r><w:r><w:t>Three.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="EndnoteReference" /></w:rPr><w:endnoteReference w:id="3" /></w:r></w:p></w:body></w:document>"#;
+    let fn_input = r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">A footnote.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+    let en_input = r#"<w:endnotes><w:endnote w:id="2"><w:p><w:r><w:t xml:space="preserve">An endnote.</w:t></w:r></w:p></w:endnote><w:endnote w:id="3"><w:p><w:r><w:rPr><w:i /></w:rPr><w:t xml:space="preserve">Supra</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">note 1.</w:t></w:r></w:p></w:endnote></w:endnotes>"#;
+
+    let output = autocref::autocref_parts(
+        &autocref::Parts {
+            endnotes: Some(en_input),
+            ..parts(doc_input, fn_input)
+        },
+        &autocref::Options::default(),
+    )
+    .unwrap();
+
+    // Only the first endnote is referred to, so only it gets a bookmark.
+    assert_eq!(
+        output.document,
+        r#"<w:document><w:body><w:p><w:r><w:t>One.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r><w:r><w:t>Two.</w:t></w:r><w:bookmarkStart w:id="1" w:name="_RefEn0000001"/><w:r><w:rPr><w:rStyle w:val="EndnoteReference" /></w:rPr><w:endnoteReference w:id="2" /></w:r><w:bookmarkEnd w:id="1"/><w:r><w:t>Three.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="EndnoteReference" /></w:rPr><w:endnoteReference w:id="3" /></w:r></w:p></w:body></w:document>"#
+    );
+    assert_eq!(output.footnotes.unwrap(), fn_input);
+    // Endnotes are numbered with lowercase Roman numerals by default, which is
+    // what the field will show.
+    assert_eq!(
+        output.endnotes.unwrap(),
+        r#"<w:endnotes><w:endnote w:id="2"><w:p><w:r><w:t xml:space="preserve">An endnote.</w:t></w:r></w:p></w:endnote><w:endnote w:id="3"><w:p><w:r><w:rPr><w:i /></w:rPr><w:t xml:space="preserve">Supra</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">note </w:t></w:r><w:fldSimple w:instr=" NOTEREF _RefEn0000001 "><w:r><w:t>i</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">.</w:t></w:r></w:p></w:endnote></w:endnotes>"#
+    );
+}
+
+#[test]
+fn test_autocref_note_ids() {
+    // The second note is in a text box, which Word writes twice. The notes in
+    // footnotes.xml are out of order.
+    let doc_input = format!(
+        r#"<w:document><w:body><w:p>{}</w:p><w:p><mc:AlternateContent><mc:Choice><w:p>{}</w:p></mc:Choice><mc:Fallback><w:p>{}</w:p></mc:Fallback></mc:AlternateContent></w:p><w:tbl><w:tr><w:tc><w:p>{}</w:p></w:tc></w:tr></w:tbl></w:body></w:document>"#,
+        fn_ref(7),
+        fn_ref(5),
+        fn_ref(5),
+        fn_ref(6)
+    );
+    let fn_input = r#"<w:footnotes><w:footnote w:type="separator" w:id="-1"><w:p><w:r><w:separator /></w:r></w:p></w:footnote><w:footnote w:type="continuationSeparator" w:id="0"><w:p><w:r><w:continuationSeparator /></w:r></w:p></w:footnote><w:footnote w:id="6"><w:p><w:r><w:rPr><w:i /></w:rPr><w:t xml:space="preserve">Supra</w:t></w:r><w:r><w:t xml:space="preserve"> </w:t></w:r><w:r><w:t xml:space="preserve">note 2.</w:t></w:r></w:p></w:footnote><w:footnote w:id="5"><w:p><w:r><w:t xml:space="preserve">Second.</w:t></w:r></w:p></w:footnote><w:footnote w:id="7"><w:p><w:r><w:t xml:space="preserve">First.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+
+    let (doc_output, fn_output) = autocref::autocref(&doc_input, fn_input).unwrap();
+
+    // Only the first copy of the second note gets a bookmark.
+    assert_eq!(
+        doc_output,
+        format!(
+            r#"<w:document><w:body><w:p>{}</w:p><w:p><mc:AlternateContent><mc:Choice><w:p><w:bookmarkStart w:id="1" w:name="_Ref000000002"/>{}<w:bookmarkEnd w:id="1"/></w:p></mc:Choice><mc:Fallback><w:p>{}</w:p></mc:Fallback></mc:AlternateContent></w:p><w:tbl><w:tr><w:tc><w:p>{}</w:p></w:tc></w:tr></w:tbl></w:body></w:document>"#,
+            fn_ref(7),
+            fn_ref(5),
+            fn_ref(5),
+            fn_ref(6)
+        )
+    );
+    assert!(fn_output.contains(
+        r#"<w:fldSimple w:instr=" NOTEREF _Ref000000002 "><w:r><w:t>2</w:t></w:r></w:fldSimple>"#
+    ));
+}
+
+#[test]
+fn test_autocref_numbering_settings() {
+    // Numbering restarts in each section, so both sections have a note 1.
+    let doc_input = format!(
+        r#"<w:document><w:body><w:p>{}{}<w:pPr><w:sectPr><w:pgSz w:w="12240" /></w:sectPr></w:pPr></w:p><w:p>{}{}</w:p><w:sectPr><w:pgSz w:w="12240" /></w:sectPr></w:body></w:document>"#,
+        fn_ref(2),
+        fn_ref(3),
+        fn_ref(4),
+        fn_ref(5)
+    );
+    let fn_input = format!(
+        "<w:footnotes>{}{}{}{}</w:footnotes>",
+        note(2, "First."),
+        note(3, "Second."),
+        note(4, "Third."),
+        note(5, &format!("See {}note 1; {}ch. 1, n. 1.", SUPRA, SUPRA))
+    );
+    let settings_input = r#"<w:settings><w:footnotePr><w:numRestart w:val="eachSect" /></w:footnotePr></w:settings>"#;
+
+    let output = autocref::autocref_parts(
+        &autocref::Parts {
+            settings: Some(settings_input),
+            ..parts(&doc_input, &fn_input)
+        },
+        &autocref::Options::default(),
+    )
+    .unwrap();
+
+    // The unqualified reference is to the note in its own section; the
+    // qualified one is to the note in the first section.
+    let fn_output = output.footnotes.unwrap();
+    assert!(fn_output.contains(
+        r#"note </w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000003 "><w:r><w:t>1</w:t></w:r></w:fldSimple>"#
+    ));
+    assert!(fn_output.contains(
+        r#"n. </w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>1</w:t></w:r></w:fldSimple>"#
+    ));
+    assert!(output
+        .document
+        .contains(r#"<w:bookmarkStart w:id="1" w:name="_Ref000000001"/>"#));
+    assert!(output
+        .document
+        .contains(r#"<w:bookmarkStart w:id="2" w:name="_Ref000000003"/>"#));
+
+    // Numbering can also start somewhere other than 1.
+    let settings_input =
+        r#"<w:settings><w:footnotePr><w:numStart w:val="150" /></w:footnotePr></w:settings>"#;
+    let fn_input = format!(
+        "<w:footnotes>{}{}</w:footnotes>",
+        note(2, "First."),
+        note(3, &format!("{}note 150.", SUPRA))
+    );
+    let doc_input = format!(
+        "<w:document><w:body><w:p>{}{}</w:p></w:body></w:document>",
+        fn_ref(2),
+        fn_ref(3)
+    );
+
+    let output = autocref::autocref_parts(
+        &autocref::Parts {
+            settings: Some(settings_input),
+            ..parts(&doc_input, &fn_input)
+        },
+        &autocref::Options::default(),
+    )
+    .unwrap();
+
+    assert!(output.footnotes.unwrap().contains(
+        r#"<w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>150</w:t></w:r></w:fldSimple>"#
+    ));
+}
+
+#[test]
+fn test_autocref_first_note() {
+    let doc_input = format!(
+        r#"<w:document><w:body><w:p>{}{}</w:p><w:sectPr><w:pgSz w:w="12240" /></w:sectPr></w:body></w:document>"#,
+        fn_ref(2),
+        fn_ref(3)
+    );
+    let fn_input = r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">First.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">See </w:t></w:r><w:r><w:rPr><w:i /></w:rPr><w:t>supra</w:t></w:r><w:r><w:t xml:space="preserve"> note 12.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+
+    let output = autocref::autocref_parts(
+        &parts(&doc_input, fn_input),
+        &autocref::Options {
+            first_note: Some(12),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    // The first note is 12, and Word is told so.
+    assert_eq!(
+        output.document,
+        format!(
+            r#"<w:document><w:body><w:p><w:bookmarkStart w:id="1" w:name="_Ref000000001"/>{}<w:bookmarkEnd w:id="1"/>{}</w:p><w:sectPr><w:footnotePr><w:numStart w:val="12"/></w:footnotePr><w:pgSz w:w="12240" /></w:sectPr></w:body></w:document>"#,
+            fn_ref(2),
+            fn_ref(3)
+        )
+    );
+    assert!(output.footnotes.unwrap().contains(
+        r#"<w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>12</w:t></w:r></w:fldSimple>"#
+    ));
+}
+
+#[test]
+fn test_autocref_custom_marks() {
+    // The first footnote is an author's footnote with a star.
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:t>Title</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:customMarkFollows="1" w:id="2" /><w:t>*</w:t></w:r></w:p><w:p><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="4" /></w:r></w:p></w:body></w:document>"#;
+    let fn_input = r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">Professor.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">First.</w:t></w:r></w:p></w:footnote><w:footnote w:id="4"><w:p><w:r><w:rPr><w:i /></w:rPr><w:t>Supra</w:t></w:r><w:r><w:t xml:space="preserve"> note 1; </w:t></w:r><w:r><w:rPr><w:i /></w:rPr><w:t>supra</w:t></w:r><w:r><w:t xml:space="preserve"> note *.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+
+    let (doc_output, fn_output) = autocref::autocref(doc_input, fn_input).unwrap();
+
+    // The star footnote does not take up a number, so note 1 is the second
+    // footnote. But it can still be referred to by its mark.
+    assert!(doc_output.contains(
+        r#"<w:bookmarkStart w:id="1" w:name="_Ref000000001"/><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:customMarkFollows="1" w:id="2" /><w:t>*</w:t></w:r><w:bookmarkEnd w:id="1"/>"#
+    ));
+    assert!(doc_output.contains(
+        r#"<w:bookmarkStart w:id="2" w:name="_Ref000000002"/><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r><w:bookmarkEnd w:id="2"/>"#
+    ));
+    assert!(fn_output.contains(
+        r#"<w:fldSimple w:instr=" NOTEREF _Ref000000002 "><w:r><w:t>1</w:t></w:r></w:fldSimple>"#
+    ));
+    assert!(fn_output.contains(
+        r#"<w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>*</w:t></w:r></w:fldSimple>"#
+    ));
+}
+
+#[test]
+fn test_autocref_number_formats() {
+    let doc_input = format!(
+        "<w:document><w:body><w:p>{}{}{}{}</w:p></w:body></w:document>",
+        fn_ref(2),
+        fn_ref(3),
+        fn_ref(4),
+        fn_ref(5)
+    );
+    let fn_input = format!(
+        "<w:footnotes>{}{}{}{}</w:footnotes>",
+        note(2, "First."),
+        note(3, "Second."),
+        note(4, &format!("{}note iv.", SUPRA)),
+        note(5, &format!("{}notes i–ii.", SUPRA))
+    );
+    let settings_input =
+        r#"<w:settings><w:footnotePr><w:numFmt w:val="lowerRoman" /></w:footnotePr></w:settings>"#;
+
+    let output = autocref::autocref_parts(
+        &autocref::Parts {
+            settings: Some(settings_input),
+            ..parts(&doc_input, &fn_input)
+        },
+        &autocref::Options::default(),
+    )
+    .unwrap();
+
+    let fn_output = output.footnotes.unwrap();
+    for (ref_id, result) in [
+        ("_Ref000000004", "iv"),
+        ("_Ref000000001", "i"),
+        ("_Ref000000002", "ii"),
+    ] {
+        assert!(fn_output.contains(&format!(
+            r#"<w:fldSimple w:instr=" NOTEREF {} "><w:r><w:t>{}</w:t></w:r></w:fldSimple>"#,
+            ref_id, result
+        )));
+    }
+
+    // With Chicago symbols, "†" is the second note.
+    let fn_input = format!(
+        "<w:footnotes>{}{}{}{}</w:footnotes>",
+        note(2, "First."),
+        note(3, "Second."),
+        note(4, &format!("{}note †.", SUPRA)),
+        note(5, "Fourth.")
+    );
+    let settings_input =
+        r#"<w:settings><w:footnotePr><w:numFmt w:val="chicago" /></w:footnotePr></w:settings>"#;
+
+    let output = autocref::autocref_parts(
+        &autocref::Parts {
+            settings: Some(settings_input),
+            ..parts(&doc_input, &fn_input)
+        },
+        &autocref::Options::default(),
+    )
+    .unwrap();
+
+    assert!(output.footnotes.unwrap().contains(
+        r#"<w:fldSimple w:instr=" NOTEREF _Ref000000002 "><w:r><w:t>†</w:t></w:r></w:fldSimple>"#
+    ));
+}
+
+#[test]
+fn test_autocref_split_runs() {
+    let raw_note = |id: u32, runs: &str| {
+        format!(
+            r#"<w:footnote w:id="{}"><w:p>{}</w:p></w:footnote>"#,
+            id, runs
+        )
+    };
+    let doc_input = format!(
+        "<w:document><w:body><w:p>{}</w:p></w:body></w:document>",
+        (2..13).map(fn_ref).collect::<String>()
+    );
+
+    // Word has split "note 10" into " no", "te 1", and "0, at 3", marking a
+    // spelling error and changing the formatting along the way.
+    let split = r#"<w:r><w:rPr><w:i /></w:rPr><w:t>Supra</w:t></w:r><w:r><w:t xml:space="preserve"> no</w:t></w:r><w:proofErr w:type="spellStart" /><w:r w:rsidR="00A1"><w:rPr><w:sz w:val="20" /></w:rPr><w:t>te 1</w:t></w:r><w:r><w:rPr><w:b /></w:rPr><w:t>0, at 3.</w:t></w:r>"#;
+    let fn_input = format!(
+        "<w:footnotes>{}{}</w:footnotes>",
+        (2..12)
+            .map(|id| raw_note(id, r#"<w:r><w:t>Text.</w:t></w:r>"#))
+            .collect::<String>(),
+        raw_note(12, split)
+    );
+
+    let (_, fn_output) = autocref::autocref(&doc_input, &fn_input).unwrap();
+
+    // The field takes the place of the number. The text around it keeps its
+    // runs' properties, and the space before it is preserved.
+    assert!(fn_output.contains(r#"<w:r w:rsidR="00A1"><w:rPr><w:sz w:val="20" /></w:rPr><w:t xml:space="preserve">te </w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000010 "><w:r><w:rPr><w:sz w:val="20" /></w:rPr><w:t>10</w:t></w:r></w:fldSimple><w:r><w:rPr><w:b /></w:rPr><w:t xml:space="preserve">, at 3.</w:t></w:r>"#));
+}
+
+#[test]
+fn test_autocref_reference_styles() {
+    // A German Word calls the reference style "Funotenzeichen," and the
+    // custom mark has ended up in a run of its own.
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:rPr><w:rStyle w:val="Funotenzeichen"/></w:rPr><w:footnoteReference w:customMarkFollows="1" w:id="2"/></w:r><w:r><w:rPr><w:rStyle w:val="Funotenzeichen"/></w:rPr><w:t>*</w:t></w:r><w:r><w:t>Text.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="Funotenzeichen"/></w:rPr><w:footnoteReference w:id="3"/></w:r></w:p></w:body></w:document>"#;
+    let fn_input = r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t>Author.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">See </w:t></w:r><w:r><w:rPr><w:i /></w:rPr><w:t>supra</w:t></w:r><w:r><w:t xml:space="preserve"> note *.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+    let styles_input = r#"<w:styles><w:style w:type="character" w:styleId="Funotenzeichen"><w:name w:val="footnote reference"/></w:style></w:styles>"#;
+
+    // The bookmark covers the reference and its mark.
+    let expected_doc = r#"<w:document><w:body><w:p><w:bookmarkStart w:id="1" w:name="_Ref000000001"/><w:r><w:rPr><w:rStyle w:val="Funotenzeichen"/></w:rPr><w:footnoteReference w:customMarkFollows="1" w:id="2"/></w:r><w:r><w:rPr><w:rStyle w:val="Funotenzeichen"/></w:rPr><w:t>*</w:t></w:r><w:bookmarkEnd w:id="1"/><w:r><w:t>Text.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="Funotenzeichen"/></w:rPr><w:footnoteReference w:id="3"/></w:r></w:p></w:body></w:document>"#;
+
+    let parts = autocref::Parts {
+        styles: Some(styles_input),
+        ..parts(doc_input, fn_input)
+    };
+    let output = autocref::autocref_parts(&parts, &autocref::Options::default()).unwrap();
+    assert_eq!(output.document, expected_doc);
+    assert!(output.footnotes.unwrap().contains(
+        r#"<w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>*</w:t></w:r></w:fldSimple>"#
+    ));
+
+    // The style can also be provided directly.
+    let options = autocref::Options {
+        reference_styles: vec!["Funotenzeichen".to_string()],
+        ..Default::default()
+    };
+    let output = autocref::autocref_parts(
+        &autocref::Parts {
+            styles: None,
+            numbering: None,
+            ..parts
+        },
+        &options,
+    )
+    .unwrap();
+    assert_eq!(output.document, expected_doc);
+
+    // Without it, the mark is not found, so the cross-reference refers to a
+    // note that does not exist. That is an error, unless it is allowed, in
+    // which case the cross-reference is left alone.
+    let parts = autocref::Parts {
+        styles: None,
+        numbering: None,
+        ..parts
+    };
+    let err = autocref::autocref_parts(&parts, &autocref::Options::default()).unwrap_err();
+    assert_eq!(err.kind, autocref::diagnostic::Kind::Reference);
+    assert_eq!(
+        err.message,
+        "Footnote 1 refers to footnote *, which does not exist"
+    );
+    assert_eq!(err.part.as_deref(), Some("word/footnotes.xml"));
+    assert_eq!(err.offset, Some(fn_input.find("note *").unwrap() + 5));
+    assert_eq!(err.note, Some(1));
+    assert_eq!(err.context.as_deref(), Some("See supra note *."));
+    let options = autocref::Options {
+        allow_dangling: true,
+        ..Default::default()
+    };
+    let output = autocref::autocref_parts(&parts, &options).unwrap();
+    assert_eq!(output.document, doc_input);
+    assert_eq!(output.footnotes.unwrap(), fn_input);
+}
+
+#[test]
+fn test_autocref_lenient() {
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r></w:p></w:body></w:document>"#;
+    let fn_input = r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">First.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">As discussed in note 1.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+    let parts = parts(doc_input, fn_input);
+
+    // Without an italicized supra, the reference is left alone by default.
+    let output = autocref::autocref_parts(&parts, &autocref::Options::default()).unwrap();
+    assert_eq!(output.document, doc_input);
+    assert_eq!(output.footnotes.unwrap(), fn_input);
+
+    // But not in the lenient mode.
+    let options = autocref::Options {
+        lenient: true,
+        ..Default::default()
+    };
+    let output = autocref::autocref_parts(&parts, &options).unwrap();
+    assert!(output.footnotes.unwrap().contains(
+        r#"in note </w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>1</w:t></w:r></w:fldSimple>"#
+    ));
+}
+
+#[test]
+fn test_autocref_citation_style() {
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r></w:p></w:body></w:document>"#;
+    let fn_input = r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">Smith, Torts (OUP 2001).</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">Smith (n 1) 45.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+    let parts = parts(doc_input, fn_input);
+
+    // The Bluebook grammar does not recognize OSCOLA's "(n 1)."
+    let output = autocref::autocref_parts(&parts, &autocref::Options::default()).unwrap();
+    assert_eq!(output.footnotes.unwrap(), fn_input);
+
+    // The OSCOLA grammar does.
+    let options = autocref::Options {
+        citation_style: autocref::grammar::CitationStyle::Oscola,
+        ..Default::default()
+    };
+    let output = autocref::autocref_parts(&parts, &options).unwrap();
+    assert!(output.footnotes.unwrap().contains(
+        r#"Smith (n </w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>1</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">) 45.</w:t></w:r>"#
+    ));
+}
+
+#[test]
+fn test_autocref_config() {
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r></w:p></w:body></w:document>"#;
+    let fn_input = r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">Erste.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">Siehe oben Fn. 1.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+    let config_input = "[[phrase]]\npattern = 'oben Fn\\. {NOTE}'\nsignal = false\n";
+    let parts = parts(doc_input, fn_input);
+
+    let options = autocref::Options {
+        grammar: Some(autocref::config::grammar(config_input, None).unwrap()),
+        ..Default::default()
+    };
+    let output = autocref::autocref_parts(&parts, &options).unwrap();
+    assert!(output.footnotes.unwrap().contains(
+        r#"oben Fn. </w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>1</w:t></w:r></w:fldSimple>"#
+    ));
+}
+
+#[test]
+fn test_autocref_typographic_variants() {
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="4" /></w:r></w:p></w:body></w:document>"#;
+    let fn_input = format!(
+        r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t>First.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t>Second.</w:t></w:r></w:p></w:footnote><w:footnote w:id="4"><w:p><w:r><w:t xml:space="preserve">See {}notes&#160;1</w:t></w:r><w:r><w:noBreakHyphen/></w:r><w:r><w:t>2.</w:t></w:r></w:p></w:footnote></w:footnotes>"#,
+        SUPRA
+    );
+
+    let (_, fn_output) = autocref::autocref(doc_input, &fn_input).unwrap();
+
+    // The no-break space and the non-breaking hyphen are left as they were.
+    assert!(fn_output.contains(r#"notes&#160;</w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000001 "><w:r><w:t>1</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve"></w:t></w:r><w:r><w:noBreakHyphen/></w:r><w:r><w:t xml:space="preserve"></w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000002 "><w:r><w:t>2</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">.</w:t></w:r>"#));
+}
+
+#[test]
+fn test_autocref_body_text() {
+    let doc_input = format!(
+        r#"<w:document><w:body><w:p><w:r><w:t xml:space="preserve">As discussed {}note 2, the rule applies.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r></w:p><w:p><w:r><w:t>More.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r></w:p></w:body></w:document>"#,
+        SUPRA.replace("supra", "infra")
+    );
+    let fn_input = r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t>First.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t>Second.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+
+    let (doc_output, fn_output) = autocref::autocref(&doc_input, fn_input).unwrap();
+
+    // The field in the body text refers to the second note, which is
+    // bookmarked even though nothing in the notes refers to it.
+    assert!(doc_output.contains(r#"<w:t xml:space="preserve"> note </w:t></w:r><w:fldSimple w:instr=" NOTEREF _Ref000000002 "><w:r><w:t>2</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">, the rule applies.</w:t></w:r>"#));
+    assert!(doc_output.contains(r#"<w:bookmarkStart w:id="1" w:name="_Ref000000002"/><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r><w:bookmarkEnd w:id="1"/>"#));
+    assert!(!doc_output.contains("_Ref000000001"));
+    assert_eq!(fn_output, fn_input);
+}
+
+#[test]
+fn test_autocref_heading_refs() {
+    let heading = |level: u32, text: &str| {
+        format!(
+            r#"<w:p><w:pPr><w:pStyle w:val="Heading{}"/></w:pPr><w:r><w:t>{}</w:t></w:r></w:p>"#,
+            level, text
+        )
+    };
+    let doc_input = format!(
+        r#"<w:document><w:body>{}<w:p><w:r><w:t>Text.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r></w:p>{}{}</w:body></w:document>"#,
+        heading(1, "Introduction"),
+        heading(1, "Background"),
+        heading(2, "History")
+    );
+    let fn_input = format!(
+        r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">See {}Part II.A; {}Part I; Part II.</w:t></w:r></w:p></w:footnote></w:footnotes>"#,
+        SUPRA.replace("supra", "infra"),
+        SUPRA
+    );
+    let styles_input = r#"<w:styles><w:style w:type="paragraph" w:styleId="Heading1"><w:name w:val="heading 1"/><w:pPr><w:numPr><w:numId w:val="1"/></w:numPr><w:outlineLvl w:val="0"/></w:pPr></w:style><w:style w:type="paragraph" w:styleId="Heading2"><w:name w:val="heading 2"/><w:pPr><w:numPr><w:ilvl w:val="1"/><w:numId w:val="1"/></w:numPr><w:outlineLvl w:val="1"/></w:pPr></w:style></w:styles>"#;
+    let numbering_input = r#"<w:numbering><w:abstractNum w:abstractNumId="0"><w:lvl w:ilvl="0"><w:start w:val="1"/><w:numFmt w:val="upperRoman"/><w:lvlText w:val="%1."/></w:lvl><w:lvl w:ilvl="1"><w:start w:val="1"/><w:numFmt w:val="upperLetter"/><w:lvlText w:val="%2."/></w:lvl></w:abstractNum><w:num w:numId="1"><w:abstractNumId w:val="0"/></w:num></w:numbering>"#;
+
+    let output = autocref::autocref_parts(
+        &autocref::Parts {
+            styles: Some(styles_input),
+            numbering: Some(numbering_input),
+            ..parts(&doc_input, &fn_input)
+        },
+        &autocref::Options::default(),
+    )
+    .unwrap();
+
+    // The references with a signal become fields showing the numbers in full
+    // context, and the one without a signal is left alone.
+    let fn_output = output.footnotes.unwrap();
+    assert!(fn_output.contains(r#"<w:t xml:space="preserve"> Part </w:t></w:r><w:fldSimple w:instr=" REF _RefPara00003 \w \h "><w:r><w:t>II.A</w:t></w:r></w:fldSimple>"#));
+    assert!(fn_output.contains(r#"<w:fldSimple w:instr=" REF _RefPara00001 \w \h "><w:r><w:t>I</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">; Part II.</w:t></w:r>"#));
+
+    // The headings that are referred to are bookmarked around their contents.
+    assert!(output.document.contains(r#"<w:pStyle w:val="Heading1"/></w:pPr><w:bookmarkStart w:id="1" w:name="_RefPara00001"/><w:r><w:t>Introduction</w:t></w:r><w:bookmarkEnd w:id="1"/></w:p>"#));
+    assert!(output.document.contains(r#"<w:pStyle w:val="Heading2"/></w:pPr><w:bookmarkStart w:id="2" w:name="_RefPara00003"/><w:r><w:t>History</w:t></w:r><w:bookmarkEnd w:id="2"/></w:p>"#));
+    assert!(!output.document.contains("_RefPara00002"));
+}
+
+#[test]
+fn test_autocref_paragraph_refs() {
+    let paragraph = |level: u32, text: &str| {
+        format!(
+            r#"<w:p><w:pPr><w:numPr><w:ilvl w:val="{}"/><w:numId w:val="4"/></w:numPr></w:pPr><w:r><w:t>{}</w:t></w:r></w:p>"#,
+            level, text
+        )
+    };
+    let doc_input = format!(
+        r#"<w:document><w:body>{}{}{}{}{}<w:p><w:r><w:t xml:space="preserve">As provided in Section 3.1(a) above, and see {}¶¶ 1–2.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r></w:p></w:body></w:document>"#,
+        paragraph(0, "Facts."),
+        paragraph(0, "More facts."),
+        paragraph(0, "Obligations."),
+        paragraph(1, "Payment."),
+        paragraph(2, "Timing."),
+        SUPRA
+    );
+    let fn_input = format!(
+        r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">See {}¶ 3.</w:t></w:r></w:p></w:footnote></w:footnotes>"#,
+        SUPRA
+    );
+    let numbering_input = r#"<w:numbering><w:abstractNum w:abstractNumId="7"><w:lvl w:ilvl="0"><w:start w:val="1"/><w:numFmt w:val="decimal"/><w:lvlText w:val="%1."/></w:lvl><w:lvl w:ilvl="1"><w:start w:val="1"/><w:numFmt w:val="decimal"/><w:lvlText w:val="%1.%2"/></w:lvl><w:lvl w:ilvl="2"><w:start w:val="1"/><w:numFmt w:val="lowerLetter"/><w:lvlText w:val="(%3)"/></w:lvl></w:abstractNum><w:num w:numId="4"><w:abstractNumId w:val="7"/></w:num></w:numbering>"#;
+
+    let output = autocref::autocref_parts(
+        &autocref::Parts {
+            numbering: Some(numbering_input),
+            ..parts(&doc_input, &fn_input)
+        },
+        &autocref::Options::default(),
+    )
+    .unwrap();
+
+    // "Section 3.1(a)" is the third level's number in full context, and each
+    // number in a list of paragraphs gets its own field.
+    assert!(output.document.contains(r#"<w:t xml:space="preserve">As provided in Section </w:t></w:r><w:fldSimple w:instr=" REF _RefPara00005 \w \h "><w:r><w:t>3.1(a)</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve"> above, and see </w:t></w:r>"#));
+    assert!(output.document.contains(r#"<w:fldSimple w:instr=" REF _RefPara00001 \w \h "><w:r><w:t>1</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">–</w:t></w:r><w:fldSimple w:instr=" REF _RefPara00002 \w \h "><w:r><w:t>2</w:t></w:r></w:fldSimple>"#));
+    assert!(output.footnotes.unwrap().contains(
+        r#"<w:fldSimple w:instr=" REF _RefPara00003 \w \h "><w:r><w:t>3</w:t></w:r></w:fldSimple>"#
+    ));
+
+    // The paragraphs are bookmarked in order, after the existing bookmarks.
+    assert!(output.document.contains(r#"<w:bookmarkStart w:id="1" w:name="_RefPara00001"/><w:r><w:t>Facts.</w:t></w:r><w:bookmarkEnd w:id="1"/>"#));
+    assert!(output.document.contains(r#"<w:bookmarkStart w:id="4" w:name="_RefPara00005"/><w:r><w:t>Timing.</w:t></w:r><w:bookmarkEnd w:id="4"/>"#));
+    assert!(!output.document.contains("_RefPara00004"));
+}
+
+#[test]
+fn test_autocref_caption_refs() {
+    let caption = |style: &str, text: &str| {
+        format!(
+            r#"<w:p><w:pPr><w:pStyle w:val="{}"/></w:pPr><w:r><w:t xml:space="preserve">{}</w:t></w:r></w:p>"#,
+            style, text
+        )
+    };
+    let doc_input = format!(
+        r#"<w:document><w:body><w:p><w:r><w:t xml:space="preserve">The results, {}Table 2, vary.</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r></w:p>{}{}{}</w:body></w:document>"#,
+        SUPRA.replace("supra", "infra"),
+        caption("TableCaption", "Table 1: Sample"),
+        caption("ImageCaption", "Figure 1: Trend"),
+        caption("TableCaption", "Table 2: Results")
+    );
+    let fn_input = format!(
+        r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">See {}Figure 1; {}Table 3.</w:t></w:r></w:p></w:footnote></w:footnotes>"#,
+        SUPRA.replace("supra", "infra"),
+        SUPRA
+    );
+    let styles_input = r#"<w:styles><w:style w:type="paragraph" w:styleId="Caption"><w:name w:val="caption"/></w:style><w:style w:type="paragraph" w:customStyle="1" w:styleId="TableCaption"><w:name w:val="Table Caption"/><w:basedOn w:val="Caption"/></w:style><w:style w:type="paragraph" w:customStyle="1" w:styleId="ImageCaption"><w:name w:val="Image Caption"/><w:basedOn w:val="Caption"/></w:style></w:styles>"#;
+
+    let output = autocref::autocref_parts(
+        &autocref::Parts {
+            styles: Some(styles_input),
+            ..parts(&doc_input, &fn_input)
+        },
+        &autocref::Options::default(),
+    )
+    .unwrap();
+
+    // Every caption's number becomes a SEQ field, and those that are referred
+    // to are bookmarked around the field.
+    assert!(output.document.contains(r#"<w:t xml:space="preserve">Table </w:t></w:r><w:fldSimple w:instr=" SEQ Table \* ARABIC "><w:r><w:t>1</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">: Sample</w:t></w:r>"#));
+    assert!(output.document.contains(r#"<w:t xml:space="preserve">Figure </w:t></w:r><w:bookmarkStart w:id="1" w:name="_RefFigure00001"/><w:fldSimple w:instr=" SEQ Figure \* ARABIC "><w:r><w:t>1</w:t></w:r></w:fldSimple><w:bookmarkEnd w:id="1"/><w:r><w:t xml:space="preserve">: Trend</w:t></w:r>"#));
+    assert!(output.document.contains(r#"<w:bookmarkStart w:id="2" w:name="_RefTable00002"/><w:fldSimple w:instr=" SEQ Table \* ARABIC "><w:r><w:t>2</w:t></w:r></w:fldSimple><w:bookmarkEnd w:id="2"/>"#));
+    assert!(!output.document.contains("_RefTable00001"));
+
+    // The references become REF fields, and the one to a missing table is
+    // left alone.
+    assert!(output.document.contains(r#"<w:t xml:space="preserve"> Table </w:t></w:r><w:fldSimple w:instr=" REF _RefTable00002 \h "><w:r><w:t>2</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">, vary.</w:t></w:r>"#));
+    let fn_output = output.footnotes.unwrap();
+    assert!(fn_output.contains(r#"<w:fldSimple w:instr=" REF _RefFigure00001 \h "><w:r><w:t>1</w:t></w:r></w:fldSimple><w:r><w:t xml:space="preserve">; </w:t></w:r>"#));
+    assert!(fn_output.contains(r#"<w:t xml:space="preserve"> Table 3.</w:t></w:r>"#));
+}
+
+#[test]
+fn test_autocref_docx_without_notes() {
+    // The document has no notes, but it still refers to its paragraphs and
+    // captions.
+    let doc_input = format!(
+        r#"<w:document><w:body><w:p><w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="4"/></w:numPr></w:pPr><w:r><w:t>Facts.</w:t></w:r></w:p><w:p><w:r><w:t xml:space="preserve">As provided in Section 1 above, see {}Table 1.</w:t></w:r></w:p><w:p><w:pPr><w:pStyle w:val="Caption"/></w:pPr><w:r><w:t xml:space="preserve">Table 1: Results</w:t></w:r></w:p></w:body></w:document>"#,
+        SUPRA.replace("supra", "infra")
+    );
+    let numbering_input = r#"<w:numbering><w:abstractNum w:abstractNumId="7"><w:lvl w:ilvl="0"><w:start w:val="1"/><w:numFmt w:val="decimal"/><w:lvlText w:val="%1."/></w:lvl></w:abstractNum><w:num w:numId="4"><w:abstractNumId w:val="7"/></w:num></w:numbering>"#;
+    let styles_input = r#"<w:styles><w:style w:type="paragraph" w:styleId="Caption"><w:name w:val="caption"/></w:style></w:styles>"#;
+
+    let docx_input = build_docx(&[
+        (
+            "[Content_Types].xml",
+            b"<Types/>",
+            CompressionMethod::Deflated,
+        ),
+        (
+            "word/document.xml",
+            doc_input.as_bytes(),
+            CompressionMethod::Deflated,
+        ),
+        (
+            "word/styles.xml",
+            styles_input.as_bytes(),
+            CompressionMethod::Deflated,
+        ),
+        (
+            "word/numbering.xml",
+            numbering_input.as_bytes(),
+            CompressionMethod::Deflated,
+        ),
+    ]);
+
+    let docx_output = autocref::autocref_docx(&docx_input, &autocref::Options::default()).unwrap();
+    let mut archive = ZipArchive::new(Cursor::new(docx_output)).unwrap();
+    let mut doc_output = String::new();
+    archive
+        .by_name("word/document.xml")
+        .unwrap()
+        .read_to_string(&mut doc_output)
+        .unwrap();
+
+    // The paragraph and the caption are bookmarked in order, and the references
+    // to them become fields.
+    assert!(doc_output.contains(r#"<w:bookmarkStart w:id="1" w:name="_RefPara00001"/><w:r><w:t>Facts.</w:t></w:r><w:bookmarkEnd w:id="1"/>"#));
+    assert!(doc_output.contains(
+        r#"<w:fldSimple w:instr=" REF _RefPara00001 \w \h "><w:r><w:t>1</w:t></w:r></w:fldSimple>"#
+    ));
+    assert!(doc_output.contains(r#"<w:bookmarkStart w:id="2" w:name="_RefTable00001"/><w:fldSimple w:instr=" SEQ Table \* ARABIC "><w:r><w:t>1</w:t></w:r></w:fldSimple><w:bookmarkEnd w:id="2"/>"#));
+    assert!(doc_output.contains(
+        r#"<w:fldSimple w:instr=" REF _RefTable00001 \h "><w:r><w:t>1</w:t></w:r></w:fldSimple>"#
+    ));
+}
+
+#[test]
+fn test_check_parts() {
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r></w:p></w:body></w:document>"#;
+    let fn_input = format!(
+        r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">First.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">See {}note 1, {}note 9, and supra note 1.</w:t></w:r></w:p></w:footnote></w:footnotes>"#,
+        SUPRA, SUPRA
+    );
+    let parts = parts(doc_input, &fn_input);
+
+    // The converted cross-reference is fine, and the other two are reported
+    // where they are.
+    let found = autocref::check_parts(&parts, &autocref::Options::default()).unwrap();
+    let messages: Vec<&str> = found.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "Footnote 2 has \"supra note 1\", which was not converted because \"supra\" is not italic",
+            "Footnote 2 refers to footnote 9, which does not exist",
+        ]
+    );
+    assert_eq!(found[0].severity, autocref::diagnostic::Severity::Warning);
+    assert_eq!(found[0].part.as_deref(), Some("word/footnotes.xml"));
+    assert_eq!(found[0].note, Some(2));
+    assert_eq!(
+        found[0].offset,
+        Some(fn_input.find("supra note 1").unwrap())
+    );
+    assert_eq!(found[1].kind, autocref::diagnostic::Kind::Reference);
+    assert_eq!(found[1].note, Some(2));
+
+    // Nothing is reported in a document without problems.
+    let found = autocref::check_parts(
+        &autocref::Parts {
+            footnotes: Some(&fn_input.replace("note 9, and supra note 1", "note 1")),
+            ..parts
+        },
+        &autocref::Options::default(),
+    )
+    .unwrap();
+    assert!(found.is_empty(), "{:?}", found);
+}
+
+#[test]
+fn test_autocref_directions() {
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="4" /></w:r></w:p></w:body></w:document>"#;
+    let infra = SUPRA.replace(">supra<", ">infra<");
+    let fn_input = format!(
+        r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">See {SUPRA}note 1.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">See {SUPRA}note 3; {infra}note 3.</w:t></w:r></w:p></w:footnote><w:footnote w:id="4"><w:p><w:r><w:t xml:space="preserve">See {infra}note 1; {SUPRA}note 2; note 1 below.</w:t></w:r></w:p></w:footnote></w:footnotes>"#
+    );
+    let parts = parts(doc_input, &fn_input);
+
+    // Each cross-reference that points the wrong way, or to its own note, is
+    // reported where it is.
+    let found = autocref::check_parts(&parts, &autocref::Options::default()).unwrap();
+    let messages: Vec<&str> = found.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "Footnote 1 refers to itself",
+            "Footnote 2 refers to footnote 3 as supra, but that note comes after it",
+            "Footnote 3 refers to footnote 1 as infra, but that note comes before it",
+            "Footnote 3 refers to footnote 1 as infra, but that note comes before it",
+        ]
+    );
+    assert!(found
+        .iter()
+        .all(|d| d.severity == autocref::diagnostic::Severity::Warning));
+    assert_eq!(found[1].note, Some(2));
+    assert_eq!(found[1].part.as_deref(), Some("word/footnotes.xml"));
+    assert_eq!(found[1].offset, Some(fn_input.find("3; ").unwrap()));
+    assert_eq!(
+        found[3].context.as_deref(),
+        Some("…e infra note 1; supra note 2; note 1 below.")
+    );
+
+    // They are converted all the same.
+    let output = autocref::autocref_parts(&parts, &autocref::Options::default()).unwrap();
+    assert_eq!(output.footnotes.unwrap().matches("NOTEREF").count(), 6);
+}
+
+#[test]
+fn test_autocref_fix_signals() {
+    let doc_input = r#"<w:document><w:body><w:p><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="2" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="3" /></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="4" /></w:r></w:p></w:body></w:document>"#;
+    let infra = SUPRA.replace(">supra<", ">infra<");
+    let notes = |second: &str, third: &str| {
+        format!(
+            r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">See {SUPRA}note 1.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">See {second}</w:t></w:r></w:p></w:footnote><w:footnote w:id="4"><w:p><w:r><w:t xml:space="preserve">See {third}</w:t></w:r></w:p></w:footnote></w:footnotes>"#
+        )
+    };
+    let fn_input = notes(
+        &format!("{SUPRA}note 3; {SUPRA}notes 1, 3."),
+        &format!("{infra}note 1; note 2 below."),
+    );
+    let fixed_input = notes(
+        &format!("{infra}note 3; {SUPRA}notes 1, 3."),
+        &format!("{SUPRA}note 1; note 2 above."),
+    );
+    let with_notes = |fn_input| parts(doc_input, fn_input);
+
+    // The signals that point the wrong way are changed in place, keeping
+    // their runs' italics. A signal that is right for one of its
+    // cross-references (and one in a note that cites itself) is left alone.
+    let options = autocref::Options {
+        fix_signals: true,
+        ..Default::default()
+    };
+    let output = autocref::autocref_parts(&with_notes(&fn_input), &options).unwrap();
+    let expected =
+        autocref::autocref_parts(&with_notes(&fixed_input), &autocref::Options::default()).unwrap();
+    assert_eq!(output.footnotes, expected.footnotes);
+    assert!(output
+        .footnotes
+        .unwrap()
+        .contains(r#"<w:r><w:rPr><w:i /></w:rPr><w:t>infra</w:t></w:r>"#));
+
+    let found =
+        autocref::check_parts(&with_notes(&fixed_input), &autocref::Options::default()).unwrap();
+    let messages: Vec<&str> = found.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "Footnote 1 refers to itself",
+            "Footnote 2 refers to footnote 3 as supra, but that note comes after it",
+        ]
+    );
+}
+
+#[test]
+fn test_autocref_body_directions() {
+    let note_ref = |id: u32| {
+        format!(
+            r#"</w:t></w:r><w:r><w:rPr><w:rStyle w:val="FootnoteReference" /></w:rPr><w:footnoteReference w:id="{id}" /></w:r><w:r><w:t xml:space="preserve">"#
+        )
+    };
+    let infra = SUPRA.replace(">supra<", ">infra<");
+    let document = |first: &str, second: &str| {
+        format!(
+            r#"<w:document><w:body><w:p><w:r><w:t xml:space="preserve">First.{} As discussed {first}note 1, and see {second}note 2.{} Then {SUPRA}note 1.</w:t></w:r></w:p></w:body></w:document>"#,
+            note_ref(2),
+            note_ref(3)
+        )
+    };
+    let doc_input = document(&infra, SUPRA);
+    let fixed_input = document(SUPRA, &infra);
+    let fn_input = r#"<w:footnotes><w:footnote w:id="2"><w:p><w:r><w:t xml:space="preserve">One.</w:t></w:r></w:p></w:footnote><w:footnote w:id="3"><w:p><w:r><w:t xml:space="preserve">Two.</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+    let with_document = |document| parts(document, fn_input);
+
+    // In the body text, a cross-reference points the way of the note's
+    // reference.
+    let found =
+        autocref::check_parts(&with_document(&doc_input), &autocref::Options::default()).unwrap();
+    let messages: Vec<&str> = found.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "The body text refers to footnote 1 as infra, but that note comes before it",
+            "The body text refers to footnote 2 as supra, but that note comes after it",
+        ]
+    );
+    assert_eq!(found[0].part.as_deref(), Some("word/document.xml"));
+    assert_eq!(found[0].note, None);
+    assert!(
+        autocref::check_parts(&with_document(&fixed_input), &autocref::Options::default())
+            .unwrap()
+            .is_empty()
+    );
+
+    // The signals are changed in place.
+    let options = autocref::Options {
+        fix_signals: true,
+        ..Default::default()
+    };
+    let output = autocref::autocref_parts(&with_document(&doc_input), &options).unwrap();
+    let expected =
+        autocref::autocref_parts(&with_document(&fixed_input), &autocref::Options::default())
+            .unwrap();
+    assert_eq!(output.document, expected.document);
+}